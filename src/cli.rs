@@ -0,0 +1,151 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::commands;
+use crate::commands::certs::RevocationReason;
+
+// Non-interactive entry points. Running `defe` without a subcommand opens the
+// interactive menu instead.
+#[derive(Parser)]
+#[command(name = "defe", version, about = "Decentralized frontend tooling")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Certificate management commands
+    Certs {
+        #[command(subcommand)]
+        command: CertsCommand,
+    },
+    /// TLS server commands
+    Tls {
+        #[command(subcommand)]
+        command: TlsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CertsCommand {
+    /// Encrypt the certificate key to the MPC key set for threshold recovery
+    Backup {
+        /// MPC share file [default: key_shares.json]
+        #[arg(long)]
+        shares: Option<PathBuf>,
+        /// Where to write the backup [default: privkey.backup.json]
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Produce this participant's decryption share of a key backup
+    DecryptionShare {
+        /// Key backup to decrypt
+        backup: PathBuf,
+        /// This participant's index in the share file
+        #[arg(long)]
+        index: usize,
+        /// MPC share file [default: key_shares.json]
+        #[arg(long)]
+        shares: Option<PathBuf>,
+    },
+    /// Restore the certificate key from a backup with enough decryption shares
+    Restore {
+        /// Key backup to restore
+        backup: PathBuf,
+        /// MPC share file [default: key_shares.json]
+        #[arg(long)]
+        shares: Option<PathBuf>,
+        /// Replace an existing key store
+        #[arg(long)]
+        force: bool,
+    },
+    /// Copy a certificate certbot obtained from its live directory into the workspace
+    Import {
+        /// Domain the certificate was obtained for
+        domain: String,
+        /// Certbot's configuration directory [default: acme.certbot_dir]
+        #[arg(long)]
+        certbot_dir: Option<PathBuf>,
+    },
+    /// Describe the certificate chain and check it against the key and trusted roots
+    Inspect {
+        /// Certificate chain [default: fullchain.pem]
+        #[arg(long)]
+        cert: Option<PathBuf>,
+        /// Private key or key store [default: the workspace key]
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// PEM roots to trust in addition to the Mozilla root program
+        #[arg(long)]
+        roots: Option<PathBuf>,
+    },
+    /// Renew the certificate in the current directory if it is due
+    Renew {
+        /// Keep running and renew whenever the certificate is due
+        #[arg(long)]
+        daemon: bool,
+    },
+    /// Revoke a certificate with the CA and take it out of service
+    Revoke {
+        /// Certificate chain [default: fullchain.pem]
+        #[arg(long)]
+        cert: Option<PathBuf>,
+        /// Sign the request with the certificate key instead of the ACME account key
+        #[arg(long)]
+        use_certificate_key: bool,
+        /// Certificate key or key store; implies --use-certificate-key [default: the workspace key]
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// RFC 5280 revocation reason
+        #[arg(long, value_enum, default_value = "unspecified")]
+        reason: RevocationReason,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TlsCommand {
+    /// Print the effective TLS protocol, cipher and session policy
+    Policy,
+}
+
+pub fn run(command: Command) -> io::Result<()> {
+    match command {
+        Command::Certs { command } => match command {
+            CertsCommand::Backup { shares, output } => commands::certs::backup(shares, output),
+            CertsCommand::DecryptionShare {
+                backup,
+                index,
+                shares,
+            } => commands::certs::decryption_share(backup, shares, index),
+            CertsCommand::Restore {
+                backup,
+                shares,
+                force,
+            } => commands::certs::restore(backup, shares, force),
+            CertsCommand::Import {
+                domain,
+                certbot_dir,
+            } => commands::certs::import(domain, certbot_dir),
+            CertsCommand::Inspect { cert, key, roots } => {
+                commands::certs::inspect(cert, key, roots)
+            }
+            CertsCommand::Renew { daemon } => commands::certs::renew(daemon),
+            CertsCommand::Revoke {
+                cert,
+                use_certificate_key,
+                key,
+                reason,
+                yes,
+            } => commands::certs::revoke(cert, key, use_certificate_key, reason, yes),
+        },
+        Command::Tls { command } => match command {
+            TlsCommand::Policy => commands::tls::print_policy(),
+        },
+    }
+}
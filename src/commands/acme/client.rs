@@ -0,0 +1,519 @@
+use std::fs;
+use std::path::Path;
+
+use reqwest::header::{HeaderMap, CONTENT_TYPE, LOCATION};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::{sleep, Duration, Instant};
+
+use super::jws::{base64url, AccountKey};
+use super::AcmeError;
+
+// Failed requests rejected only for a stale nonce are retried this many times
+const BAD_NONCE_RETRIES: usize = 3;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Directory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+    pub revoke_cert: String,
+    #[serde(default)]
+    pub meta: DirectoryMeta,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryMeta {
+    // Issuer domain names the CA recognizes in CAA records
+    #[serde(default)]
+    pub caa_identities: Vec<String>,
+    // New accounts must be bound to an account registered with the CA
+    #[serde(default)]
+    pub external_account_required: bool,
+}
+
+// Certificates in `root_bundle` are trusted for the directory's TLS endpoint
+// in addition to the system roots, e.g. Pebble's test CA
+pub fn http_client(root_bundle: Option<&Path>) -> Result<reqwest::Client, AcmeError> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("defe/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30));
+    if let Some(path) = root_bundle {
+        let pem = fs::read(path)?;
+        for der in rustls_pemfile::certs(&mut pem.as_slice()) {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_der(&der?)?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+pub async fn fetch_directory(
+    http: &reqwest::Client,
+    directory_url: &str,
+) -> Result<Directory, AcmeError> {
+    Ok(http
+        .get(directory_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Directory>()
+        .await?)
+}
+
+// RFC 7807 problem document returned by the CA
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Problem {
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    #[serde(default)]
+    pub detail: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub status: Status,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    #[serde(default)]
+    pub certificate: Option<String>,
+    #[serde(default)]
+    pub error: Option<Problem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Authorization {
+    pub status: Status,
+    // For a wildcard name the identifier is the base domain
+    pub identifier: Identifier,
+    pub challenges: Vec<Challenge>,
+    #[serde(default)]
+    pub wildcard: bool,
+}
+
+impl Authorization {
+    // The name as ordered
+    pub fn domain(&self) -> String {
+        if self.wildcard {
+            format!("*.{}", self.identifier.value)
+        } else {
+            self.identifier.value.clone()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub error: Option<Problem>,
+}
+
+// An ACME v2 (RFC 8555) client bound to one account key
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: AccountKey,
+    // Account URL, known once registered
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    // Fetches the directory
+    pub async fn connect(
+        directory_url: &str,
+        root_bundle: Option<&Path>,
+        key: AccountKey,
+    ) -> Result<Self, AcmeError> {
+        let http = http_client(root_bundle)?;
+        let directory = fetch_directory(&http, directory_url).await?;
+        Ok(Self {
+            http,
+            directory,
+            key,
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    pub fn directory(&self) -> &Directory {
+        &self.directory
+    }
+
+    pub fn key(&self) -> &AccountKey {
+        &self.key
+    }
+
+    async fn nonce(&mut self) -> Result<String, AcmeError> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        replay_nonce(response.headers())
+            .ok_or_else(|| AcmeError::Protocol("CA returned no Replay-Nonce".to_string()))
+    }
+
+    // Sends a signed request and returns the successful response
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<reqwest::Response, AcmeError> {
+        let mut attempt = 0;
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.key.sign(url, &nonce, self.kid.as_deref(), payload)?;
+            let response = self
+                .http
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(serde_json::to_vec(&body)?)
+                .send()
+                .await?;
+            self.nonce = replay_nonce(response.headers());
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status().as_u16();
+            let problem = response.json::<Problem>().await.unwrap_or_default();
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && attempt < BAD_NONCE_RETRIES
+            {
+                attempt += 1;
+                continue;
+            }
+            return Err(AcmeError::Problem { status, problem });
+        }
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<(T, Option<String>), AcmeError> {
+        let response = self.post(url, payload).await?;
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok((response.json::<T>().await?, location))
+    }
+
+    // Registers the account, or looks up the existing one for this key.
+    // `eab` is the key ID and MAC key for External Account Binding.
+    pub async fn register(
+        &mut self,
+        email: Option<&str>,
+        eab: Option<(&str, &[u8])>,
+    ) -> Result<String, AcmeError> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        if let Some((key_id, hmac_key)) = eab {
+            payload["externalAccountBinding"] =
+                self.key.external_account_binding(key_id, hmac_key, &url)?;
+        }
+        let (_, location) = self.post_json::<Value>(&url, Some(&payload)).await?;
+        let kid = location
+            .ok_or_else(|| AcmeError::Protocol("CA returned no account URL".to_string()))?;
+        self.kid = Some(kid.clone());
+        Ok(kid)
+    }
+
+    // Looks up the account for this key without creating one
+    pub async fn find_account(&mut self) -> Result<String, AcmeError> {
+        let url = self.directory.new_account.clone();
+        let payload = json!({ "onlyReturnExisting": true });
+        let (_, location) = self.post_json::<Value>(&url, Some(&payload)).await?;
+        let kid = location
+            .ok_or_else(|| AcmeError::Protocol("CA returned no account URL".to_string()))?;
+        self.kid = Some(kid.clone());
+        Ok(kid)
+    }
+
+    // Places an order for `domains`. Returns the order and its URL.
+    pub async fn new_order(&mut self, domains: &[String]) -> Result<(Order, String), AcmeError> {
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+        let url = self.directory.new_order.clone();
+        let (order, location) = self
+            .post_json::<Order>(&url, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let location =
+            location.ok_or_else(|| AcmeError::Protocol("CA returned no order URL".to_string()))?;
+        Ok((order, location))
+    }
+
+    pub async fn authorization(&mut self, url: &str) -> Result<Authorization, AcmeError> {
+        Ok(self.post_json(url, None).await?.0)
+    }
+
+    pub async fn order(&mut self, url: &str) -> Result<Order, AcmeError> {
+        Ok(self.post_json(url, None).await?.0)
+    }
+
+    // Tells the CA the challenge response is in place
+    pub async fn respond(&mut self, challenge_url: &str) -> Result<(), AcmeError> {
+        self.post(challenge_url, Some(&json!({}))).await?;
+        Ok(())
+    }
+
+    // Polls the authorization until the CA has decided on it
+    pub async fn wait_for_authorization(
+        &mut self,
+        url: &str,
+        timeout: Duration,
+    ) -> Result<Authorization, AcmeError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let authorization = self.authorization(url).await?;
+            match authorization.status {
+                Status::Pending | Status::Processing => {}
+                Status::Valid => return Ok(authorization),
+                _ => {
+                    let problem = authorization
+                        .challenges
+                        .iter()
+                        .find_map(|challenge| challenge.error.clone())
+                        .unwrap_or_default();
+                    return Err(AcmeError::Validation {
+                        domain: authorization.domain(),
+                        problem,
+                    });
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(AcmeError::Timeout(format!(
+                    "authorization for {}",
+                    authorization.domain()
+                )));
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    // Waits for the order to become ready, submits the CSR and waits for the
+    // certificate to be issued. Returns the PEM certificate chain.
+    pub async fn finalize(
+        &mut self,
+        order: &Order,
+        order_url: &str,
+        csr_der: &[u8],
+        timeout: Duration,
+    ) -> Result<String, AcmeError> {
+        let deadline = Instant::now() + timeout;
+        // The CA may not have moved the order on from its last authorization
+        let mut current = self.order(order_url).await?;
+        while current.status == Status::Pending {
+            if Instant::now() >= deadline {
+                return Err(AcmeError::Timeout("order to become ready".to_string()));
+            }
+            sleep(POLL_INTERVAL).await;
+            current = self.order(order_url).await?;
+        }
+        let mut order = if current.status == Status::Ready {
+            let payload = json!({ "csr": base64url(csr_der) });
+            self.post_json::<Order>(&order.finalize, Some(&payload))
+                .await?
+                .0
+        } else {
+            current
+        };
+
+        while order.status == Status::Processing {
+            if Instant::now() >= deadline {
+                return Err(AcmeError::Timeout("certificate issuance".to_string()));
+            }
+            sleep(POLL_INTERVAL).await;
+            order = self.order(order_url).await?;
+        }
+
+        let certificate_url = match (order.status, order.certificate) {
+            (Status::Valid, Some(url)) => url,
+            _ => {
+                return Err(AcmeError::Protocol(format!(
+                    "Order ended as {:?}: {}",
+                    order.status,
+                    order
+                        .error
+                        .map(|problem| problem.detail)
+                        .unwrap_or_default()
+                )))
+            }
+        };
+        Ok(self.post(&certificate_url, None).await?.text().await?)
+    }
+
+    // Revokes the DER certificate with an RFC 5280 reason code. Signed by the
+    // account if one was found, otherwise by the key itself, which must then
+    // be the certificate's.
+    pub async fn revoke(&mut self, certificate_der: &[u8], reason: u8) -> Result<(), AcmeError> {
+        let mut payload = json!({ "certificate": base64url(certificate_der) });
+        // Unspecified is expressed by leaving the reason out
+        if reason != 0 {
+            payload["reason"] = json!(reason);
+        }
+        let url = self.directory.revoke_cert.clone();
+        self.post(&url, Some(&payload)).await?;
+        Ok(())
+    }
+}
+
+fn replay_nonce(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("replay-nonce")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_rfc8555_objects() {
+        // RFC 8555 section 7.1.1, 7.1.3 and 7.1.4 examples
+        let directory: Directory = serde_json::from_str(
+            r#"{
+                "newNonce": "https://example.com/acme/new-nonce",
+                "newAccount": "https://example.com/acme/new-account",
+                "newOrder": "https://example.com/acme/new-order",
+                "newAuthz": "https://example.com/acme/new-authz",
+                "revokeCert": "https://example.com/acme/revoke-cert",
+                "keyChange": "https://example.com/acme/key-change",
+                "meta": {
+                    "termsOfService": "https://example.com/acme/terms/2017-5-30",
+                    "website": "https://www.example.com/",
+                    "caaIdentities": ["example.com"],
+                    "externalAccountRequired": false
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            directory.revoke_cert,
+            "https://example.com/acme/revoke-cert"
+        );
+        assert_eq!(directory.meta.caa_identities, ["example.com"]);
+        assert!(!directory.meta.external_account_required);
+
+        let order: Order = serde_json::from_str(
+            r#"{
+                "status": "valid",
+                "expires": "2016-01-20T14:09:07.99Z",
+                "identifiers": [
+                    { "type": "dns", "value": "www.example.org" },
+                    { "type": "dns", "value": "example.org" }
+                ],
+                "notBefore": "2016-01-01T00:00:00Z",
+                "notAfter": "2016-01-08T00:00:00Z",
+                "authorizations": [
+                    "https://example.com/acme/authz/PAniVnsZcis",
+                    "https://example.com/acme/authz/r4HqLzrSrpI"
+                ],
+                "finalize": "https://example.com/acme/order/TOlocE8rfgo/finalize",
+                "certificate": "https://example.com/acme/cert/mAt3xBGaobw"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(order.status, Status::Valid);
+        assert_eq!(order.authorizations.len(), 2);
+        assert_eq!(
+            order.certificate.as_deref(),
+            Some("https://example.com/acme/cert/mAt3xBGaobw")
+        );
+        assert!(order.error.is_none());
+
+        let authorization: Authorization = serde_json::from_str(
+            r#"{
+                "status": "valid",
+                "expires": "2015-03-01T14:09:07.99Z",
+                "identifier": { "type": "dns", "value": "www.example.org" },
+                "challenges": [
+                    {
+                        "url": "https://example.com/acme/chall/prV_B7yEyA4",
+                        "type": "http-01",
+                        "status": "valid",
+                        "token": "DGyRejmCefe7v4NfDGDKfA",
+                        "validated": "2014-12-01T12:05:58.16Z"
+                    }
+                ],
+                "wildcard": false
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(authorization.domain(), "www.example.org");
+        assert_eq!(authorization.challenges[0].kind, "http-01");
+        assert_eq!(
+            authorization.challenges[0].token.as_deref(),
+            Some("DGyRejmCefe7v4NfDGDKfA")
+        );
+    }
+
+    #[test]
+    fn names_wildcard_authorizations_as_ordered() {
+        let authorization: Authorization = serde_json::from_str(
+            r#"{
+                "status": "invalid",
+                "identifier": { "type": "dns", "value": "example.org" },
+                "challenges": [{
+                    "type": "dns-01",
+                    "url": "https://example.com/acme/chall/1",
+                    "error": {
+                        "type": "urn:ietf:params:acme:error:dns",
+                        "detail": "No TXT record found"
+                    }
+                }],
+                "wildcard": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(authorization.status, Status::Invalid);
+        assert_eq!(authorization.domain(), "*.example.org");
+        let problem = authorization.challenges[0].error.as_ref().unwrap();
+        assert_eq!(problem.kind, "urn:ietf:params:acme:error:dns");
+    }
+
+    #[test]
+    fn reads_replay_nonces() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(replay_nonce(&headers), None);
+        headers.insert("Replay-Nonce", "oFvnlFP1wIhRlYS2jTaXbA".parse().unwrap());
+        assert_eq!(
+            replay_nonce(&headers).as_deref(),
+            Some("oFvnlFP1wIhRlYS2jTaXbA")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::env;
+
+use async_trait::async_trait;
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::time::Duration;
+
+use super::DnsProvider;
+use crate::commands::acme::AcmeError;
+
+// API token with the Zone / DNS / Edit permission
+pub const TOKEN_ENV: &str = "CLOUDFLARE_API_TOKEN";
+
+const API: &str = "https://api.cloudflare.com/client/v4";
+
+// Every API response is wrapped in this envelope
+#[derive(Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    id: String,
+}
+
+pub struct Cloudflare {
+    http: reqwest::Client,
+    token: String,
+    zone_id: Option<String>,
+    ttl: u32,
+}
+
+impl Cloudflare {
+    pub fn new(zone_id: Option<String>, ttl: u32) -> Result<Self, AcmeError> {
+        let token =
+            env::var(TOKEN_ENV).map_err(|_| AcmeError::Dns(format!("{} is not set", TOKEN_ENV)))?;
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("defe/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            http,
+            token,
+            zone_id,
+            ttl,
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, AcmeError> {
+        let envelope = request
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .json::<Envelope<T>>()
+            .await?;
+        match envelope.result {
+            Some(result) if envelope.success => Ok(result),
+            _ => Err(AcmeError::Dns(format!(
+                "Cloudflare API error: {}",
+                envelope
+                    .errors
+                    .iter()
+                    .map(|error| format!("{} ({})", error.message, error.code))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    // The configured zone, or else the longest parent domain of `name` that
+    // is a zone on the account
+    async fn zone_id(&self, name: &str) -> Result<String, AcmeError> {
+        if let Some(zone_id) = &self.zone_id {
+            return Ok(zone_id.clone());
+        }
+        let labels: Vec<&str> = name.trim_end_matches('.').split('.').collect();
+        for start in 1..labels.len().saturating_sub(1) {
+            let candidate = labels[start..].join(".");
+            let zones: Vec<Item> = self
+                .call(
+                    self.http
+                        .get(format!("{}/zones", API))
+                        .query(&[("name", candidate.as_str())]),
+                )
+                .await?;
+            if let Some(zone) = zones.into_iter().next() {
+                return Ok(zone.id);
+            }
+        }
+        Err(AcmeError::Dns(format!(
+            "No Cloudflare zone on this account contains {}",
+            name
+        )))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        let zone_id = self.zone_id(name).await?;
+        let body = json!({
+            "type": "TXT",
+            "name": name,
+            "content": value,
+            "ttl": self.ttl,
+        });
+        self.call::<Item>(
+            self.http
+                .post(format!("{}/zones/{}/dns_records", API, zone_id))
+                .json(&body),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        let zone_id = self.zone_id(name).await?;
+        let records: Vec<Item> = self
+            .call(
+                self.http
+                    .get(format!("{}/zones/{}/dns_records", API, zone_id))
+                    .query(&[("type", "TXT"), ("name", name), ("content", value)]),
+            )
+            .await?;
+        for record in records {
+            self.call::<Item>(self.http.delete(format!(
+                "{}/zones/{}/dns_records/{}",
+                API, zone_id, record.id
+            )))
+            .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::DnsProvider;
+use crate::commands::acme::{write_public, AcmeError};
+
+// Keeps TXT records in a JSON object mapping each name to its values, for
+// tests where a local DNS server is fed from the file
+pub struct FileProvider {
+    path: PathBuf,
+    // Serializes the read-modify-write of the file
+    lock: Mutex<()>,
+}
+
+impl FileProvider {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn edit(&self, change: impl FnOnce(&mut BTreeMap<String, Vec<String>>)) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut records: BTreeMap<String, Vec<String>> = match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        change(&mut records);
+        write_public(&self.path, &serde_json::to_vec_pretty(&records)?)
+    }
+}
+
+#[async_trait]
+impl DnsProvider for FileProvider {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.edit(|records| {
+            records
+                .entry(name.to_string())
+                .or_default()
+                .push(value.to_string())
+        })?;
+        Ok(())
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.edit(|records| {
+            if let Some(values) = records.get_mut(name) {
+                values.retain(|existing| existing != value);
+                if values.is_empty() {
+                    records.remove(name);
+                }
+            }
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_records_side_by_side() {
+        let path = std::env::temp_dir().join(format!("defe-dns01-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let provider = FileProvider::new(path.to_str().unwrap());
+        let name = "_acme-challenge.example.com";
+
+        // A domain and its wildcard share the record name
+        provider.create_txt_record(name, "one").await.unwrap();
+        provider.create_txt_record(name, "two").await.unwrap();
+        let records: BTreeMap<String, Vec<String>> =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(records[name], vec!["one", "two"]);
+
+        provider.delete_txt_record(name, "one").await.unwrap();
+        provider.delete_txt_record(name, "missing").await.unwrap();
+        let records: BTreeMap<String, Vec<String>> =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(records[name], vec!["two"]);
+
+        provider.delete_txt_record(name, "two").await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), "{}");
+        fs::remove_file(&path).unwrap();
+    }
+}
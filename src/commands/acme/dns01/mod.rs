@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::config::{DnsProviderSettings, DnsSettings};
+
+use super::jws::base64url;
+use super::AcmeError;
+
+mod cloudflare;
+mod file;
+mod rfc2136;
+pub mod wire;
+
+use cloudflare::Cloudflare;
+use file::FileProvider;
+use rfc2136::Rfc2136;
+
+// Somewhere DNS-01 challenge records can be published. Records are added
+// alongside any others at the same name, since an order for a domain and its
+// wildcard has two challenges there.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError>;
+    // Removes only the record with `value`
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError>;
+}
+
+pub fn provider(settings: &DnsSettings) -> Result<Box<dyn DnsProvider>, AcmeError> {
+    Ok(match &settings.provider {
+        DnsProviderSettings::Cloudflare { zone_id } => {
+            Box::new(Cloudflare::new(zone_id.clone(), settings.ttl)?)
+        }
+        DnsProviderSettings::Rfc2136 {
+            server,
+            zone,
+            key_name,
+        } => Box::new(Rfc2136::new(server, zone, key_name, settings.ttl)?),
+        DnsProviderSettings::File { path } => Box::new(FileProvider::new(path)),
+    })
+}
+
+// Where the challenge for `domain` is published. Wildcard authorizations name
+// the base domain, so "*.example.com" shares the record name of "example.com".
+pub fn record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
+
+pub fn record_value(key_authorization: &str) -> String {
+    base64url(&Sha256::digest(key_authorization.as_bytes()))
+}
+
+// Polls every configured resolver until each returns every record, so the CA
+// isn't asked to validate before it can see them
+pub async fn wait_for_propagation(
+    settings: &DnsSettings,
+    records: &[(String, String)],
+) -> Result<(), AcmeError> {
+    let mut pending: Vec<(&str, &(String, String))> = settings
+        .resolvers
+        .iter()
+        .flat_map(|resolver| {
+            records
+                .iter()
+                .map(move |record| (resolver.as_str(), record))
+        })
+        .collect();
+    let deadline = Instant::now() + Duration::from_secs(settings.propagation_timeout_secs);
+
+    loop {
+        let mut missing = Vec::new();
+        for (resolver, record) in pending {
+            let (name, value) = record;
+            match wire::lookup_txt(resolver, name).await {
+                Ok(values) if values.contains(value) => {}
+                Ok(_) => missing.push((resolver, record)),
+                Err(e) => {
+                    eprintln!("Error asking {} for {}: {}", resolver, name, e);
+                    missing.push((resolver, record));
+                }
+            }
+        }
+        pending = missing;
+
+        let Some((resolver, (name, _))) = pending.first() else {
+            return Ok(());
+        };
+        if Instant::now() >= deadline {
+            return Err(AcmeError::Timeout(format!(
+                "{} to be visible at {}",
+                name, resolver
+            )));
+        }
+        println!(
+            "Waiting for {} challenge record(s) to propagate...",
+            pending.len()
+        );
+        sleep(Duration::from_secs(settings.propagation_interval_secs)).await;
+    }
+}
@@ -0,0 +1,235 @@
+use std::env;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::wire::{self, CLASS_ANY, CLASS_IN, CLASS_NONE, TYPE_SOA, TYPE_TSIG, TYPE_TXT};
+use super::DnsProvider;
+use crate::commands::acme::AcmeError;
+
+// Base64 TSIG secret, as in the `secret` of a BIND key statement
+pub const SECRET_ENV: &str = "DEFE_TSIG_SECRET";
+
+const ALGORITHM: &str = "hmac-sha256";
+// Clock difference tolerated between us and the server
+const FUDGE_SECS: u16 = 300;
+const OPCODE_UPDATE: u16 = 5 << 11;
+
+// Publishes records with RFC 2136 dynamic updates sent to the zone's primary
+pub struct Rfc2136 {
+    server: SocketAddr,
+    zone: String,
+    key_name: String,
+    secret: Vec<u8>,
+    ttl: u32,
+}
+
+impl Rfc2136 {
+    pub fn new(server: &str, zone: &str, key_name: &str, ttl: u32) -> Result<Self, AcmeError> {
+        let secret = env::var(SECRET_ENV).map_err(|_| {
+            AcmeError::Dns(format!(
+                "{} must hold the TSIG secret for {}",
+                SECRET_ENV, key_name
+            ))
+        })?;
+        let secret = general_purpose::STANDARD
+            .decode(secret.trim())
+            .map_err(|e| AcmeError::Dns(format!("Invalid {}: {}", SECRET_ENV, e)))?;
+        Ok(Self {
+            server: wire::server_address(server)?,
+            zone: zone.to_string(),
+            key_name: key_name.to_string(),
+            secret,
+            ttl,
+        })
+    }
+
+    // Class IN adds the record; class NONE with TTL 0 deletes exactly that
+    // record and leaves any others at the name (RFC 2136 section 2.5)
+    fn update_message(
+        &self,
+        id: u16,
+        name: &str,
+        value: &str,
+        class: u16,
+        ttl: u32,
+    ) -> Result<Vec<u8>, AcmeError> {
+        let mut message = Vec::new();
+        wire::put_header(&mut message, id, OPCODE_UPDATE, [1, 0, 1, 0]);
+        wire::put_name(&mut message, &self.zone)?;
+        wire::put_u16(&mut message, TYPE_SOA);
+        wire::put_u16(&mut message, CLASS_IN);
+        wire::put_record(
+            &mut message,
+            name,
+            TYPE_TXT,
+            class,
+            ttl,
+            &wire::txt_rdata(value),
+        )?;
+        Ok(message)
+    }
+
+    async fn update(&self, name: &str, value: &str, class: u16, ttl: u32) -> Result<(), AcmeError> {
+        let id = wire::random_id();
+        let mut message = self.update_message(id, name, value, class, ttl)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        self.sign(&mut message, id, now)?;
+
+        // TCP, so a lost datagram can't leave it unclear whether the update
+        // was applied
+        let response = wire::exchange_tcp(self.server, &message).await?;
+        let flags = wire::check_response(&response, id)?;
+        if flags & 0x000f != 0 {
+            return Err(AcmeError::Dns(format!(
+                "{} refused the update for {}: {}",
+                self.server,
+                name,
+                wire::rcode_name(flags)
+            )));
+        }
+        Ok(())
+    }
+
+    // Appends a TSIG record (RFC 8945) covering the finished message, signed
+    // at `now` seconds since the epoch
+    fn sign(&self, message: &mut Vec<u8>, id: u16, now: u64) -> Result<(), AcmeError> {
+        // 48-bit seconds since the epoch
+        let time_signed = &now.to_be_bytes()[2..];
+
+        let mut variables = Vec::new();
+        wire::put_name(&mut variables, &self.key_name)?;
+        wire::put_u16(&mut variables, CLASS_ANY);
+        wire::put_u32(&mut variables, 0);
+        wire::put_name(&mut variables, ALGORITHM)?;
+        variables.extend_from_slice(time_signed);
+        wire::put_u16(&mut variables, FUDGE_SECS);
+        // Error, and the length of the empty other data
+        wire::put_u16(&mut variables, 0);
+        wire::put_u16(&mut variables, 0);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .map_err(|e| AcmeError::Dns(format!("Invalid TSIG secret: {}", e)))?;
+        mac.update(message);
+        mac.update(&variables);
+        let mac = mac.finalize().into_bytes();
+
+        let mut rdata = Vec::new();
+        wire::put_name(&mut rdata, ALGORITHM)?;
+        rdata.extend_from_slice(time_signed);
+        wire::put_u16(&mut rdata, FUDGE_SECS);
+        wire::put_u16(&mut rdata, mac.len() as u16);
+        rdata.extend_from_slice(&mac);
+        wire::put_u16(&mut rdata, id);
+        wire::put_u16(&mut rdata, 0);
+        wire::put_u16(&mut rdata, 0);
+        wire::put_record(message, &self.key_name, TYPE_TSIG, CLASS_ANY, 0, &rdata)?;
+
+        let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+        message[10..12].copy_from_slice(&additional.to_be_bytes());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.update(name, value, CLASS_IN, self.ttl).await
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeError> {
+        self.update(name, value, CLASS_NONE, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> Rfc2136 {
+        Rfc2136 {
+            server: "192.0.2.53:53".parse().unwrap(),
+            zone: "example.com".to_string(),
+            key_name: "acme-key".to_string(),
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+            ttl: 60,
+        }
+    }
+
+    #[test]
+    fn encodes_updates() {
+        let message = provider()
+            .update_message(0x0102, "_acme-challenge.example.com", "token", CLASS_IN, 60)
+            .unwrap();
+        let mut expected = vec![
+            0x01, 0x02, 0x28, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        ];
+        // Zone section: example.com SOA IN
+        expected.extend(b"\x07example\x03com\x00\x00\x06\x00\x01");
+        // Update section: the TXT record, class IN, TTL 60
+        expected.extend(b"\x0f_acme-challenge\x07example\x03com\x00");
+        expected.extend([
+            0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x06, 0x05,
+        ]);
+        expected.extend(b"token");
+        assert_eq!(message, expected);
+
+        // Deleting names the record with class NONE and TTL 0
+        let delete = provider()
+            .update_message(
+                0x0102,
+                "_acme-challenge.example.com",
+                "token",
+                CLASS_NONE,
+                0,
+            )
+            .unwrap();
+        let record = &delete[expected.len() - 16..expected.len() - 8];
+        assert_eq!(record, [0x00, 0x10, 0x00, 0xfe, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    // The TSIG record laid out field by field as in RFC 8945 section 4.2, and
+    // the MAC input as in section 4.3.3
+    #[test]
+    fn signs_with_tsig() {
+        let provider = provider();
+        let unsigned = provider
+            .update_message(0x0102, "_acme-challenge.example.com", "token", CLASS_IN, 60)
+            .unwrap();
+        let mut signed = unsigned.clone();
+        // 2024-01-01T00:00:00Z
+        provider.sign(&mut signed, 0x0102, 1_704_067_200).unwrap();
+
+        let time_signed = [0x00, 0x00, 0x65, 0x92, 0x00, 0x80];
+        let mut variables = b"\x08acme-key\x00".to_vec();
+        variables.extend([0x00, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        variables.extend(b"\x0bhmac-sha256\x00");
+        variables.extend(time_signed);
+        variables.extend([0x01, 0x2c, 0x00, 0x00, 0x00, 0x00]);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&provider.secret).unwrap();
+        mac.update(&unsigned);
+        mac.update(&variables);
+        let mac = mac.finalize().into_bytes();
+
+        let mut record = b"\x08acme-key\x00".to_vec();
+        record.extend([0x00, 0xfa, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3d]);
+        record.extend(b"\x0bhmac-sha256\x00");
+        record.extend(time_signed);
+        record.extend([0x01, 0x2c, 0x00, 0x20]);
+        record.extend(mac);
+        record.extend([0x01, 0x02, 0x00, 0x00, 0x00, 0x00]);
+
+        // ARCOUNT goes up by one and the record is appended
+        assert_eq!(&signed[10..12], [0x00, 0x01]);
+        assert_eq!(&signed[..10], &unsigned[..10]);
+        assert_eq!(&signed[12..unsigned.len()], &unsigned[12..]);
+        assert_eq!(&signed[unsigned.len()..], record.as_slice());
+    }
+}
@@ -0,0 +1,398 @@
+use std::io;
+use std::net::SocketAddr;
+
+use rand::{thread_rng, Rng};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration};
+
+use crate::commands::invalid_data;
+
+// Just enough of the DNS wire format (RFC 1035) for lookups and RFC 2136
+// updates
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_CAA: u16 = 257;
+pub const TYPE_TSIG: u16 = 250;
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+const HEADER_LENGTH: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn random_id() -> u16 {
+    thread_rng().gen()
+}
+
+// Appends `name` in uncompressed wire form, lowercased so the result is also
+// the canonical form TSIG signs over
+pub fn put_name(out: &mut Vec<u8>, name: &str) -> io::Result<()> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid DNS name {}", name),
+            ));
+        }
+        out.push(label.len() as u8);
+        out.extend(label.bytes().map(|byte| byte.to_ascii_lowercase()));
+    }
+    out.push(0);
+    Ok(())
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_header(out: &mut Vec<u8>, id: u16, flags: u16, counts: [u16; 4]) {
+    put_u16(out, id);
+    put_u16(out, flags);
+    for count in counts {
+        put_u16(out, count);
+    }
+}
+
+// A resource record; `rdata` is written with its length prefix
+pub fn put_record(
+    out: &mut Vec<u8>,
+    name: &str,
+    kind: u16,
+    class: u16,
+    ttl: u32,
+    rdata: &[u8],
+) -> io::Result<()> {
+    put_name(out, name)?;
+    put_u16(out, kind);
+    put_u16(out, class);
+    put_u32(out, ttl);
+    put_u16(out, rdata.len() as u16);
+    out.extend_from_slice(rdata);
+    Ok(())
+}
+
+// TXT RDATA holding `value` split into character strings of at most 255 bytes
+pub fn txt_rdata(value: &str) -> Vec<u8> {
+    let mut rdata = Vec::with_capacity(value.len() + 1);
+    for chunk in value.as_bytes().chunks(255) {
+        rdata.push(chunk.len() as u8);
+        rdata.extend_from_slice(chunk);
+    }
+    rdata
+}
+
+fn read_u16(message: &[u8], at: usize) -> io::Result<u16> {
+    message
+        .get(at..at + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| invalid_data("Truncated DNS message"))
+}
+
+// Returns the offset just past the (possibly compressed) name at `at`
+fn skip_name(message: &[u8], mut at: usize) -> io::Result<usize> {
+    loop {
+        let length = *message
+            .get(at)
+            .ok_or_else(|| invalid_data("Truncated DNS name"))?;
+        match length {
+            0 => return Ok(at + 1),
+            // A compression pointer ends the name
+            length if length & 0xc0 == 0xc0 => return Ok(at + 2),
+            length => at += 1 + length as usize,
+        }
+    }
+}
+
+// Checks that `response` answers request `id`. Returns the header flags.
+pub fn check_response(response: &[u8], id: u16) -> io::Result<u16> {
+    if response.len() < HEADER_LENGTH || read_u16(response, 0)? != id {
+        return Err(invalid_data("DNS response does not match the request"));
+    }
+    let flags = read_u16(response, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(invalid_data(
+            "DNS server sent a request instead of a response",
+        ));
+    }
+    Ok(flags)
+}
+
+pub fn rcode_name(flags: u16) -> &'static str {
+    match flags & 0x000f {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown error",
+    }
+}
+
+// The answer section as (type, RDATA) pairs. Records of other types, such
+// as the CNAMEs leading to the answer, are included.
+fn answers(response: &[u8]) -> io::Result<Vec<(u16, Vec<u8>)>> {
+    let questions = read_u16(response, 4)?;
+    let count = read_u16(response, 6)?;
+    let mut at = HEADER_LENGTH;
+    for _ in 0..questions {
+        at = skip_name(response, at)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..count {
+        at = skip_name(response, at)?;
+        let kind = read_u16(response, at)?;
+        let length = read_u16(response, at + 8)? as usize;
+        at += 10;
+        let rdata = response
+            .get(at..at + length)
+            .ok_or_else(|| invalid_data("Truncated DNS record"))?;
+        at += length;
+        records.push((kind, rdata.to_vec()));
+    }
+    Ok(records)
+}
+
+// A TXT value with its character strings joined
+fn txt_value(rdata: &[u8]) -> io::Result<String> {
+    let mut value = Vec::new();
+    let mut strings = rdata;
+    while let Some((&length, rest)) = strings.split_first() {
+        let chunk = rest
+            .get(..length as usize)
+            .ok_or_else(|| invalid_data("Truncated TXT record"))?;
+        value.extend_from_slice(chunk);
+        strings = &rest[length as usize..];
+    }
+    Ok(String::from_utf8_lossy(&value).into_owned())
+}
+
+// "192.0.2.53" and "[2001:db8::53]" default to port 53
+pub fn server_address(server: &str) -> io::Result<SocketAddr> {
+    server
+        .parse::<SocketAddr>()
+        .or_else(|_| format!("{}:53", server).parse())
+        .or_else(|_| format!("[{}]:53", server).parse())
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid DNS server address {}", server),
+            )
+        })
+}
+
+pub async fn exchange_udp(server: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = if server.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    socket.send(request).await?;
+    let mut response = vec![0u8; 4096];
+    let length = timeout(EXCHANGE_TIMEOUT, socket.recv(&mut response))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))??;
+    response.truncate(length);
+    Ok(response)
+}
+
+// Messages over TCP carry a two-byte length prefix
+pub async fn exchange_tcp(server: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut framed = Vec::with_capacity(request.len() + 2);
+        put_u16(&mut framed, request.len() as u16);
+        framed.extend_from_slice(request);
+        stream.write_all(&framed).await?;
+
+        let length = stream.read_u16().await? as usize;
+        let mut response = vec![0u8; length];
+        stream.read_exact(&mut response).await?;
+        Ok::<_, io::Error>(response)
+    };
+    timeout(EXCHANGE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS exchange timed out"))?
+}
+
+// A recursive query for the records of type `kind` at `name`
+fn query(id: u16, name: &str, kind: u16) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    put_header(&mut request, id, FLAG_RECURSION_DESIRED, [1, 0, 0, 0]);
+    put_name(&mut request, name)?;
+    put_u16(&mut request, kind);
+    put_u16(&mut request, CLASS_IN);
+    Ok(request)
+}
+
+// Asks `resolver` for the records of type `kind` at `name`, retrying over TCP
+// when the UDP answer is truncated. Returns their RDATA; a name that does not
+// exist has no records.
+pub async fn lookup(resolver: &str, name: &str, kind: u16) -> io::Result<Vec<Vec<u8>>> {
+    let server = server_address(resolver)?;
+    let id = random_id();
+    let request = query(id, name, kind)?;
+
+    let mut response = exchange_udp(server, &request).await?;
+    if check_response(&response, id)? & FLAG_TRUNCATED != 0 {
+        response = exchange_tcp(server, &request).await?;
+    }
+    let flags = check_response(&response, id)?;
+    match flags & 0x000f {
+        0 => Ok(answers(&response)?
+            .into_iter()
+            .filter(|(found, _)| *found == kind)
+            .map(|(_, rdata)| rdata)
+            .collect()),
+        3 => Ok(Vec::new()),
+        _ => Err(io::Error::other(format!(
+            "{} answered {}",
+            resolver,
+            rcode_name(flags)
+        ))),
+    }
+}
+
+pub async fn lookup_txt(resolver: &str, name: &str) -> io::Result<Vec<String>> {
+    lookup(resolver, name, TYPE_TXT)
+        .await?
+        .iter()
+        .map(|rdata| txt_value(rdata))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A response to `request` whose answers are a CNAME from the queried name
+    // to "acme.example.net" and a TXT record there, both using compression
+    // pointers as resolvers do
+    fn compressed_response(request: &[u8]) -> Vec<u8> {
+        let mut response = request[..2].to_vec();
+        response.extend([0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00]);
+        response.extend(&request[HEADER_LENGTH..]);
+        // CNAME at the question name (offset 12)
+        response.extend([0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c]);
+        let target_at = response.len() + 2;
+        response.extend([0x00, 0x12]);
+        response.extend(b"\x04acme\x07example\x03net\x00");
+        // TXT at the CNAME target, split into two character strings
+        response.extend([
+            0xc0,
+            target_at as u8,
+            0x00,
+            0x10,
+            0x00,
+            0x01,
+            0x00,
+            0x00,
+            0x00,
+            0x3c,
+        ]);
+        response.extend([0x00, 0x0a, 0x04]);
+        response.extend(b"abcd");
+        response.push(0x04);
+        response.extend(b"efgh");
+        response
+    }
+
+    #[test]
+    fn encodes_queries() {
+        let request = query(0x1234, "Example.COM.", TYPE_A).unwrap();
+        let mut expected = vec![
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        expected.extend(b"\x07example\x03com\x00");
+        expected.extend([0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(request, expected);
+
+        assert!(query(1, "a..example.com", TYPE_A).is_err());
+        assert!(query(1, &format!("{}.com", "a".repeat(64)), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn reads_compressed_answers() {
+        let request = query(0xbeef, "_acme-challenge.example.com", TYPE_TXT).unwrap();
+        let response = compressed_response(&request);
+        assert_eq!(check_response(&response, 0xbeef).unwrap() & 0x000f, 0);
+
+        let records = answers(&response).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, 5);
+        assert_eq!(records[1].0, TYPE_TXT);
+        assert_eq!(txt_value(&records[1].1).unwrap(), "abcdefgh");
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let request = query(7, "example.com", TYPE_TXT).unwrap();
+        let response = compressed_response(&request);
+        for length in HEADER_LENGTH..response.len() {
+            assert!(answers(&response[..length]).is_err(), "length {}", length);
+        }
+        assert!(check_response(&response, 8).is_err());
+        // The request itself is not a response
+        assert!(check_response(&request, 7).is_err());
+        assert!(txt_value(&[0x05, b'a']).is_err());
+    }
+
+    #[test]
+    fn splits_long_txt_values() {
+        let value = "x".repeat(300);
+        let rdata = txt_rdata(&value);
+        assert_eq!(rdata[0], 255);
+        assert_eq!(rdata[256], 45);
+        assert_eq!(txt_value(&rdata).unwrap(), value);
+    }
+
+    #[test]
+    fn parses_server_addresses() {
+        assert_eq!(
+            server_address("192.0.2.53").unwrap().to_string(),
+            "192.0.2.53:53"
+        );
+        assert_eq!(server_address("192.0.2.53:5353").unwrap().port(), 5353);
+        assert_eq!(
+            server_address("2001:db8::53").unwrap().to_string(),
+            "[2001:db8::53]:53"
+        );
+        assert!(server_address("ns.example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn looks_up_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut request = vec![0u8; 512];
+            let (length, client) = server.recv_from(&mut request).await.unwrap();
+            let response = compressed_response(&request[..length]);
+            server.send_to(&response, client).await.unwrap();
+        });
+
+        let values = lookup_txt(&address.to_string(), "_acme-challenge.example.com")
+            .await
+            .unwrap();
+        assert_eq!(values, vec!["abcdefgh"]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+// Longest request line read; challenge targets are far shorter
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+
+// Answers HTTP-01 validation requests on plain HTTP while an order is being
+// validated. Tokens are added as challenges come in; the listener is closed
+// when the responder is dropped.
+pub struct Http01Responder {
+    tokens: Arc<Mutex<HashMap<String, String>>>,
+    task: JoinHandle<()>,
+}
+
+impl Http01Responder {
+    pub async fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to listen for HTTP-01 challenges on {}: {}",
+                    address, e
+                ),
+            )
+        })?;
+        println!("Answering HTTP-01 challenges on {}", listener.local_addr()?);
+
+        let tokens = Arc::new(Mutex::new(HashMap::new()));
+        let served = Arc::clone(&tokens);
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Errors such as running out of file descriptors
+                        // persist; wait instead of spinning on them
+                        eprintln!("Error accepting HTTP-01 connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let tokens = Arc::clone(&served);
+                tokio::spawn(async move {
+                    if let Err(e) = answer(stream, &tokens).await {
+                        eprintln!("Error answering HTTP-01 request from {}: {}", peer, e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { tokens, task })
+    }
+
+    pub fn add(&self, token: &str, key_authorization: String) {
+        self.tokens
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(token.to_string(), key_authorization);
+    }
+}
+
+impl Drop for Http01Responder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn answer(
+    stream: tokio::net::TcpStream,
+    tokens: &Mutex<HashMap<String, String>>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    let mut limited = (&mut reader).take(MAX_REQUEST_LINE);
+    timeout(
+        Duration::from_secs(10),
+        limited.read_line(&mut request_line),
+    )
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let key_authorization = target
+        .strip_prefix(CHALLENGE_PREFIX)
+        .filter(|_| method == "GET" || method == "HEAD")
+        .and_then(|token| {
+            tokens
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(token)
+                .cloned()
+        });
+
+    let (status, body) = match key_authorization {
+        Some(key_authorization) => ("200 OK", key_authorization),
+        None => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        if method == "HEAD" { "" } else { body.as_str() }
+    );
+    reader.get_mut().write_all(response.as_bytes()).await?;
+    reader.get_mut().shutdown().await
+}
@@ -0,0 +1,413 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::sign::SigningKey;
+use rustls::SignatureScheme;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, SubjectPublicKeyInfo};
+use x509_parser::public_key::PublicKey;
+
+use super::{write_private, AcmeError};
+
+pub fn base64url(data: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(data)
+}
+
+// Key used for JSON Web Signatures: normally the ACME account key (ECDSA
+// P-256, ES256), or a certificate's own key when revoking with it
+pub struct AccountKey {
+    signing_key: Arc<dyn SigningKey>,
+    scheme: SignatureScheme,
+    alg: &'static str,
+    // Public JWK members, in the lexicographic order RFC 7638 thumbprints use
+    members: Vec<(&'static str, String)>,
+    // Coordinate width of an ECDSA key, whose signatures need converting
+    ecdsa_width: Option<usize>,
+}
+
+impl AccountKey {
+    fn from_key_pair(key_pair: &KeyPair) -> Result<Self, AcmeError> {
+        let unusable =
+            |e: &dyn std::fmt::Display| AcmeError::Protocol(format!("Unusable key: {}", e));
+        let der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+        let signing_key =
+            rustls::crypto::aws_lc_rs::sign::any_supported_type(&der).map_err(|e| unusable(&e))?;
+        let algorithm = key_pair.algorithm();
+
+        let ec = |crv: &str, width: usize| -> Result<Vec<(&'static str, String)>, AcmeError> {
+            let point = key_pair.public_key_raw();
+            if point.len() != 1 + 2 * width || point[0] != 0x04 {
+                return Err(unusable(&"unexpected public key encoding"));
+            }
+            Ok(vec![
+                ("crv", crv.to_string()),
+                ("kty", "EC".to_string()),
+                ("x", base64url(&point[1..1 + width])),
+                ("y", base64url(&point[1 + width..])),
+            ])
+        };
+        let (scheme, alg, members, ecdsa_width) = if algorithm == &PKCS_ECDSA_P256_SHA256 {
+            (
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                "ES256",
+                ec("P-256", 32)?,
+                Some(32),
+            )
+        } else if algorithm == &PKCS_ECDSA_P384_SHA384 {
+            (
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                "ES384",
+                ec("P-384", 48)?,
+                Some(48),
+            )
+        } else if algorithm == &PKCS_ED25519 {
+            let members = vec![
+                ("crv", "Ed25519".to_string()),
+                ("kty", "OKP".to_string()),
+                ("x", base64url(key_pair.public_key_raw())),
+            ];
+            (SignatureScheme::ED25519, "EdDSA", members, None)
+        } else {
+            let spki = key_pair.public_key_der();
+            let (_, spki) = SubjectPublicKeyInfo::from_der(&spki).map_err(|e| unusable(&e))?;
+            let Ok(PublicKey::RSA(rsa)) = spki.parsed() else {
+                return Err(unusable(&"unsupported key type"));
+            };
+            // JWK integers have no leading zeros
+            let unsigned = |integer: &[u8]| {
+                let start = integer.iter().take_while(|byte| **byte == 0).count();
+                base64url(&integer[start..])
+            };
+            let members = vec![
+                ("e", unsigned(rsa.exponent)),
+                ("kty", "RSA".to_string()),
+                ("n", unsigned(rsa.modulus)),
+            ];
+            (SignatureScheme::RSA_PKCS1_SHA256, "RS256", members, None)
+        };
+        Ok(Self {
+            signing_key,
+            scheme,
+            alg,
+            members,
+            ecdsa_width,
+        })
+    }
+
+    // Loads the account key from `path`, creating it on first use. Returns
+    // whether it was created.
+    pub fn load_or_create(path: &Path) -> Result<(Self, bool), AcmeError> {
+        if path.exists() {
+            return Ok((Self::load(path)?, false));
+        }
+
+        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)
+            .map_err(|e| AcmeError::Protocol(e.to_string()))?;
+        write_private(path, key_pair.serialize_pem().as_bytes())?;
+        Ok((Self::from_key_pair(&key_pair)?, true))
+    }
+
+    // Loads an existing account key
+    pub fn load(path: &Path) -> Result<Self, AcmeError> {
+        let pem = fs::read_to_string(path)?;
+        let key_pair = KeyPair::from_pem(&pem)
+            .map_err(|e| AcmeError::Protocol(format!("Invalid account key: {}", e)))?;
+        Self::from_key_pair(&key_pair)
+    }
+
+    // A certificate key, which can sign its own revocation request
+    pub fn from_certificate_key(pkcs8_der: &[u8]) -> Result<Self, AcmeError> {
+        let key_pair = KeyPair::try_from(pkcs8_der)
+            .map_err(|e| AcmeError::Protocol(format!("Invalid certificate key: {}", e)))?;
+        Self::from_key_pair(&key_pair)
+    }
+
+    pub fn jwk(&self) -> Value {
+        let mut jwk = serde_json::Map::new();
+        for (name, value) in &self.members {
+            jwk.insert(name.to_string(), json!(value));
+        }
+        Value::Object(jwk)
+    }
+
+    // RFC 7638 thumbprint: the required members in lexicographic order,
+    // without whitespace
+    pub fn thumbprint(&self) -> String {
+        let members: Vec<String> = self
+            .members
+            .iter()
+            .map(|(name, value)| format!(r#""{}":"{}""#, name, value))
+            .collect();
+        let canonical = format!("{{{}}}", members.join(","));
+        base64url(&Sha256::digest(canonical.as_bytes()))
+    }
+
+    // RFC 8555 External Account Binding: the account's public key, MACed with
+    // the key the CA issued for `key_id`, vouching that they belong together
+    pub fn external_account_binding(
+        &self,
+        key_id: &str,
+        hmac_key: &[u8],
+        url: &str,
+    ) -> Result<Value, AcmeError> {
+        let protected = json!({
+            "alg": "HS256",
+            "kid": key_id,
+            "url": url,
+        });
+        let protected = base64url(serde_json::to_string(&protected)?.as_bytes());
+        let payload = base64url(serde_json::to_string(&self.jwk())?.as_bytes());
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(hmac_key)
+            .map_err(|e| AcmeError::Protocol(format!("Invalid EAB MAC key: {}", e)))?;
+        mac.update(format!("{}.{}", protected, payload).as_bytes());
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": base64url(&mac.finalize().into_bytes()),
+        }))
+    }
+
+    // What the HTTP-01 challenge response body must contain for `token`
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    // A flattened JWS over `payload`. `kid` names the account once it is
+    // registered; before that the public key is embedded instead. A `None`
+    // payload makes a POST-as-GET request.
+    pub fn sign(
+        &self,
+        url: &str,
+        nonce: &str,
+        kid: Option<&str>,
+        payload: Option<&Value>,
+    ) -> Result<Value, AcmeError> {
+        let mut protected = json!({
+            "alg": self.alg,
+            "nonce": nonce,
+            "url": url,
+        });
+        match kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = base64url(serde_json::to_string(&protected)?.as_bytes());
+        let payload = match payload {
+            Some(payload) => base64url(serde_json::to_string(payload)?.as_bytes()),
+            None => String::new(),
+        };
+
+        let signer = self
+            .signing_key
+            .choose_scheme(&[self.scheme])
+            .ok_or_else(|| AcmeError::Protocol(format!("Key cannot sign {}", self.alg)))?;
+        let signature = signer
+            .sign(format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|e| AcmeError::Protocol(format!("Failed to sign request: {}", e)))?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": match self.ecdsa_width {
+                Some(width) => base64url(&der_to_raw_ecdsa(&signature, width)?),
+                None => base64url(&signature),
+            },
+        }))
+    }
+}
+
+// JWS wants ECDSA signatures as fixed-width r || s rather than the DER
+// SEQUENCE of two INTEGERs the signer produces
+fn der_to_raw_ecdsa(der: &[u8], width: usize) -> Result<Vec<u8>, AcmeError> {
+    let malformed = || AcmeError::Protocol("Malformed ECDSA signature".to_string());
+    let read = |data: &[u8], tag: u8| -> Option<(Vec<u8>, usize)> {
+        if data.len() < 2 || data[0] != tag || data[1] >= 0x80 {
+            return None;
+        }
+        let length = data[1] as usize;
+        data.get(2..2 + length)
+            .map(|content| (content.to_vec(), 2 + length))
+    };
+
+    let (sequence, _) = read(der, 0x30).ok_or_else(malformed)?;
+    let (r, used) = read(&sequence, 0x02).ok_or_else(malformed)?;
+    let (s, _) = read(&sequence[used..], 0x02).ok_or_else(malformed)?;
+
+    let mut raw = Vec::with_capacity(width * 2);
+    for integer in [r, s] {
+        let start = integer.iter().take_while(|byte| **byte == 0).count();
+        let digits = &integer[start..];
+        if digits.len() > width {
+            return Err(malformed());
+        }
+        raw.extend(std::iter::repeat_n(0, width - digits.len()));
+        raw.extend_from_slice(digits);
+    }
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lc_rs::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, ED25519};
+
+    // RFC 8037 appendix A.1: the Ed25519 key as PKCS#8
+    fn rfc8037_key() -> AccountKey {
+        let seed = general_purpose::URL_SAFE_NO_PAD
+            .decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A")
+            .unwrap();
+        let mut pkcs8 = hex::decode("302e020100300506032b657004220420").unwrap();
+        pkcs8.extend_from_slice(&seed);
+        AccountKey::from_certificate_key(&pkcs8).unwrap()
+    }
+
+    fn decode(text: &str) -> Vec<u8> {
+        general_purpose::URL_SAFE_NO_PAD.decode(text).unwrap()
+    }
+
+    fn verify(
+        jws: &Value,
+        algorithm: &'static dyn aws_lc_rs::signature::VerificationAlgorithm,
+        public_key: &[u8],
+    ) {
+        let signed = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        UnparsedPublicKey::new(algorithm, public_key)
+            .verify(
+                signed.as_bytes(),
+                &decode(jws["signature"].as_str().unwrap()),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn thumbprint_matches_rfc8037() {
+        let key = rfc8037_key();
+        assert_eq!(
+            key.jwk(),
+            json!({
+                "crv": "Ed25519",
+                "kty": "OKP",
+                "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+            })
+        );
+        assert_eq!(
+            key.thumbprint(),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+        assert_eq!(
+            key.key_authorization("token-1"),
+            "token-1.kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+    }
+
+    #[test]
+    fn signs_eddsa() {
+        let key = rfc8037_key();
+        let payload = json!({ "termsOfServiceAgreed": true });
+        let jws = key
+            .sign("https://ca.test/new-acct", "nonce-1", None, Some(&payload))
+            .unwrap();
+        let protected: Value =
+            serde_json::from_slice(&decode(jws["protected"].as_str().unwrap())).unwrap();
+        assert_eq!(protected["alg"], "EdDSA");
+        assert_eq!(protected["nonce"], "nonce-1");
+        assert_eq!(protected["url"], "https://ca.test/new-acct");
+        assert_eq!(protected["jwk"], key.jwk());
+        assert!(protected.get("kid").is_none());
+        verify(
+            &jws,
+            &ED25519,
+            &decode("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"),
+        );
+    }
+
+    #[test]
+    fn signs_es256_as_raw_r_and_s() {
+        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let key = AccountKey::from_key_pair(&key_pair).unwrap();
+        // POST-as-GET with an account URL
+        let jws = key
+            .sign(
+                "https://ca.test/order/1",
+                "nonce-2",
+                Some("https://ca.test/acct/1"),
+                None,
+            )
+            .unwrap();
+        let protected: Value =
+            serde_json::from_slice(&decode(jws["protected"].as_str().unwrap())).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["kid"], "https://ca.test/acct/1");
+        assert!(protected.get("jwk").is_none());
+        assert_eq!(jws["payload"], "");
+        assert_eq!(decode(jws["signature"].as_str().unwrap()).len(), 64);
+        verify(&jws, &ECDSA_P256_SHA256_FIXED, key_pair.public_key_raw());
+    }
+
+    #[test]
+    fn external_account_binding_macs_the_jwk() {
+        let key = rfc8037_key();
+        let binding = key
+            .external_account_binding("kid-1", b"secret", "https://ca.test/new-acct")
+            .unwrap();
+        let payload: Value =
+            serde_json::from_slice(&decode(binding["payload"].as_str().unwrap())).unwrap();
+        assert_eq!(payload, key.jwk());
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(
+            format!(
+                "{}.{}",
+                binding["protected"].as_str().unwrap(),
+                binding["payload"].as_str().unwrap()
+            )
+            .as_bytes(),
+        );
+        mac.verify_slice(&decode(binding["signature"].as_str().unwrap()))
+            .unwrap();
+    }
+
+    #[test]
+    fn der_to_raw_ecdsa_pads_and_strips() {
+        // r has a sign-padding zero byte, s is one byte short
+        let mut r = vec![0x02, 33, 0x00];
+        r.extend([0x80; 32]);
+        let mut s = vec![0x02, 31];
+        s.extend([0x11; 31]);
+        let mut der = vec![0x30, (r.len() + s.len()) as u8];
+        der.extend(&r);
+        der.extend(&s);
+
+        let raw = der_to_raw_ecdsa(&der, 32).unwrap();
+        assert_eq!(&raw[..32], &[0x80; 32]);
+        assert_eq!(raw[32], 0);
+        assert_eq!(&raw[33..], &[0x11; 31]);
+    }
+
+    #[test]
+    fn der_to_raw_ecdsa_rejects_malformed() {
+        // Wrong outer tag
+        assert!(der_to_raw_ecdsa(&[0x31, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01], 32).is_err());
+        // Length past the end
+        assert!(der_to_raw_ecdsa(&[0x30, 0x08, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01], 32).is_err());
+        // Missing s
+        assert!(der_to_raw_ecdsa(&[0x30, 0x03, 0x02, 0x01, 0x01], 32).is_err());
+        // Integer wider than the curve
+        let mut der = vec![0x30, 0x25, 0x02, 0x21];
+        der.extend([0x01; 33]);
+        der.extend([0x02, 0x01, 0x01]);
+        assert!(der_to_raw_ecdsa(&der, 32).is_err());
+        assert!(der_to_raw_ecdsa(&[], 32).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use aws_lc_rs::encoding::AsDer;
+use aws_lc_rs::rsa::{KeyPair as RsaKeyPair, KeySize};
+use rcgen::{
+    CertificateParams, DistinguishedName, KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384,
+    PKCS_ED25519,
+};
+
+use crate::config::KeyType;
+
+use super::AcmeError;
+
+// A freshly generated certificate key and the CSR signed with it
+pub struct GeneratedKey {
+    // PKCS#8 DER; only ever written out encrypted
+    pub pkcs8: Vec<u8>,
+    pub csr: Vec<u8>,
+}
+
+fn key_error(e: impl std::fmt::Display) -> AcmeError {
+    AcmeError::Protocol(format!("Key generation failed: {}", e))
+}
+
+fn rsa(size: KeySize) -> Result<KeyPair, AcmeError> {
+    let key = RsaKeyPair::generate(size).map_err(key_error)?;
+    let der = AsDer::as_der(&key).map_err(key_error)?;
+    KeyPair::try_from(der.as_ref()).map_err(key_error)
+}
+
+pub fn generate(key_type: KeyType, domains: &[String]) -> Result<GeneratedKey, AcmeError> {
+    let key_pair = match key_type {
+        KeyType::EcdsaP256 => KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(key_error)?,
+        KeyType::EcdsaP384 => KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384).map_err(key_error)?,
+        KeyType::Ed25519 => KeyPair::generate_for(&PKCS_ED25519).map_err(key_error)?,
+        KeyType::Rsa2048 => rsa(KeySize::Rsa2048)?,
+        KeyType::Rsa4096 => rsa(KeySize::Rsa4096)?,
+    };
+    let csr = CertificateParams::new(domains.to_vec())
+        .and_then(|mut params| {
+            // rcgen's default subject CN is not one of the names; CAs
+            // reject a CSR naming anything the order doesn't
+            params.distinguished_name = DistinguishedName::new();
+            params.serialize_request(&key_pair)
+        })
+        .map_err(|e| AcmeError::Protocol(format!("Failed to build CSR: {}", e)))?;
+    Ok(GeneratedKey {
+        pkcs8: key_pair.serialize_der(),
+        csr: csr.der().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::certification_request::X509CertificationRequest;
+    use x509_parser::extensions::{GeneralName, ParsedExtension};
+    use x509_parser::prelude::FromDer;
+
+    #[test]
+    fn csr_names_only_the_domains() {
+        let domains = vec!["example.com".to_string(), "*.example.com".to_string()];
+        let generated = generate(KeyType::EcdsaP256, &domains).unwrap();
+        let (_, csr) = X509CertificationRequest::from_der(&generated.csr).unwrap();
+
+        let subject = &csr.certification_request_info.subject;
+        assert_eq!(subject.iter_attributes().count(), 0);
+
+        let names: Vec<String> = csr
+            .requested_extensions()
+            .into_iter()
+            .flatten()
+            .filter_map(|extension| match extension {
+                ParsedExtension::SubjectAlternativeName(san) => Some(san),
+                _ => None,
+            })
+            .flat_map(|san| san.general_names.iter())
+            .map(|name| match name {
+                GeneralName::DNSName(dns) => dns.to_string(),
+                other => panic!("unexpected name {:?}", other),
+            })
+            .collect();
+        assert_eq!(names, domains);
+    }
+}
@@ -0,0 +1,319 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+use thiserror::Error;
+use tokio::time::Duration;
+
+use crate::config::{AcmeSettings, ChallengeKind};
+
+mod client;
+mod dns01;
+mod http01;
+mod jws;
+mod keygen;
+mod names;
+mod preflight;
+
+use client::{AcmeClient, Order, Problem, Status};
+use dns01::DnsProvider;
+use http01::Http01Responder;
+use jws::AccountKey;
+pub use keygen::{generate, GeneratedKey};
+pub use names::{display_domain, parse_domains};
+pub use preflight::preflight;
+
+#[derive(Error, Debug)]
+pub enum AcmeError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("HTTP error talking to the CA: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("CA rejected the request ({status}): {} {}", .problem.kind, .problem.detail)]
+    Problem { status: u16, problem: Problem },
+    #[error("Validation failed for {domain}: {} {}", .problem.kind, .problem.detail)]
+    Validation { domain: String, problem: Problem },
+    #[error("DNS provider error: {0}")]
+    Dns(String),
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+    #[error("{0}")]
+    Protocol(String),
+}
+
+// Writes `contents` readable by the owner only, replacing `path` atomically so
+// a reader never sees a partial file
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    // A leftover from an interrupted write would keep its old mode
+    match fs::remove_file(&temporary) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&temporary)?.write_all(contents)?;
+    fs::rename(&temporary, path)
+}
+
+pub(crate) fn write_public(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+// The EAB MAC key, kept out of defe.json since it lets anyone register
+// accounts under the team's CA account
+pub const EAB_HMAC_KEY_ENV: &str = "DEFE_EAB_HMAC_KEY";
+
+// Decodes the EAB MAC key when `acme.eab` is configured
+pub(crate) fn external_account_key(settings: &AcmeSettings) -> Result<Option<Vec<u8>>, AcmeError> {
+    if settings.eab.is_none() {
+        return Ok(None);
+    }
+    let encoded = env::var(EAB_HMAC_KEY_ENV).map_err(|_| {
+        AcmeError::Protocol(format!("acme.eab is set but {} is not", EAB_HMAC_KEY_ENV))
+    })?;
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(encoded.trim().trim_end_matches('='))
+        .map(Some)
+        .map_err(|e| AcmeError::Protocol(format!("Invalid {}: {}", EAB_HMAC_KEY_ENV, e)))
+}
+
+// Obtains a certificate for the CSR from the configured ACME directory,
+// answering HTTP-01 challenges on our own listener or DNS-01 challenges
+// through the DNS provider. Returns the PEM chain.
+pub async fn issue(
+    settings: &AcmeSettings,
+    domains: &[String],
+    email: Option<&str>,
+    csr_der: &[u8],
+) -> Result<String, AcmeError> {
+    // Catch a missing provider before anything is ordered
+    let provider = match settings.challenge {
+        ChallengeKind::Http01 => None,
+        ChallengeKind::Dns01 => {
+            let dns = settings.dns.as_ref().ok_or_else(|| {
+                AcmeError::Protocol(
+                    "DNS-01 challenges need an \"acme.dns\" section in defe.json".to_string(),
+                )
+            })?;
+            Some(dns01::provider(dns)?)
+        }
+    };
+
+    let directory_url = settings.directory()?;
+    let eab_key = external_account_key(settings)?;
+
+    let (account_key, created) = AccountKey::load_or_create(Path::new(&settings.account_key))?;
+    if created {
+        println!("Created ACME account key {}", settings.account_key);
+    }
+
+    println!("Using ACME directory {}", directory_url);
+    if settings.staging {
+        println!(
+            "Staging certificates aren't trusted by browsers; turn off acme.staging for production"
+        );
+    }
+    let mut client = AcmeClient::connect(
+        directory_url,
+        settings.root_bundle.as_deref().map(Path::new),
+        account_key,
+    )
+    .await?;
+    let eab = match (&settings.eab, &eab_key) {
+        (Some(eab), Some(hmac_key)) => Some((eab.key_id.as_str(), hmac_key.as_slice())),
+        _ if client.directory().meta.external_account_required => {
+            return Err(AcmeError::Protocol(format!(
+                "{} requires External Account Binding; set acme.eab.key_id and {}",
+                directory_url, EAB_HMAC_KEY_ENV
+            )))
+        }
+        _ => None,
+    };
+    let account = client.register(email, eab).await?;
+    println!("ACME account: {}", account);
+
+    let (order, order_url) = client.new_order(domains).await?;
+    let timeout = Duration::from_secs(settings.timeout_secs);
+
+    // Challenge records are removed whether or not validation succeeded
+    let mut records = Vec::new();
+    let authorized = authorize(
+        &mut client,
+        settings,
+        &order,
+        provider.as_deref(),
+        &mut records,
+    )
+    .await;
+    if let Some(provider) = &provider {
+        for (name, value) in &records {
+            if let Err(e) = provider.delete_txt_record(name, value).await {
+                eprintln!("Failed to remove the TXT record at {}: {}", name, e);
+            }
+        }
+    }
+    authorized?;
+
+    client.finalize(&order, &order_url, csr_der, timeout).await
+}
+
+// Which key vouches for a revocation request
+#[derive(Clone, Copy)]
+pub enum RevocationKey<'a> {
+    // The ACME account that obtained the certificate
+    Account,
+    // The certificate's own private key, as PKCS#8 DER
+    Certificate(&'a [u8]),
+}
+
+// Asks the configured CA to revoke the DER certificate. Returns false if it
+// had already been revoked.
+pub async fn revoke(
+    settings: &AcmeSettings,
+    certificate_der: &[u8],
+    reason: u8,
+    key: RevocationKey<'_>,
+) -> Result<bool, AcmeError> {
+    let signing_key = match key {
+        RevocationKey::Account => {
+            let path = Path::new(&settings.account_key);
+            if !path.exists() {
+                return Err(AcmeError::Protocol(format!(
+                    "No ACME account key at {}; revoke with the certificate key instead",
+                    settings.account_key
+                )));
+            }
+            AccountKey::load(path)?
+        }
+        RevocationKey::Certificate(pkcs8) => AccountKey::from_certificate_key(pkcs8)?,
+    };
+
+    let directory_url = settings.directory()?;
+    println!("Using ACME directory {}", directory_url);
+    let mut client = AcmeClient::connect(
+        directory_url,
+        settings.root_bundle.as_deref().map(Path::new),
+        signing_key,
+    )
+    .await?;
+    if let RevocationKey::Account = key {
+        let account = client.find_account().await?;
+        println!("ACME account: {}", account);
+    }
+
+    match client.revoke(certificate_der, reason).await {
+        Ok(()) => Ok(true),
+        Err(AcmeError::Problem { problem, .. })
+            if problem.kind == "urn:ietf:params:acme:error:alreadyRevoked" =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Completes every pending authorization in the order. DNS-01 records
+// published along the way are added to `records`.
+async fn authorize(
+    client: &mut AcmeClient,
+    settings: &AcmeSettings,
+    order: &Order,
+    provider: Option<&dyn DnsProvider>,
+    records: &mut Vec<(String, String)>,
+) -> Result<(), AcmeError> {
+    let kind = settings.challenge.name();
+    let timeout = Duration::from_secs(settings.timeout_secs);
+
+    // Only start listening once there is something to validate
+    let mut responder = None;
+    let mut pending = Vec::new();
+    for authorization_url in &order.authorizations {
+        let authorization = client.authorization(authorization_url).await?;
+        let domain = authorization.domain();
+        if authorization.status == Status::Valid {
+            println!("{} is already authorized for this account", domain);
+            continue;
+        }
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == kind)
+            .ok_or_else(|| {
+                AcmeError::Protocol(format!("CA offered no {} challenge for {}", kind, domain))
+            })?;
+        let token = challenge
+            .token
+            .as_deref()
+            .ok_or_else(|| AcmeError::Protocol(format!("{} challenge has no token", kind)))?;
+        let key_authorization = client.key().key_authorization(token);
+
+        match provider {
+            None => {
+                if responder.is_none() {
+                    responder = Some(Http01Responder::bind(&settings.http01_listen).await?);
+                }
+                if let Some(responder) = &responder {
+                    responder.add(token, key_authorization);
+                }
+            }
+            Some(provider) => {
+                let name = dns01::record_name(&authorization.identifier.value);
+                let value = dns01::record_value(&key_authorization);
+                println!("Publishing TXT record {}", name);
+                provider.create_txt_record(&name, &value).await?;
+                records.push((name, value));
+            }
+        }
+        pending.push((authorization_url, domain, challenge.url.clone()));
+    }
+
+    if let Some(dns) = settings.dns.as_ref().filter(|_| !records.is_empty()) {
+        dns01::wait_for_propagation(dns, records).await?;
+    }
+
+    for (authorization_url, domain, challenge_url) in pending {
+        println!("Validating {}...", domain);
+        client.respond(&challenge_url).await?;
+        client
+            .wait_for_authorization(authorization_url, timeout)
+            .await?;
+        println!("{} validated", domain);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("defe-acme-{}.pem", std::process::id()));
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, "stale").unwrap();
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"key").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, b"key");
+        assert!(!temporary.exists());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+
+// RFC 1035 limits, applied to the ASCII form
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 253;
+
+// Parses a comma- or whitespace-separated list of domain names into the
+// ASCII (A-label) forms a certificate carries. Internationalized names are
+// converted with IDNA. Wildcards (`*.example.com`) need DNS-01 validation.
+pub fn parse_domains(input: &str, allow_wildcards: bool) -> Result<Vec<String>, String> {
+    let mut domains = Vec::new();
+    let mut seen = BTreeSet::new();
+    for name in input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+    {
+        let domain = normalize(name, allow_wildcards)?;
+        if seen.insert(domain.clone()) {
+            domains.push(domain);
+        }
+    }
+    if domains.is_empty() {
+        return Err("Enter at least one domain name".to_string());
+    }
+
+    // CAs reject names already covered by a wildcard in the same order
+    for domain in &domains {
+        let Some((_, parent)) = domain.split_once('.') else {
+            continue;
+        };
+        if !domain.starts_with("*.") && seen.contains(&format!("*.{}", parent)) {
+            return Err(format!(
+                "{} is already covered by *.{}; list only one of them",
+                domain, parent
+            ));
+        }
+    }
+    Ok(domains)
+}
+
+fn normalize(name: &str, allow_wildcards: bool) -> Result<String, String> {
+    let name = name.trim_end_matches('.');
+    let (wildcard, base) = match name.strip_prefix("*.") {
+        Some(base) => (true, base),
+        None => (false, name),
+    };
+    if wildcard && !allow_wildcards {
+        return Err(format!(
+            "{} is a wildcard name, which needs DNS-01 validation; set acme.challenge to \"dns-01\"",
+            name
+        ));
+    }
+    if base.contains('*') {
+        return Err(format!(
+            "{}: a wildcard may only be the whole leftmost label, as in *.example.com",
+            name
+        ));
+    }
+
+    let ascii = idna::domain_to_ascii_strict(base)
+        .map_err(|_| format!("{} is not a valid domain name", name))?;
+    let labels: Vec<&str> = ascii.split('.').collect();
+    if labels.len() < 2 {
+        return Err(format!(
+            "{} is not a fully qualified domain name, e.g. example.com",
+            name
+        ));
+    }
+    for label in &labels {
+        if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+            return Err(format!(
+                "{}: each label must be 1 to {} characters",
+                name, MAX_LABEL_LENGTH
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("{}: labels can't start or end with a hyphen", name));
+        }
+    }
+    // Rules out IPv4 addresses, which need IP identifiers instead
+    if labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{} is an IP address, not a domain name", name));
+    }
+
+    let domain = if wildcard {
+        format!("*.{}", ascii)
+    } else {
+        ascii
+    };
+    if domain.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "{} is longer than {} characters",
+            name, MAX_NAME_LENGTH
+        ));
+    }
+    Ok(domain)
+}
+
+// How a domain is shown to people: the ASCII form, followed by the Unicode
+// form for an internationalized name
+pub fn display_domain(domain: &str) -> String {
+    let (unicode, result) = idna::domain_to_unicode(domain);
+    if result.is_ok() && unicode != domain {
+        format!("{} ({})", domain, unicode)
+    } else {
+        domain.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lists_of_names() {
+        assert_eq!(
+            parse_domains("Example.com, www.example.com.\n example.com", false).unwrap(),
+            ["example.com", "www.example.com"]
+        );
+        assert!(parse_domains(" , ", false).is_err());
+    }
+
+    #[test]
+    fn converts_internationalized_names() {
+        assert_eq!(
+            parse_domains("bücher.example", false).unwrap(),
+            ["xn--bcher-kva.example"]
+        );
+        assert_eq!(
+            display_domain("xn--bcher-kva.example"),
+            "xn--bcher-kva.example (bücher.example)"
+        );
+        assert_eq!(display_domain("example.com"), "example.com");
+    }
+
+    #[test]
+    fn checks_wildcards() {
+        assert!(parse_domains("*.example.com", false).is_err());
+        assert_eq!(
+            parse_domains("*.example.com example.com", true).unwrap(),
+            ["*.example.com", "example.com"]
+        );
+        assert!(parse_domains("*.example.com www.example.com", true).is_err());
+        assert!(parse_domains("www.*.example.com", true).is_err());
+        assert!(parse_domains("w*.example.com", true).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let long_label = format!("{}.com", "a".repeat(MAX_LABEL_LENGTH + 1));
+        let long_name = format!("{}.com", vec!["a".repeat(60); 5].join("."));
+        for name in [
+            "localhost",
+            "-example.com",
+            "example-.com",
+            "exa_mple.com",
+            "a..example.com",
+            "192.0.2.1",
+            long_label.as_str(),
+            long_name.as_str(),
+        ] {
+            assert!(parse_domains(name, false).is_err(), "{}", name);
+        }
+    }
+}
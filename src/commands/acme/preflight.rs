@@ -0,0 +1,443 @@
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use rand::{thread_rng, Rng};
+use tokio::time::Duration;
+
+use crate::config::{AcmeSettings, ChallengeKind, PreflightSettings};
+
+use super::client::{fetch_directory, http_client};
+use super::dns01::wire::{self, TYPE_A, TYPE_AAAA, TYPE_CAA};
+use super::http01::Http01Responder;
+use super::jws::base64url;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Checks that the CA will be able to validate and issue for `domains` before
+// anything is ordered. Returns a diagnosis for each problem found.
+pub async fn preflight(settings: &AcmeSettings, domains: &[String]) -> Vec<String> {
+    let checks = &settings.preflight;
+    let mut problems = Vec::new();
+
+    // DNS-01 doesn't need the domains to point here yet
+    if settings.challenge == ChallengeKind::Http01 {
+        let public_ips = public_ips(checks, &mut problems).await;
+        for domain in domains {
+            check_addresses(checks, domain, public_ips.as_ref(), &mut problems).await;
+        }
+        if checks.check_port_80 && problems.is_empty() {
+            probe_port_80(settings, domains, &mut problems).await;
+        }
+    }
+    check_caa(settings, domains, &mut problems).await;
+    problems
+}
+
+// This host's public addresses, as configured or as seen from outside
+async fn public_ips(
+    checks: &PreflightSettings,
+    problems: &mut Vec<String>,
+) -> Option<BTreeSet<IpAddr>> {
+    if !checks.public_ips.is_empty() {
+        let mut configured = BTreeSet::new();
+        for ip in &checks.public_ips {
+            match ip.parse() {
+                Ok(ip) => {
+                    configured.insert(ip);
+                }
+                Err(_) => problems.push(format!(
+                    "acme.preflight.public_ips: {} is not an IP address",
+                    ip
+                )),
+            }
+        }
+        return Some(configured);
+    }
+
+    let Ok(http) = reqwest::Client::builder().timeout(PROBE_TIMEOUT).build() else {
+        return None;
+    };
+    let mut detected = BTreeSet::new();
+    // An echo service for an address family the host lacks just fails
+    for url in &checks.ip_echo_urls {
+        let response = http
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Ok(body) = match response {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        } {
+            if let Ok(ip) = body.trim().parse() {
+                detected.insert(ip);
+            }
+        }
+    }
+    if detected.is_empty() {
+        problems.push(format!(
+            "Could not detect this host's public IP address (asked {}). Set acme.preflight.public_ips.",
+            checks.ip_echo_urls.join(", ")
+        ));
+        return None;
+    }
+    println!("This host's public address(es): {}", join(detected.iter()));
+    Some(detected)
+}
+
+fn join<T: ToString>(items: impl Iterator<Item = T>) -> String {
+    items
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+// The A and AAAA addresses `resolver` returns for `domain`
+async fn resolve(resolver: &str, domain: &str) -> std::io::Result<BTreeSet<IpAddr>> {
+    let mut addresses = BTreeSet::new();
+    for rdata in wire::lookup(resolver, domain, TYPE_A).await? {
+        if let Ok(octets) = <[u8; 4]>::try_from(rdata.as_slice()) {
+            addresses.insert(IpAddr::V4(Ipv4Addr::from(octets)));
+        }
+    }
+    for rdata in wire::lookup(resolver, domain, TYPE_AAAA).await? {
+        if let Ok(octets) = <[u8; 16]>::try_from(rdata.as_slice()) {
+            addresses.insert(IpAddr::V6(Ipv6Addr::from(octets)));
+        }
+    }
+    Ok(addresses)
+}
+
+// Checks that every resolver agrees `domain` points at this host
+async fn check_addresses(
+    checks: &PreflightSettings,
+    domain: &str,
+    public_ips: Option<&BTreeSet<IpAddr>>,
+    problems: &mut Vec<String>,
+) {
+    if domain.starts_with("*.") {
+        problems.push(format!(
+            "{} is a wildcard name, which can only be validated with DNS-01. Set acme.challenge to \"dns-01\".",
+            domain
+        ));
+        return;
+    }
+
+    let mut answers = Vec::new();
+    for resolver in &checks.resolvers {
+        match resolve(resolver, domain).await {
+            Ok(addresses) => answers.push((resolver, addresses)),
+            Err(e) => problems.push(format!(
+                "Resolver {} could not look up {}: {}",
+                resolver, domain, e
+            )),
+        }
+    }
+    let Some((_, first)) = answers.first() else {
+        return;
+    };
+    if answers.iter().any(|(_, addresses)| addresses != first) {
+        problems.push(format!(
+            "Resolvers disagree about {}: {}. A recent DNS change may still be propagating.",
+            domain,
+            join(answers.iter().map(|(resolver, addresses)| format!(
+                "{} returns [{}]",
+                resolver,
+                join(addresses.iter())
+            )))
+        ));
+    }
+
+    let addresses: BTreeSet<IpAddr> = answers
+        .into_iter()
+        .flat_map(|(_, addresses)| addresses)
+        .collect();
+    if addresses.is_empty() {
+        problems.push(format!(
+            "{} has no A or AAAA record. Create an A record pointing to {}.",
+            domain,
+            public_ips
+                .map(|ips| join(ips.iter()))
+                .unwrap_or_else(|| "this server's public IP address".to_string())
+        ));
+        return;
+    }
+    println!("{} resolves to {}", domain, join(addresses.iter()));
+
+    for address in &addresses {
+        let record = if address.is_ipv4() { "A" } else { "AAAA" };
+        if is_private(address) {
+            problems.push(format!(
+                "The {} record of {} points to {}, a private address the CA can't reach.",
+                record, domain, address
+            ));
+            continue;
+        }
+        let Some(public_ips) = public_ips else {
+            continue;
+        };
+        if public_ips.contains(address) {
+            continue;
+        }
+        let same_family = public_ips
+            .iter()
+            .any(|ip| ip.is_ipv4() == address.is_ipv4());
+        problems.push(if same_family {
+            format!(
+                "The {} record of {} points to {}, which is not this host ({}). Update the record.",
+                record,
+                domain,
+                address,
+                join(public_ips.iter())
+            )
+        } else {
+            // Let's Encrypt tries IPv6 first when there is an AAAA record
+            format!(
+                "{} has an {} record ({}) but this host has no public {} address. Remove the record or give the host that address.",
+                domain,
+                record,
+                address,
+                if address.is_ipv4() { "IPv4" } else { "IPv6" }
+            )
+        });
+    }
+}
+
+// Serves a random token on the HTTP-01 listener and fetches it through each
+// domain, the way the CA will
+async fn probe_port_80(settings: &AcmeSettings, domains: &[String], problems: &mut Vec<String>) {
+    let responder = match Http01Responder::bind(&settings.http01_listen).await {
+        Ok(responder) => responder,
+        Err(e) => {
+            problems.push(format!(
+                "{}. Stop any other web server using the port or change acme.http01_listen.",
+                e
+            ));
+            return;
+        }
+    };
+    let token = base64url(&thread_rng().gen::<[u8; 16]>());
+    let expected = format!("defe-preflight.{}", token);
+    responder.add(&token, expected.clone());
+
+    let Ok(http) = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    else {
+        return;
+    };
+    for domain in domains.iter().filter(|domain| !domain.starts_with("*.")) {
+        let url = format!("http://{}/.well-known/acme-challenge/{}", domain, token);
+        let diagnosis = match http.get(&url).send().await {
+            Ok(response) if response.status().is_success() => match response.text().await {
+                Ok(body) if body == expected => {
+                    println!("Port 80 of {} reaches this host", domain);
+                    continue;
+                }
+                _ => format!(
+                    "Port 80 of {} is answered by a different server. A proxy or CDN in front of the domain must pass /.well-known/acme-challenge/ through.",
+                    domain
+                ),
+            },
+            Ok(response) => format!(
+                "Port 80 of {} answered {} instead of serving the probe. Another server, proxy or CDN is handling the domain.",
+                domain,
+                response.status()
+            ),
+            Err(e) if e.is_timeout() => format!(
+                "Timed out connecting to port 80 of {}. A firewall or a missing port forward is probably dropping connections. (Some routers can't connect a host to its own public address; if so, check from outside.)",
+                domain
+            ),
+            Err(e) if e.is_connect() => format!(
+                "Could not connect to port 80 of {}: {}. Open port 80 in the firewall and forward it to this host.",
+                domain, e
+            ),
+            Err(e) => format!("Could not fetch {}: {}", url, e),
+        };
+        problems.push(diagnosis);
+    }
+}
+
+// (flags, tag, value) of a CAA record
+fn parse_caa(rdata: &[u8]) -> Option<(u8, String, String)> {
+    let (&flags, rest) = rdata.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    let tag = rest.get(..length as usize)?;
+    let value = &rest[length as usize..];
+    Some((
+        flags,
+        String::from_utf8_lossy(tag).to_ascii_lowercase(),
+        String::from_utf8_lossy(value).into_owned(),
+    ))
+}
+
+// Checks that CAA records, if any, let the CA issue for each domain
+async fn check_caa(settings: &AcmeSettings, domains: &[String], problems: &mut Vec<String>) {
+    let checks = &settings.preflight;
+    let Some(resolver) = checks.resolvers.first() else {
+        return;
+    };
+    let identities = if checks.caa_identities.is_empty() {
+        let directory_url = match settings.directory() {
+            Ok(url) => url,
+            Err(e) => {
+                problems.push(e.to_string());
+                return;
+            }
+        };
+        let directory = match http_client(settings.root_bundle.as_deref().map(Path::new)) {
+            Ok(http) => fetch_directory(&http, directory_url).await,
+            Err(e) => Err(e),
+        };
+        match directory {
+            Ok(directory) => directory.meta.caa_identities,
+            Err(e) => {
+                problems.push(format!(
+                    "Could not read the CA's CAA identities from {}: {}. Set acme.preflight.caa_identities.",
+                    directory_url, e
+                ));
+                return;
+            }
+        }
+    } else {
+        checks.caa_identities.clone()
+    };
+    if identities.is_empty() {
+        println!("The CA names no CAA identities; skipping the CAA check");
+        return;
+    }
+
+    for domain in domains {
+        let wildcard = domain.starts_with("*.");
+        let labels: Vec<&str> = domain.trim_start_matches("*.").split('.').collect();
+
+        // RFC 8659: the closest CAA record set walking up from the name applies
+        let mut relevant = None;
+        for start in 0..labels.len() {
+            let name = labels[start..].join(".");
+            match wire::lookup(resolver, &name, TYPE_CAA).await {
+                Ok(records) if records.is_empty() => {}
+                Ok(records) => {
+                    relevant = Some((name, records));
+                    break;
+                }
+                Err(e) => {
+                    problems.push(format!("Could not look up CAA records for {}: {}", name, e));
+                    break;
+                }
+            }
+        }
+        // No CAA records anywhere lets every CA issue
+        let Some((name, records)) = relevant else {
+            continue;
+        };
+
+        let records: Vec<(u8, String, String)> = records
+            .iter()
+            .filter_map(|rdata| parse_caa(rdata))
+            .collect();
+        if let Some((_, tag, _)) = records.iter().find(|(flags, tag, _)| {
+            flags & 0x80 != 0 && !matches!(tag.as_str(), "issue" | "issuewild" | "iodef")
+        }) {
+            problems.push(format!(
+                "CAA records at {} include the critical property \"{}\", which the CA will refuse to issue under.",
+                name, tag
+            ));
+            continue;
+        }
+        // Wildcards are governed by issuewild when there is any
+        let tag = if wildcard && records.iter().any(|(_, tag, _)| tag == "issuewild") {
+            "issuewild"
+        } else {
+            "issue"
+        };
+        let values: Vec<&String> = records
+            .iter()
+            .filter(|(_, found, _)| found == tag)
+            .map(|(_, _, value)| value)
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        let allowed = values.iter().any(|value| {
+            let issuer = value.split(';').next().unwrap_or_default().trim();
+            identities
+                .iter()
+                .any(|identity| identity.eq_ignore_ascii_case(issuer))
+        });
+        if !allowed {
+            problems.push(format!(
+                "CAA records at {} only allow [{}] to issue for {}. Add the record: {} CAA 0 {} \"{}\"",
+                name,
+                join(values.iter()),
+                domain,
+                name,
+                tag,
+                identities[0]
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_caa_records() {
+        // 0 issue "letsencrypt.org; validationmethods=dns-01"
+        let mut rdata = vec![0, 5];
+        rdata.extend_from_slice(b"issue");
+        rdata.extend_from_slice(b"letsencrypt.org; validationmethods=dns-01");
+        assert_eq!(
+            parse_caa(&rdata),
+            Some((
+                0,
+                "issue".to_string(),
+                "letsencrypt.org; validationmethods=dns-01".to_string()
+            ))
+        );
+
+        // Critical flag, tags compared case-insensitively, empty value
+        assert_eq!(
+            parse_caa(b"\x80\x09IssueWild"),
+            Some((0x80, "issuewild".to_string(), String::new()))
+        );
+
+        assert_eq!(parse_caa(b""), None);
+        assert_eq!(parse_caa(b"\x00"), None);
+        assert_eq!(parse_caa(b"\x00\x05iss"), None);
+    }
+
+    #[test]
+    fn recognizes_private_addresses() {
+        for ip in [
+            "10.1.2.3",
+            "192.168.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(is_private(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["203.0.113.7", "2001:db8::1"] {
+            assert!(!is_private(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+// Append-only record of irreversible actions taken on a workspace, one JSON
+// object per line, kept in the workspace directory
+pub const AUDIT_FILE: &str = "defe-audit.jsonl";
+
+pub fn record(dir: &Path, action: &str, details: Value) -> io::Result<()> {
+    let entry = json!({
+        "time": Utc::now().to_rfc3339(),
+        "action": action,
+        "user": env::var("USER").ok(),
+        "details": details,
+    });
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(dir.join(AUDIT_FILE))?;
+    // One write, so concurrent writers can't interleave within a line
+    file.write_all(format!("{}\n", entry).as_bytes())?;
+    file.sync_all()
+}
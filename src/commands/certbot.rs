@@ -1,10 +1,17 @@
+use crate::commands::acme::{self, write_private, write_public};
+use crate::commands::certs::{run_post_hooks, run_pre_hooks, HookContext};
+use crate::commands::{invalid_data, keystore};
+use crate::config::{AcmeClientKind, AcmeSettings, ChallengeKind, Config, KeyType};
 use crate::*;
-use dialoguer::{Confirm, Input};
+use base64::{engine::general_purpose, Engine as _};
+use dialoguer::{Confirm, Input, Select};
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::prelude::{FromDer, X509Certificate};
 // this program will fetch the SSL chain spec from Let's Encrypt using the Certbot toolkit in rust-sgx
 pub fn run() {
     println!("Running defe-certbot");
@@ -63,13 +70,45 @@ pub fn run() {
             }
         }
 
-        confirm_domain_configuration_certbot();
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                log_error!("Failed to load configuration: {}", e);
+                return;
+            }
+        };
+        let settings = config.acme.clone();
+        if settings.challenge == ChallengeKind::Dns01 && settings.client == AcmeClientKind::Certbot
+        {
+            log_error!("DNS-01 challenges need the built-in ACME client; set \"acme.client\" to \"native\"");
+            return;
+        }
 
-        // Prompt for domain name
-        let domain_name = Input::<String>::new()
-            .with_prompt("Enter your domain name (e.g., example.com)")
+        // Prompt for the domain names; every one becomes a SAN
+        let wildcards = settings.challenge == ChallengeKind::Dns01;
+        let prompt = if wildcards {
+            "Enter your domain names, separated by commas (e.g., example.com, www.example.com, *.example.com)"
+        } else {
+            "Enter your domain names, separated by commas (e.g., example.com, www.example.com)"
+        };
+        let domain_names = Input::<String>::new()
+            .with_prompt(prompt)
+            .validate_with(|input: &String| -> Result<(), String> {
+                acme::parse_domains(input, wildcards).map(|_| ())
+            })
             .interact_text()
             .unwrap();
+        let domains = match acme::parse_domains(&domain_names, wildcards) {
+            Ok(domains) => domains,
+            Err(e) => {
+                log_error!("{}", e);
+                return;
+            }
+        };
+        let domain_list: Vec<String> = domains
+            .iter()
+            .map(|domain| format!("  - {}", acme::display_domain(domain)))
+            .collect();
 
         // Prompt for email address
         let email_address = Input::<String>::new()
@@ -77,9 +116,30 @@ pub fn run() {
             .interact_text()
             .unwrap();
 
+        // Check what the CA will check before spending an order on it
+        println!("Checking that {} is ready for validation...", domains.join(", "));
+        let problems = acme::preflight(&settings, &domains).await;
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("{}", format!("Problem: {}", problem).bright_red());
+            }
+            if settings.challenge == ChallengeKind::Http01 {
+                print_domain_configuration_help_certbot();
+            }
+            let proceed = Confirm::new()
+                .with_prompt("Validation is likely to fail. Proceed anyway?")
+                .default(false)
+                .interact()
+                .unwrap();
+            if !proceed {
+                println!("Exiting...");
+                return;
+            }
+        }
+
         // Confirm the entered information
         let confirm = Confirm::new()
-            .with_prompt(format!("Domain: {}\nEmail: {}\n\nPlease confirm that your domain is properly configured and pointing to your server's IP address.\nAlso, ensure that your server is accessible from the internet on port 80 (HTTP) and 443 (HTTPS).\n\nIs this information correct and are you ready to proceed?", domain_name, email_address))
+            .with_prompt(format!("Domains:\n{}\nEmail: {}\n\nIs this information correct and are you ready to proceed?", domain_list.join("\n"), email_address))
             .interact()
             .unwrap();
 
@@ -88,34 +148,246 @@ pub fn run() {
             return;
         }
 
-        println!("Running Certbot to obtain the SSL/TLS certificate and key files...");
-
-        // Run Certbot to obtain the certificate and key files in the target directory
-        let output = match Command::new("certbot")
-            .arg("certonly")
-            .arg("--standalone")
-            .arg("--noninteractive")
-            .arg("--agree-tos")
-            .arg(format!("--email={}", email_address))
-            .arg(format!("--domain={}", domain_name))
-            .arg("--cert-path")
-            .arg(target_dir.join("fullchain.pem"))
-            .arg("--key-path")
-            .arg(target_dir.join("privkey.pem"))
-            .output()
-        {
-            Ok(output) => output,
+        // The key is generated here and never handed to an external tool
+        let key_type = KeyType::ALL[Select::new()
+            .with_prompt("Select the certificate key type")
+            .items(&KeyType::ALL.map(KeyType::label))
+            .default(
+                KeyType::ALL
+                    .iter()
+                    .position(|key_type| *key_type == settings.key_type)
+                    .unwrap_or(0),
+            )
+            .interact()
+            .unwrap()];
+        let key = match acme::generate(key_type, &domains) {
+            Ok(key) => key,
             Err(e) => {
-                log_error!("Failed to execute Certbot: {}", e);
+                log_error!("{}", e);
+                return;
+            }
+        };
+        // Ask for the passphrase before issuance so a typo can't cost a certificate
+        let stored_key = match keystore::encrypt(&key.pkcs8, settings.key_store) {
+            Ok(stored_key) => stored_key,
+            Err(e) => {
+                log_error!("Failed to protect the private key: {}", e);
                 return;
             }
         };
 
-        if output.status.success() {
-            print_success_message_certbot(&target_dir);
-        } else {
-            let error_message = String::from_utf8_lossy(&output.stderr);
-            print_certbot_error_message(&error_message);
+        let context = HookContext {
+            event: "issue",
+            dir: &target_dir,
+            domains: &domains,
+        };
+        if let Err(e) = run_pre_hooks(&config, &context).await {
+            log_error!("{}", e);
+            return;
+        }
+
+        let chain =
+            obtain_certificate(&settings, &key.csr, &domains, Some(&email_address), &target_dir)
+                .await;
+        let chain = match chain {
+            Ok(chain) => chain,
+            Err(e) => {
+                print_certbot_error_message(&e);
+                writeln!(log_file, "{}", e).expect("Failed to write to log file");
+                return;
+            }
+        };
+
+        match install_certificate(&target_dir, &chain, &stored_key) {
+            Ok(()) => {
+                print_success_message_certbot(&target_dir, &domain_list);
+                // The certificate is installed; hook failures only need reporting
+                for failure in run_post_hooks(&config, &context).await {
+                    eprintln!("{}", failure.bright_red());
+                    writeln!(log_file, "{}", failure).expect("Failed to write to log file");
+                }
+            }
+            Err(e) => {
+                log_error!("Failed to save the certificate and key: {}", e);
+            }
         }
     });
 }
+
+// Has the configured ACME client sign the CSR. Returns the PEM chain.
+pub(crate) async fn obtain_certificate(
+    settings: &AcmeSettings,
+    csr: &[u8],
+    domains: &[String],
+    email_address: Option<&str>,
+    target_dir: &Path,
+) -> Result<String, String> {
+    match settings.client {
+        AcmeClientKind::Native => {
+            println!("Requesting the SSL/TLS certificate with the built-in ACME client...");
+            acme::issue(settings, domains, email_address, csr)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        AcmeClientKind::Certbot => {
+            println!("Running Certbot to obtain the SSL/TLS certificate...");
+            run_certbot_with_csr(settings, csr, domains, email_address, target_dir)
+        }
+    }
+}
+
+// Replaces the certificate and key in `target_dir`. The key goes first: a
+// server reloading in between fails the key check and keeps the old pair.
+pub(crate) fn install_certificate(
+    target_dir: &Path,
+    chain: &str,
+    stored_key: &[u8],
+) -> io::Result<()> {
+    write_private(&target_dir.join(keystore::STORE_FILE), stored_key)?;
+    write_public(&target_dir.join("fullchain.pem"), chain.as_bytes())
+}
+
+// Has certbot sign our CSR, so it never sees the private key. Certbot won't
+// overwrite existing files, so it writes into a scratch directory that is
+// removed afterwards, along with any EAB credentials written there. The CA and its credentials come from `settings` as for
+// the built-in client. Returns the PEM chain.
+fn run_certbot_with_csr(
+    settings: &AcmeSettings,
+    csr: &[u8],
+    domains: &[String],
+    email_address: Option<&str>,
+    target_dir: &Path,
+) -> Result<String, String> {
+    let directory_url = settings.directory().map_err(|e| e.to_string())?;
+    let eab_key = acme::external_account_key(settings).map_err(|e| e.to_string())?;
+    let work_dir = target_dir.join(".defe-certbot");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).map_err(|e| e.to_string())?;
+    let csr_path = work_dir.join("request.csr");
+    fs::write(&csr_path, csr).map_err(|e| e.to_string())?;
+
+    let mut command = Command::new("certbot");
+    command
+        .arg("certonly")
+        .arg("--standalone")
+        .arg("--noninteractive")
+        .arg("--agree-tos")
+        .arg("--csr")
+        .arg(&csr_path)
+        .arg("--cert-path")
+        .arg(work_dir.join("cert.pem"))
+        .arg("--chain-path")
+        .arg(work_dir.join("chain.pem"))
+        .arg("--fullchain-path")
+        .arg(work_dir.join("fullchain.pem"))
+        .arg("--server")
+        .arg(directory_url);
+    // The HMAC key goes in a private config file rather than on the command
+    // line, where other local users could read it
+    if let (Some(eab), Some(hmac_key)) = (&settings.eab, &eab_key) {
+        let config_path = work_dir.join("eab.ini");
+        let config = format!(
+            "eab-kid = {}\neab-hmac-key = {}\n",
+            eab.key_id,
+            general_purpose::URL_SAFE_NO_PAD.encode(hmac_key)
+        );
+        if let Err(e) = write_private(&config_path, config.as_bytes()) {
+            let _ = fs::remove_dir_all(&work_dir);
+            return Err(e.to_string());
+        }
+        command.arg("--config").arg(config_path);
+    }
+    // Certbot trusts the CA's TLS endpoint through the requests library
+    if let Some(root_bundle) = &settings.root_bundle {
+        command.env("REQUESTS_CA_BUNDLE", root_bundle);
+    }
+    // Renewals reuse the account registered on first issuance
+    match email_address {
+        Some(email_address) => command.arg(format!("--email={}", email_address)),
+        None => command.arg("--register-unsafely-without-email"),
+    };
+    for domain in domains {
+        command.arg(format!("--domain={}", domain));
+    }
+
+    let result = match command.output() {
+        Ok(output) if output.status.success() => {
+            match fs::read_to_string(work_dir.join("fullchain.pem")) {
+                Ok(chain) => Ok(chain),
+                // Some certbot versions save into the live directory instead
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    chain_from_lineage(settings, csr, &domains[0]).map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).into_owned()),
+        Err(e) => Err(format!("Failed to execute Certbot: {}", e)),
+    };
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+// Certbot keeps each certificate lineage in live/<name>/ as symlinks into
+// archive/. The name is the first domain without any wildcard prefix, with a
+// -NNNN suffix when the plain name was taken. Returns the most recently
+// updated lineage for `domain`.
+pub(crate) fn find_lineage(certbot_dir: &Path, domain: &str) -> io::Result<PathBuf> {
+    let live = certbot_dir.join("live");
+    let base = domain.trim_start_matches("*.");
+    let mut newest = None;
+    for entry in fs::read_dir(&live)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let matches = match name.strip_prefix(base) {
+            Some("") => true,
+            Some(suffix) => suffix
+                .strip_prefix('-')
+                .is_some_and(|n| n.len() == 4 && n.chars().all(|c| c.is_ascii_digit())),
+            None => false,
+        };
+        // Metadata follows the symlink to the current archive file
+        let modified = fs::metadata(entry.path().join("fullchain.pem")).and_then(|m| m.modified());
+        if let (true, Ok(modified)) = (matches, modified) {
+            if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                newest = Some((modified, entry.path()));
+            }
+        }
+    }
+    newest.map(|(_, path)| path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No certbot lineage for {} in {}", base, live.display()),
+        )
+    })
+}
+
+// Reads a file of a lineage through its symlink. Returns the resolved path too.
+pub(crate) fn read_lineage_file(lineage: &Path, file: &str) -> io::Result<(PathBuf, Vec<u8>)> {
+    let path = fs::canonicalize(lineage.join(file))?;
+    let contents = fs::read(&path)?;
+    Ok((path, contents))
+}
+
+// The chain certbot saved in its lineage for `domain`, provided it was issued
+// for the CSR's key rather than being an older certificate of the lineage
+fn chain_from_lineage(settings: &AcmeSettings, csr: &[u8], domain: &str) -> io::Result<String> {
+    let lineage = find_lineage(Path::new(&settings.certbot_dir), domain)?;
+    let (path, pem) = read_lineage_file(&lineage, "fullchain.pem")?;
+
+    let leaf = rustls_pemfile::certs(&mut pem.as_slice())
+        .next()
+        .ok_or_else(|| invalid_data(format!("No certificate found in {}", path.display())))??;
+    let (_, certificate) = X509Certificate::from_der(leaf.as_ref())
+        .map_err(|e| invalid_data(format!("Invalid certificate {}: {}", path.display(), e)))?;
+    let (_, request) = X509CertificationRequest::from_der(csr)
+        .map_err(|e| invalid_data(format!("Invalid CSR: {}", e)))?;
+    if certificate.public_key().raw != request.certification_request_info.subject_pki.raw {
+        return Err(invalid_data(format!(
+            "{} was not issued for the requested key",
+            path.display()
+        )));
+    }
+    println!("Copied the certificate from {}", path.display());
+    String::from_utf8(pem).map_err(|e| invalid_data(e.to_string()))
+}
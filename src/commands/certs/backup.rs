@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use dialoguer::{Input, Password};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::sign::CertifiedKey;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use threshold_crypto::serde_impl::SerdeSecret;
+use threshold_crypto::{Ciphertext, DecryptionShare, PublicKeySet, SecretKey, SecretKeyShare};
+
+use crate::commands::acme::write_private;
+use crate::commands::mpc::{self, KeyShares};
+use crate::commands::{audit, invalid_data, keystore};
+use crate::config::Config;
+
+pub const BACKUP_FILE: &str = "privkey.backup.json";
+
+// The certificate key encrypted to the MPC key set; any t+1 participants can
+// decrypt it together, and no fewer
+#[derive(Serialize, Deserialize)]
+struct KeyBackup {
+    // Hex threshold_crypto public key of the set, to catch a mismatched share file
+    public_key: String,
+    // Number of participants needed to decrypt
+    participants_needed: usize,
+    // Hex bincode-serialized threshold_crypto ciphertext of the PKCS#8 key
+    ciphertext: String,
+    created: String,
+}
+
+fn decode<T: serde::de::DeserializeOwned>(hex_encoded: &str, what: &str) -> io::Result<T> {
+    let bytes = hex::decode(hex_encoded.trim())
+        .map_err(|e| invalid_data(format!("Invalid {}: {}", what, e)))?;
+    bincode::deserialize(&bytes).map_err(|e| invalid_data(format!("Invalid {}: {}", what, e)))
+}
+
+fn shares_path(shares: Option<PathBuf>) -> PathBuf {
+    shares.unwrap_or_else(|| PathBuf::from(mpc::KEY_SHARES_FILE))
+}
+
+// Loads the backup and checks it was made for the key set in `key_shares`
+fn load_backup(path: &Path, key_shares: &KeyShares) -> io::Result<Ciphertext> {
+    let backup: KeyBackup = serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| invalid_data(format!("Invalid backup {}: {}", path.display(), e)))?;
+    let public_key = hex::encode(key_shares.public_key_set.public_key().to_bytes());
+    if backup.public_key != public_key {
+        return Err(invalid_data(format!(
+            "{} was encrypted to a different key set than the share file",
+            path.display()
+        )));
+    }
+    let ciphertext: Ciphertext = decode(&backup.ciphertext, "backup ciphertext")?;
+    if !ciphertext.verify() {
+        return Err(invalid_data(format!(
+            "{} has been tampered with",
+            path.display()
+        )));
+    }
+    Ok(ciphertext)
+}
+
+// Encrypts the PKCS#8 key to the key set's public key
+fn seal(public_key_set: &PublicKeySet, pkcs8: Vec<u8>) -> io::Result<KeyBackup> {
+    let ciphertext = public_key_set.public_key().encrypt(pkcs8);
+    Ok(KeyBackup {
+        public_key: hex::encode(public_key_set.public_key().to_bytes()),
+        participants_needed: public_key_set.threshold() + 1,
+        ciphertext: hex::encode(bincode::serialize(&ciphertext).map_err(io::Error::other)?),
+        created: Utc::now().to_rfc3339(),
+    })
+}
+
+// `defe certs backup`: encrypts the workspace's certificate key to the MPC
+// key set's public key
+pub fn backup(shares: Option<PathBuf>, output: Option<PathBuf>) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let key_shares = mpc::load_key_shares(&shares_path(shares))?;
+    let output = output.unwrap_or_else(|| dir.join(BACKUP_FILE));
+
+    let key_path = keystore::key_path(&dir);
+    let pkcs8 = keystore::to_pkcs8(&keystore::load_key(&key_path)?)?;
+    let backup = seal(&key_shares.public_key_set, pkcs8)?;
+    fs::write(&output, serde_json::to_vec_pretty(&backup)?)?;
+    audit::record(
+        &dir,
+        "key-backed-up",
+        json!({
+            "key": key_path.display().to_string(),
+            "backup": output.display().to_string(),
+            "public_key": backup.public_key,
+            "participants_needed": backup.participants_needed,
+        }),
+    )?;
+    println!(
+        "Encrypted {} to {}; {} participants are needed to restore it",
+        key_path.display(),
+        output.display(),
+        backup.participants_needed
+    );
+    Ok(())
+}
+
+// `defe certs decryption-share`: run by participant `index` to produce their
+// decryption share of a backup, using the secret key whose public key they
+// gave during Shamir secret sharing
+pub fn decryption_share(
+    backup_path: PathBuf,
+    shares: Option<PathBuf>,
+    index: usize,
+) -> io::Result<()> {
+    let key_shares = mpc::load_key_shares(&shares_path(shares))?;
+    let ciphertext = load_backup(&backup_path, &key_shares)?;
+    let encrypted_key_share = key_shares.encrypted_key_shares.get(index).ok_or_else(|| {
+        invalid_data(format!(
+            "The share file has no key share for participant {}; shares made before threshold backups need to be dealt again",
+            index
+        ))
+    })?;
+
+    let secret_key = Password::new()
+        .with_prompt("Enter your secret key (hex-encoded)")
+        .interact()
+        .map_err(io::Error::other)?;
+    let secret_key = decode::<SerdeSecret<SecretKey>>(&secret_key, "secret key")?.into_inner();
+    let encrypted_key_share: Ciphertext = bincode::deserialize(encrypted_key_share)
+        .map_err(|e| invalid_data(format!("Invalid key share: {}", e)))?;
+    let key_share = secret_key.decrypt(&encrypted_key_share).ok_or_else(|| {
+        invalid_data(format!(
+            "Could not decrypt key share {}; is it yours?",
+            index
+        ))
+    })?;
+    let key_share = bincode::deserialize::<SerdeSecret<SecretKeyShare>>(&key_share)
+        .map_err(|e| invalid_data(format!("Invalid key share: {}", e)))?
+        .into_inner();
+
+    let share = key_share
+        .decrypt_share(&ciphertext)
+        .ok_or_else(|| invalid_data("The backup ciphertext is invalid".to_string()))?;
+    let share = bincode::serialize(&share).map_err(io::Error::other)?;
+    println!("Decryption share of participant {}:", index);
+    println!("{}:{}", index, hex::encode(share));
+    Ok(())
+}
+
+// `defe certs restore`: collects decryption shares until enough valid ones
+// are in, then puts the decrypted key back into the workspace key store
+pub fn restore(backup_path: PathBuf, shares: Option<PathBuf>, force: bool) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let config = Config::load()?;
+    let store_path = dir.join(keystore::STORE_FILE);
+    if store_path.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists; pass --force to replace it",
+                store_path.display()
+            ),
+        ));
+    }
+
+    let key_shares = mpc::load_key_shares(&shares_path(shares))?;
+    let ciphertext = load_backup(&backup_path, &key_shares)?;
+    let public_key_set = &key_shares.public_key_set;
+    let needed = public_key_set.threshold() + 1;
+
+    let mut collected: BTreeMap<usize, DecryptionShare> = BTreeMap::new();
+    while collected.len() < needed {
+        let entry = Input::<String>::new()
+            .with_prompt(format!(
+                "Enter decryption share {} of {} (index:hex)",
+                collected.len() + 1,
+                needed
+            ))
+            .interact_text()
+            .map_err(io::Error::other)?;
+        let Some((index, share)) = entry.trim().split_once(':') else {
+            println!("Expected the participant index, a colon and the hex share");
+            continue;
+        };
+        let Ok(index) = index.parse::<usize>() else {
+            println!("Invalid participant index {}", index);
+            continue;
+        };
+        let share: DecryptionShare = match decode(share, "decryption share") {
+            Ok(share) => share,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        // A bad share would make the combined result garbage
+        if !public_key_set
+            .public_key_share(index)
+            .verify_decryption_share(&share, &ciphertext)
+        {
+            println!(
+                "That is not a valid decryption share of participant {}",
+                index
+            );
+            continue;
+        }
+        collected.insert(index, share);
+    }
+
+    let pkcs8 = public_key_set
+        .decrypt(&collected, &ciphertext)
+        .map_err(|e| invalid_data(format!("Could not combine the decryption shares: {}", e)))?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.clone()));
+    let signing_key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)
+        .map_err(|e| invalid_data(format!("The restored key is unusable: {}", e)))?;
+
+    // Warn rather than fail: the certificate may have been renewed since
+    let chain_path = dir.join("fullchain.pem");
+    if let Ok(pem) = fs::read(&chain_path) {
+        let chain: Vec<CertificateDer<'static>> =
+            rustls_pemfile::certs(&mut pem.as_slice()).collect::<io::Result<Vec<_>>>()?;
+        if CertifiedKey::new(chain, signing_key).keys_match().is_err() {
+            eprintln!(
+                "Warning: the restored key does not match {}",
+                chain_path.display()
+            );
+        }
+    }
+
+    let stored_key = keystore::encrypt(&pkcs8, config.acme.key_store)?;
+    write_private(&store_path, &stored_key)?;
+    audit::record(
+        &dir,
+        "key-restored",
+        json!({
+            "backup": backup_path.display().to_string(),
+            "participants": collected.keys().collect::<Vec<_>>(),
+            "store": store_path.display().to_string(),
+        }),
+    )?;
+    println!("Restored the key into {}", store_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use threshold_crypto::SecretKeySet;
+
+    #[test]
+    fn restores_from_enough_valid_shares() {
+        let threshold = 2;
+        let secret_key_set = SecretKeySet::random(threshold, &mut rand::thread_rng());
+        let public_key_set = secret_key_set.public_keys();
+        let key_shares: KeyShares = serde_json::from_value(json!({
+            "public_key_set": public_key_set,
+            "encrypted_shares": [],
+        }))
+        .unwrap();
+
+        let path = env::temp_dir().join(format!("defe-backup-{}.json", std::process::id()));
+        let pkcs8 = b"not really a PKCS#8 key".to_vec();
+        let backup = seal(&public_key_set, pkcs8.clone()).unwrap();
+        assert_eq!(backup.participants_needed, threshold + 1);
+        fs::write(&path, serde_json::to_vec(&backup).unwrap()).unwrap();
+        let ciphertext = load_backup(&path, &key_shares);
+        fs::remove_file(&path).unwrap();
+        let ciphertext = ciphertext.unwrap();
+
+        let shares: BTreeMap<usize, DecryptionShare> = (0..=threshold)
+            .map(|index| {
+                let share = secret_key_set
+                    .secret_key_share(index)
+                    .decrypt_share(&ciphertext)
+                    .unwrap();
+                (index, share)
+            })
+            .collect();
+        for (&index, share) in &shares {
+            assert!(public_key_set
+                .public_key_share(index)
+                .verify_decryption_share(share, &ciphertext));
+            // Shares only verify for the participant who made them
+            assert!(!public_key_set
+                .public_key_share(index + 1)
+                .verify_decryption_share(share, &ciphertext));
+        }
+        assert_eq!(public_key_set.decrypt(&shares, &ciphertext).unwrap(), pkcs8);
+
+        // t shares are not enough
+        let mut too_few = shares;
+        too_few.remove(&0);
+        assert!(public_key_set.decrypt(&too_few, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn rejects_backups_for_another_key_set() {
+        let public_key_set = SecretKeySet::random(1, &mut rand::thread_rng()).public_keys();
+        let other = SecretKeySet::random(1, &mut rand::thread_rng()).public_keys();
+        let key_shares: KeyShares = serde_json::from_value(json!({
+            "public_key_set": other,
+            "encrypted_shares": [],
+        }))
+        .unwrap();
+
+        let path = env::temp_dir().join(format!("defe-backup-other-{}.json", std::process::id()));
+        let backup = seal(&public_key_set, b"key".to_vec()).unwrap();
+        fs::write(&path, serde_json::to_vec(&backup).unwrap()).unwrap();
+        let result = load_backup(&path, &key_shares);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::DateTime;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::{invalid_data, keystore};
+use crate::config::{Config, Hook};
+
+use super::server;
+
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// What a hook is told about the certificate being issued or renewed
+pub struct HookContext<'a> {
+    // "issue" or "renew"
+    pub event: &'static str,
+    pub dir: &'a Path,
+    pub domains: &'a [String],
+}
+
+// What post hooks learn from the installed certificate
+struct Installed {
+    spki_sha256: String,
+    not_after: String,
+}
+
+impl Installed {
+    fn load(path: &Path) -> io::Result<Self> {
+        let pem = fs::read(path)?;
+        let leaf = rustls_pemfile::certs(&mut pem.as_slice())
+            .next()
+            .ok_or_else(|| invalid_data(format!("No certificate found in {}", path.display())))??;
+        let (_, certificate) = X509Certificate::from_der(leaf.as_ref())
+            .map_err(|e| invalid_data(format!("Invalid certificate {}: {}", path.display(), e)))?;
+        Ok(Self {
+            spki_sha256: hex::encode(Sha256::digest(certificate.public_key().raw)),
+            not_after: DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+async fn run_command(
+    command: &str,
+    timeout_secs: u64,
+    stage: &str,
+    context: &HookContext<'_>,
+    installed: Option<&Installed>,
+) -> Result<(), String> {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .current_dir(context.dir)
+        .env("DEFE_HOOK_STAGE", stage)
+        .env("DEFE_EVENT", context.event)
+        .env("DEFE_DOMAINS", context.domains.join(" "))
+        .env("DEFE_WORKSPACE", context.dir)
+        .env("DEFE_CERT_PATH", context.dir.join("fullchain.pem"))
+        .env("DEFE_KEY_PATH", keystore::key_path(context.dir))
+        .kill_on_drop(true);
+    if let Some(installed) = installed {
+        process
+            .env("DEFE_SPKI_SHA256", &installed.spki_sha256)
+            .env("DEFE_NOT_AFTER", &installed.not_after);
+    }
+
+    let status = timeout(Duration::from_secs(timeout_secs), process.status())
+        .await
+        .map_err(|_| format!("timed out after {}s", timeout_secs))?
+        .map_err(|e| format!("failed to start: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("exited with {}", status))
+    }
+}
+
+fn reload_server(config: &Config, dir: &Path) -> Result<(), String> {
+    let Some(pid_file) = &config.tls.pid_file else {
+        return Err(
+            "no tls.pid_file configured; restart the TLS server to use the new certificate"
+                .to_string(),
+        );
+    };
+    let Some(pid) = server::read_pid(dir, pid_file).map_err(|e| e.to_string())? else {
+        println!("The TLS server is not running; it will use the new certificate when started.");
+        return Ok(());
+    };
+    match server::signal(pid, server::Request::Reload) {
+        Ok(true) => {
+            println!("Asked the TLS server (PID {}) to reload", pid);
+            Ok(())
+        }
+        Ok(false) => Err(format!("could not signal the TLS server (PID {})", pid)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn publish_fingerprint(
+    url: &str,
+    context: &HookContext<'_>,
+    installed: &Installed,
+) -> Result<(), String> {
+    let body = json!({
+        "event": context.event,
+        "domains": context.domains,
+        "spki_sha256": installed.spki_sha256,
+        "not_after": installed.not_after,
+    });
+    reqwest::Client::new()
+        .post(url)
+        .timeout(PUBLISH_TIMEOUT)
+        .json(&body)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| e.to_string())?;
+    println!("Published the certificate fingerprint to {}", url);
+    Ok(())
+}
+
+fn describe(hook: &Hook) -> String {
+    match hook {
+        Hook::Command { command, .. } => format!("command `{}`", command),
+        Hook::ReloadServer => "reload-server".to_string(),
+        Hook::PublishFingerprint { url } => format!("publish-fingerprint to {}", url),
+    }
+}
+
+// Runs the pre-issuance hooks in order, stopping at the first failure, which
+// cancels the issuance
+pub async fn run_pre_hooks(config: &Config, context: &HookContext<'_>) -> io::Result<()> {
+    for hook in &config.acme.hooks.pre {
+        let result = match hook {
+            Hook::Command {
+                command,
+                timeout_secs,
+            } => run_command(command, *timeout_secs, "pre", context, None).await,
+            Hook::ReloadServer => reload_server(config, context.dir),
+            Hook::PublishFingerprint { .. } => {
+                Err("only runs after issuance; move it to acme.hooks.post".to_string())
+            }
+        };
+        result.map_err(|e| io::Error::other(format!("Pre hook {} {}", describe(hook), e)))?;
+    }
+    Ok(())
+}
+
+// Runs every post-issuance hook, once the new certificate is in place.
+// Returns what failed; the certificate is kept either way.
+pub async fn run_post_hooks(config: &Config, context: &HookContext<'_>) -> Vec<String> {
+    let installed = match Installed::load(&context.dir.join("fullchain.pem")) {
+        Ok(installed) => installed,
+        Err(e) => return vec![format!("Post hooks skipped: {}", e)],
+    };
+    let mut failures = Vec::new();
+    for hook in &config.acme.hooks.post {
+        let result = match hook {
+            Hook::Command {
+                command,
+                timeout_secs,
+            } => run_command(command, *timeout_secs, "post", context, Some(&installed)).await,
+            Hook::ReloadServer => reload_server(config, context.dir),
+            Hook::PublishFingerprint { url } => publish_fingerprint(url, context, &installed).await,
+        };
+        if let Err(e) = result {
+            failures.push(format!("Post hook {} {}", describe(hook), e));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A workspace holding a certificate, removed on drop
+    struct Workspace(PathBuf);
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn workspace(name: &str) -> (Workspace, String) {
+        let dir = std::env::temp_dir().join(format!("defe-hooks-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = rcgen::CertificateParams::new(vec!["example.com".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        fs::write(dir.join("fullchain.pem"), certificate.pem()).unwrap();
+        let spki_sha256 = hex::encode(Sha256::digest(key.public_key_der()));
+        (Workspace(dir), spki_sha256)
+    }
+
+    fn command(command: &str, timeout_secs: u64) -> Hook {
+        Hook::Command {
+            command: command.to_string(),
+            timeout_secs,
+        }
+    }
+
+    fn config(pre: Vec<Hook>, post: Vec<Hook>) -> Config {
+        let mut config = Config::default();
+        config.acme.hooks.pre = pre;
+        config.acme.hooks.post = post;
+        config
+    }
+
+    #[tokio::test]
+    async fn commands_see_the_certificate() {
+        let (workspace, spki_sha256) = workspace("env");
+        let dir = &workspace.0;
+        let domains = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let context = HookContext {
+            event: "renew",
+            dir,
+            domains: &domains,
+        };
+        let config = config(
+            Vec::new(),
+            vec![command(
+                r#"printf '%s\n' "$DEFE_HOOK_STAGE" "$DEFE_EVENT" "$DEFE_DOMAINS" "$DEFE_CERT_PATH" "$DEFE_SPKI_SHA256" > env.txt"#,
+                10,
+            )],
+        );
+
+        assert!(run_post_hooks(&config, &context).await.is_empty());
+        let env = fs::read_to_string(dir.join("env.txt")).unwrap();
+        let cert_path = dir.join("fullchain.pem");
+        assert_eq!(
+            env.lines().collect::<Vec<_>>(),
+            [
+                "post",
+                "renew",
+                "example.com www.example.com",
+                cert_path.to_str().unwrap(),
+                spki_sha256.as_str(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_failures_and_timeouts() {
+        let (workspace, _) = workspace("failures");
+        let context = HookContext {
+            event: "issue",
+            dir: &workspace.0,
+            domains: &[],
+        };
+        let error = run_command("exit 3", 10, "pre", &context, None)
+            .await
+            .unwrap_err();
+        assert!(error.contains("exited with"), "{}", error);
+        assert!(error.contains('3'), "{}", error);
+        let error = run_command("sleep 5", 1, "pre", &context, None)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "timed out after 1s");
+    }
+
+    #[tokio::test]
+    async fn failing_pre_hooks_cancel_the_issuance() {
+        let (workspace, _) = workspace("pre");
+        let dir = &workspace.0;
+        let context = HookContext {
+            event: "issue",
+            dir,
+            domains: &[],
+        };
+        let config = config(
+            vec![command("exit 1", 10), command("touch ran", 10)],
+            Vec::new(),
+        );
+        assert!(run_pre_hooks(&config, &context).await.is_err());
+        assert!(!dir.join("ran").exists());
+    }
+
+    #[tokio::test]
+    async fn post_hooks_all_run() {
+        let (workspace, _) = workspace("post");
+        let dir = &workspace.0;
+        let context = HookContext {
+            event: "issue",
+            dir,
+            domains: &[],
+        };
+        let config = config(
+            Vec::new(),
+            vec![
+                command("exit 1", 10),
+                command("touch ran", 10),
+                command("exit 2", 10),
+            ],
+        );
+        let failures = run_post_hooks(&config, &context).await;
+        assert_eq!(failures.len(), 2, "{:?}", failures);
+        assert!(failures[0].starts_with("Post hook command `exit 1`"));
+        assert!(dir.join("ran").exists());
+    }
+}
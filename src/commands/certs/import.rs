@@ -0,0 +1,109 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rustls::sign::CertifiedKey;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::{certbot, invalid_data, keystore};
+use crate::config::Config;
+
+use super::{dns_names, server};
+
+// Checks the lineage's chain is current, names `domain` and belongs to the key
+fn verify(
+    chain_path: &Path,
+    chain_pem: &[u8],
+    key_path: &Path,
+    key_pem: &[u8],
+    domain: &str,
+) -> io::Result<()> {
+    let chain = rustls_pemfile::certs(&mut &chain_pem[..]).collect::<io::Result<Vec<_>>>()?;
+    let leaf = chain.first().ok_or_else(|| {
+        invalid_data(format!("No certificates found in {}", chain_path.display()))
+    })?;
+    let (_, certificate) = X509Certificate::from_der(leaf.as_ref()).map_err(|e| {
+        invalid_data(format!(
+            "Invalid certificate {}: {}",
+            chain_path.display(),
+            e
+        ))
+    })?;
+
+    let not_after = DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0);
+    if not_after.is_none_or(|not_after| not_after <= Utc::now()) {
+        return Err(invalid_data(format!(
+            "{} has expired",
+            chain_path.display()
+        )));
+    }
+    let names = dns_names(&certificate);
+    if !names.iter().any(|name| name.eq_ignore_ascii_case(domain)) {
+        return Err(invalid_data(format!(
+            "{} covers {}, not {}",
+            chain_path.display(),
+            names.join(", "),
+            domain
+        )));
+    }
+
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])?
+        .ok_or_else(|| invalid_data(format!("No private key found in {}", key_path.display())))?;
+    let signing_key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key).map_err(|e| {
+        invalid_data(format!(
+            "Unusable private key {}: {}",
+            key_path.display(),
+            e
+        ))
+    })?;
+    CertifiedKey::new(chain, signing_key)
+        .keys_match()
+        .map_err(|_| {
+            invalid_data(format!(
+                "{} does not match {}",
+                key_path.display(),
+                chain_path.display()
+            ))
+        })
+}
+
+// `defe certs import`: copies the certificate certbot obtained for `domain`
+// from its live directory into the workspace, where the TLS server looks.
+// Symlinks are resolved, the files verified, and the key moved into the key
+// store, which only the owner can read.
+pub fn import(domain: String, certbot_dir: Option<PathBuf>) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let config = Config::load()?;
+    let certbot_dir = certbot_dir.unwrap_or_else(|| PathBuf::from(&config.acme.certbot_dir));
+
+    let lineage = certbot::find_lineage(&certbot_dir, &domain)?;
+    let (chain_path, chain_pem) = certbot::read_lineage_file(&lineage, "fullchain.pem")?;
+    let (key_path, key_pem) = certbot::read_lineage_file(&lineage, "privkey.pem")?;
+    println!("Certificate: {}", chain_path.display());
+    println!("Key:         {}", key_path.display());
+    verify(&chain_path, &chain_pem, &key_path, &key_pem, &domain)?;
+
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())?
+        .ok_or_else(|| invalid_data(format!("No private key found in {}", key_path.display())))?;
+    let stored_key = keystore::encrypt(&keystore::to_pkcs8(&key)?, config.acme.key_store)?;
+    let chain = String::from_utf8(chain_pem).map_err(|e| invalid_data(e.to_string()))?;
+    certbot::install_certificate(&dir, &chain, &stored_key)?;
+    println!(
+        "Copied the certificate to {} and the key to {}",
+        dir.join("fullchain.pem").display(),
+        dir.join(keystore::STORE_FILE).display()
+    );
+
+    // A running server picks the new pair up on SIGHUP
+    let running = match &config.tls.pid_file {
+        Some(pid_file) => server::read_pid(&dir, pid_file)?,
+        None => None,
+    };
+    if let Some(pid) = running {
+        if server::signal(pid, server::Request::Reload)? {
+            println!("Asked the TLS server (PID {}) to reload", pid);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,339 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::sign::CertifiedKey;
+use rustls::{CertificateError, RootCertStore};
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::{invalid_data, keystore};
+
+use super::dns_names;
+
+const OID_RSA: &str = "1.2.840.113549.1.1.1";
+const OID_EC: &str = "1.2.840.10045.2.1";
+const OID_ED25519: &str = "1.3.101.112";
+const OID_P256: &str = "1.2.840.10045.3.1.7";
+const OID_P384: &str = "1.3.132.0.34";
+
+fn load_chain(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let pem = fs::read(path)?;
+    let chain = rustls_pemfile::certs(&mut pem.as_slice()).collect::<io::Result<Vec<_>>>()?;
+    if chain.is_empty() {
+        return Err(invalid_data(format!(
+            "No certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(chain)
+}
+
+fn parse<'a>(der: &'a CertificateDer<'_>) -> io::Result<X509Certificate<'a>> {
+    X509Certificate::from_der(der.as_ref())
+        .map(|(_, certificate)| certificate)
+        .map_err(|e| invalid_data(format!("Invalid certificate: {}", e)))
+}
+
+fn key_type(certificate: &X509Certificate<'_>) -> String {
+    let spki = certificate.public_key();
+    match spki.algorithm.algorithm.to_id_string().as_str() {
+        OID_RSA => match spki.parsed() {
+            Ok(key) => format!("RSA {}", key.key_size()),
+            Err(_) => "RSA".to_string(),
+        },
+        OID_EC => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|parameters| parameters.as_oid().ok())
+                .map(|oid| oid.to_id_string());
+            match curve.as_deref() {
+                Some(OID_P256) => "ECDSA P-256".to_string(),
+                Some(OID_P384) => "ECDSA P-384".to_string(),
+                Some(curve) => format!("ECDSA ({})", curve),
+                None => "ECDSA".to_string(),
+            }
+        }
+        OID_ED25519 => "Ed25519".to_string(),
+        oid => format!("Unknown ({})", oid),
+    }
+}
+
+fn is_ca(certificate: &X509Certificate<'_>) -> bool {
+    matches!(certificate.basic_constraints(), Ok(Some(constraints)) if constraints.value.ca)
+}
+
+fn print_certificate(index: usize, certificate: &X509Certificate<'_>) {
+    let not_before = DateTime::from_timestamp(certificate.validity().not_before.timestamp(), 0);
+    let not_after = DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0);
+
+    println!("[{}] Subject: {}", index, certificate.subject());
+    println!("    Issuer:  {}", certificate.issuer());
+    let names = dns_names(certificate);
+    if !names.is_empty() {
+        println!("    SANs:    {}", names.join(", "));
+    }
+    if let (Some(not_before), Some(not_after)) = (not_before, not_after) {
+        let remaining = (not_after - Utc::now()).num_days();
+        println!(
+            "    Valid:   {} to {} ({})",
+            not_before,
+            not_after,
+            if not_after < Utc::now() {
+                "expired".to_string()
+            } else {
+                format!("{} day(s) remaining", remaining)
+            }
+        );
+    }
+    println!("    Key:     {}", key_type(certificate));
+    // The same fingerprint /.well-known/defe.json reports
+    println!(
+        "    SPKI SHA-256: {}",
+        hex::encode(Sha256::digest(certificate.public_key().raw))
+    );
+}
+
+// Problems with the order of the chain: each certificate should be followed
+// by its issuer, starting from the leaf
+fn order_warnings(certificates: &[X509Certificate<'_>]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if is_ca(&certificates[0]) {
+        warnings.push("The first certificate is a CA certificate, not the server's".to_string());
+    }
+    for (index, pair) in certificates.windows(2).enumerate() {
+        if pair[0].issuer().as_raw() == pair[1].subject().as_raw() {
+            continue;
+        }
+        let issuer_elsewhere = certificates
+            .iter()
+            .any(|certificate| certificate.subject().as_raw() == pair[0].issuer().as_raw());
+        warnings.push(if issuer_elsewhere {
+            format!(
+                "Certificate [{}] is not followed by its issuer; the chain is out of order",
+                index
+            )
+        } else {
+            format!(
+                "The issuer of certificate [{}] ({}) is missing from the chain",
+                index,
+                pair[0].issuer()
+            )
+        });
+    }
+    if let Some(last) = certificates.last() {
+        let self_signed = last.subject().as_raw() == last.issuer().as_raw();
+        if self_signed && certificates.len() > 1 {
+            warnings
+                .push("The chain includes its self-signed root, which clients ignore".to_string());
+        }
+        // A chain ending in a leaf, usually the leaf alone, lacks the
+        // intermediate clients need to reach a root
+        let issuer_present = certificates
+            .iter()
+            .any(|certificate| certificate.subject().as_raw() == last.issuer().as_raw());
+        if !self_signed && !is_ca(last) && !issuer_present {
+            warnings.push(format!(
+                "The chain ends with a server certificate; its intermediate ({}) is missing",
+                last.issuer()
+            ));
+        }
+    }
+    warnings
+}
+
+// Checks the chain against the Mozilla root program, plus any roots in
+// `extra_roots`, the way a client connecting to its first SAN would
+fn verify_chain(
+    chain: &[CertificateDer<'static>],
+    leaf: &X509Certificate<'_>,
+    extra_roots: Option<&Path>,
+) -> io::Result<Result<(), String>> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(path) = extra_roots {
+        let (_, ignored) = roots.add_parsable_certificates(load_chain(path)?);
+        if ignored > 0 {
+            eprintln!(
+                "Warning: ignored {} unusable root(s) in {}",
+                ignored,
+                path.display()
+            );
+        }
+    }
+
+    let Some(name) = dns_names(leaf).into_iter().next() else {
+        return Ok(Err("The leaf certificate names no domain".to_string()));
+    };
+    // Any name under a wildcard will do
+    let name = match name.strip_prefix("*.") {
+        Some(base) => format!("www.{}", base),
+        None => name,
+    };
+    let server_name = ServerName::try_from(name.clone())
+        .map_err(|e| invalid_data(format!("Invalid domain {}: {}", name, e)))?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|e| invalid_data(e.to_string()))?;
+    Ok(
+        match verifier.verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &[],
+            UnixTime::now(),
+        ) {
+            Ok(_) => Ok(()),
+            Err(rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer)) => Err(
+                "The chain does not lead to a trusted root; an intermediate may be missing"
+                    .to_string(),
+            ),
+            Err(e) => Err(format!("The chain does not verify: {}", e)),
+        },
+    )
+}
+
+// `defe certs inspect`: describes the certificate chain and checks that it
+// will work before the TLS server is pointed at it. Mismatched keys and
+// untrusted chains are errors; ordering problems are warnings.
+pub fn inspect(
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+    extra_roots: Option<PathBuf>,
+) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let cert_path = cert_path.unwrap_or_else(|| dir.join("fullchain.pem"));
+    let key_path = key_path.unwrap_or_else(|| keystore::key_path(&dir));
+
+    let chain = load_chain(&cert_path)?;
+    let certificates = chain.iter().map(parse).collect::<io::Result<Vec<_>>>()?;
+    println!(
+        "Certificate chain {} ({} certificate(s))",
+        cert_path.display(),
+        chain.len()
+    );
+    for (index, certificate) in certificates.iter().enumerate() {
+        print_certificate(index, certificate);
+    }
+    println!();
+
+    let mut problems = Vec::new();
+    for warning in order_warnings(&certificates) {
+        println!("Warning: {}", warning);
+    }
+
+    match verify_chain(&chain, &certificates[0], extra_roots.as_deref())? {
+        Ok(()) => println!("Chain verifies to a trusted root"),
+        Err(problem) => problems.push(problem),
+    }
+
+    let key_matches = keystore::load_key(&key_path).and_then(|key| {
+        let signing_key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)
+            .map_err(|e| invalid_data(format!("Unusable private key: {}", e)))?;
+        CertifiedKey::new(chain.clone(), signing_key)
+            .keys_match()
+            .map_err(|_| invalid_data("does not match the leaf certificate".to_string()))
+    });
+    match key_matches {
+        Ok(()) => println!(
+            "Private key {} matches the leaf certificate",
+            key_path.display()
+        ),
+        Err(e) => problems.push(format!("Private key {}: {}", key_path.display(), e)),
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("Error: {}", problem);
+    }
+    Err(invalid_data(format!(
+        "{} problem(s) found in {}",
+        problems.len(),
+        cert_path.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, PKCS_ED25519};
+
+    // Root, intermediate and leaf DER certificates, leaf first
+    fn chain() -> Vec<CertificateDer<'static>> {
+        let ca = |name: &str| {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+        };
+        let root_key = KeyPair::generate().unwrap();
+        let root = ca("Test Root").self_signed(&root_key).unwrap();
+        let intermediate_key = KeyPair::generate().unwrap();
+        let intermediate = ca("Test Intermediate")
+            .signed_by(&intermediate_key, &root, &root_key)
+            .unwrap();
+        let leaf_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let leaf = CertificateParams::new(vec![
+            "example.com".to_string(),
+            "www.example.com".to_string(),
+        ])
+        .unwrap()
+        .signed_by(&leaf_key, &intermediate, &intermediate_key)
+        .unwrap();
+        vec![
+            leaf.der().clone(),
+            intermediate.der().clone(),
+            root.der().clone(),
+        ]
+    }
+
+    #[test]
+    fn describes_certificates() {
+        let chain = chain();
+        let leaf = parse(&chain[0]).unwrap();
+        let intermediate = parse(&chain[1]).unwrap();
+        assert_eq!(dns_names(&leaf), ["example.com", "www.example.com"]);
+        assert_eq!(key_type(&leaf), "Ed25519");
+        assert_eq!(key_type(&intermediate), "ECDSA P-256");
+        assert!(!is_ca(&leaf));
+        assert!(is_ca(&intermediate));
+        assert!(parse(&CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x00])).is_err());
+    }
+
+    #[test]
+    fn warns_about_chain_order() {
+        let chain = chain();
+        let parsed: Vec<X509Certificate<'_>> =
+            chain.iter().map(|der| parse(der).unwrap()).collect();
+        let warnings = |indexes: &[usize]| {
+            order_warnings(
+                &indexes
+                    .iter()
+                    .map(|&i| parsed[i].clone())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert!(warnings(&[0, 1]).is_empty());
+        assert_eq!(warnings(&[0, 1, 2]).len(), 1);
+        assert!(warnings(&[0, 1, 2])[0].contains("self-signed root"));
+        assert!(warnings(&[0, 2, 1])[0].contains("out of order"));
+        assert!(warnings(&[0])[0].contains("intermediate (CN=Test Intermediate) is missing"));
+        assert_eq!(warnings(&[0]).len(), 1);
+        assert!(warnings(&[0, 2])[0].contains("missing from the chain"));
+        assert!(warnings(&[1, 2])[0].contains("CA certificate"));
+    }
+}
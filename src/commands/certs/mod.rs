@@ -0,0 +1,33 @@
+mod backup;
+mod hooks;
+mod import;
+mod inspect;
+mod renew;
+mod revoke;
+mod server;
+
+pub use backup::{backup, decryption_share, restore};
+pub use hooks::{run_post_hooks, run_pre_hooks, HookContext};
+pub use import::import;
+pub use inspect::inspect;
+pub use renew::renew;
+pub use revoke::{revoke, RevocationReason};
+
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::X509Certificate;
+
+// The DNS names in the certificate's subject alternative names
+fn dns_names(certificate: &X509Certificate<'_>) -> Vec<String> {
+    match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,252 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rand::{thread_rng, Rng};
+use tokio::time::{sleep, Duration};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::{acme, certbot, invalid_data, keystore};
+use crate::config::{Config, RenewalSettings};
+
+use super::dns_names;
+use super::hooks::{run_post_hooks, run_pre_hooks, HookContext};
+
+// What renewal needs to know about the certificate being served
+struct Current {
+    domains: Vec<String>,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+}
+
+impl Current {
+    fn load(path: &Path) -> io::Result<Self> {
+        let pem = fs::read(path)?;
+        let leaf = rustls_pemfile::certs(&mut pem.as_slice())
+            .next()
+            .ok_or_else(|| invalid_data(format!("No certificate found in {}", path.display())))??;
+        let (_, certificate) = X509Certificate::from_der(leaf.as_ref())
+            .map_err(|e| invalid_data(format!("Invalid certificate {}: {}", path.display(), e)))?;
+
+        let time = |timestamp: i64| {
+            DateTime::from_timestamp(timestamp, 0)
+                .ok_or_else(|| invalid_data(format!("Invalid validity in {}", path.display())))
+        };
+        let domains = dns_names(&certificate);
+        if domains.is_empty() {
+            return Err(invalid_data(format!(
+                "{} names no domains to renew",
+                path.display()
+            )));
+        }
+
+        Ok(Self {
+            domains,
+            not_before: time(certificate.validity().not_before.timestamp())?,
+            not_after: time(certificate.validity().not_after.timestamp())?,
+        })
+    }
+
+    // Fails when the certificate would be due from the moment it was issued,
+    // e.g. `before_days` of 90 for a 90-day certificate
+    fn renew_at(&self, settings: &RenewalSettings) -> io::Result<DateTime<Utc>> {
+        let renew_at = match settings.before_days {
+            Some(days) => self.not_after - chrono::Duration::days(days as i64),
+            None => {
+                let lifetime = (self.not_after - self.not_before).num_seconds() as f64;
+                let elapsed = lifetime * settings.lifetime_fraction.clamp(0.0, 1.0);
+                self.not_before + chrono::Duration::seconds(elapsed as i64)
+            }
+        };
+        if renew_at <= self.not_before {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "acme.renewal makes the certificate for {} due as soon as it is issued; its lifetime is {} day(s)",
+                    self.domains.join(", "),
+                    (self.not_after - self.not_before).num_days()
+                ),
+            ));
+        }
+        Ok(renew_at)
+    }
+}
+
+// Up to a tenth shorter, so servers started together don't renew in lockstep
+fn jittered(wait: Duration) -> Duration {
+    wait.mul_f64(thread_rng().gen_range(0.9, 1.0))
+}
+
+fn retry_delay(settings: &RenewalSettings, failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    let delay = settings.retry_secs.max(1).saturating_mul(1u64 << doublings);
+    Duration::from_secs(delay.min(settings.max_retry_secs.max(1)))
+}
+
+// Obtains a certificate with a fresh key for the same domains and swaps it in
+async fn renew_certificate(config: &Config, dir: &Path, current: &Current) -> io::Result<()> {
+    println!(
+        "Renewing the certificate for {}",
+        current.domains.join(", ")
+    );
+    let context = HookContext {
+        event: "renew",
+        dir,
+        domains: &current.domains,
+    };
+    run_pre_hooks(config, &context).await?;
+    let key = acme::generate(config.acme.key_type, &current.domains).map_err(io::Error::other)?;
+    let stored_key = keystore::encrypt(&key.pkcs8, config.acme.key_store)?;
+    let chain = certbot::obtain_certificate(&config.acme, &key.csr, &current.domains, None, dir)
+        .await
+        .map_err(io::Error::other)?;
+    certbot::install_certificate(dir, &chain, &stored_key)?;
+    println!("Certificate renewed");
+    for failure in run_post_hooks(config, &context).await {
+        eprintln!("{}", failure);
+    }
+    Ok(())
+}
+
+// Renews the certificate if it is due. Returns how long until it should be
+// looked at again.
+async fn check(config: &Config, dir: &Path) -> io::Result<Duration> {
+    let cert_path = dir.join("fullchain.pem");
+    let mut current = Current::load(&cert_path)?;
+    let settings = &config.acme.renewal;
+    if Utc::now() >= current.renew_at(settings)? {
+        renew_certificate(config, dir, &current).await?;
+        current = Current::load(&cert_path)?;
+    }
+
+    let renew_at = current.renew_at(settings)?;
+    println!(
+        "Certificate for {} expires {}; renewal due {}",
+        current.domains.join(", "),
+        current.not_after,
+        renew_at
+    );
+    let until_due = (renew_at - Utc::now()).to_std().unwrap_or_default();
+    Ok(until_due
+        .min(Duration::from_secs(settings.check_interval_secs))
+        .max(Duration::from_secs(1)))
+}
+
+// `defe certs renew`: renews the certificate in the current directory if it is
+// due, or with `daemon` keeps doing so, retrying failures with backoff
+pub fn renew(daemon: bool) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let config = Config::load()?;
+    config.acme.renewal.validate()?;
+    if daemon && !keystore::unattended(config.acme.key_store) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The renewal daemon can't prompt for the key passphrase; set {} or use the sealed key store",
+                keystore::PASSPHRASE_ENV
+            ),
+        ));
+    }
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        if !daemon {
+            return check(&config, &dir).await.map(|_| ());
+        }
+
+        let mut failures = 0;
+        loop {
+            let wait = match check(&config, &dir).await {
+                Ok(wait) => {
+                    failures = 0;
+                    wait
+                }
+                Err(e) => {
+                    failures += 1;
+                    eprintln!("Renewal attempt {} failed: {}", failures, e);
+                    retry_delay(&config.acme.renewal, failures)
+                }
+            };
+            let wait = jittered(wait);
+            println!("Next check in {} minute(s)", wait.as_secs() / 60);
+            sleep(wait).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> Current {
+        let not_before = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        Current {
+            domains: vec!["example.com".to_string()],
+            not_before,
+            not_after: not_before + chrono::Duration::days(90),
+        }
+    }
+
+    #[test]
+    fn renews_after_a_fraction_of_the_lifetime() {
+        let current = current();
+        let settings = RenewalSettings::default();
+        assert_eq!(
+            current.renew_at(&settings).unwrap(),
+            current.not_before + chrono::Duration::days(60)
+        );
+    }
+
+    #[test]
+    fn renews_days_before_expiry() {
+        let current = current();
+        let settings = RenewalSettings {
+            before_days: Some(30),
+            ..RenewalSettings::default()
+        };
+        assert_eq!(
+            current.renew_at(&settings).unwrap(),
+            current.not_after - chrono::Duration::days(30)
+        );
+
+        // Never renewing in a loop
+        for before_days in [90, 120] {
+            let settings = RenewalSettings {
+                before_days: Some(before_days),
+                ..RenewalSettings::default()
+            };
+            assert!(current.renew_at(&settings).is_err());
+        }
+        for lifetime_fraction in [0.0, -1.0, 1.0, f64::NAN] {
+            let settings = RenewalSettings {
+                lifetime_fraction,
+                ..RenewalSettings::default()
+            };
+            assert!(settings.validate().is_err(), "{}", lifetime_fraction);
+        }
+        assert!(RenewalSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn backs_off_up_to_the_cap() {
+        let settings = RenewalSettings {
+            retry_secs: 60,
+            max_retry_secs: 3600,
+            ..RenewalSettings::default()
+        };
+        let delays: Vec<u64> = (1..=8)
+            .map(|failures| retry_delay(&settings, failures).as_secs())
+            .collect();
+        assert_eq!(delays, [60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(retry_delay(&settings, u32::MAX).as_secs(), 3600);
+
+        let unset = RenewalSettings {
+            retry_secs: 0,
+            max_retry_secs: 0,
+            ..RenewalSettings::default()
+        };
+        assert_eq!(retry_delay(&unset, 1).as_secs(), 1);
+    }
+}
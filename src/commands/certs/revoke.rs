@@ -0,0 +1,282 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use dialoguer::Confirm;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::sign::CertifiedKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::acme::{self, RevocationKey};
+use crate::commands::{audit, invalid_data, keystore};
+use crate::config::{Config, TlsSettings};
+
+use super::{dns_names, server};
+
+// Where revoked certificates and their keys are moved, relative to the workspace
+const REVOKED_DIR: &str = "revoked";
+
+// RFC 5280 CRLReason codes a subscriber may ask for. RFC 8555 CAs reject the
+// others, which only a CA can assert, with badRevocationReason.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RevocationReason {
+    Unspecified,
+    KeyCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl RevocationReason {
+    pub fn code(self) -> u8 {
+        match self {
+            RevocationReason::Unspecified => 0,
+            RevocationReason::KeyCompromise => 1,
+            RevocationReason::AffiliationChanged => 3,
+            RevocationReason::Superseded => 4,
+            RevocationReason::CessationOfOperation => 5,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RevocationReason::Unspecified => "unspecified",
+            RevocationReason::KeyCompromise => "keyCompromise",
+            RevocationReason::AffiliationChanged => "affiliationChanged",
+            RevocationReason::Superseded => "superseded",
+            RevocationReason::CessationOfOperation => "cessationOfOperation",
+        }
+    }
+}
+
+fn load_chain(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let pem = fs::read(path)?;
+    let chain = rustls_pemfile::certs(&mut pem.as_slice()).collect::<io::Result<Vec<_>>>()?;
+    if chain.is_empty() {
+        return Err(invalid_data(format!(
+            "No certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(chain)
+}
+
+// Loads the certificate key and checks it belongs to the chain, so the CA
+// isn't sent a request it will reject as unauthorized
+fn certificate_key(
+    path: &Path,
+    chain: &[CertificateDer<'static>],
+) -> io::Result<PrivateKeyDer<'static>> {
+    let key = keystore::load_key(path)?;
+    let signing_key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)
+        .map_err(|e| invalid_data(format!("Unusable private key {}: {}", path.display(), e)))?;
+    CertifiedKey::new(chain.to_vec(), signing_key)
+        .keys_match()
+        .map_err(|_| {
+            invalid_data(format!(
+                "Private key {} does not match the certificate",
+                path.display()
+            ))
+        })?;
+    if !matches!(key, PrivateKeyDer::Pkcs8(_)) {
+        return Err(invalid_data(format!(
+            "Private key {} must be PKCS#8 to sign a revocation request",
+            path.display()
+        )));
+    }
+    Ok(key)
+}
+
+// Moves the revoked chain and its key out of the workspace, so neither is
+// served or renewed again. Returns where they went.
+fn retire(dir: &Path, serial: &str) -> io::Result<PathBuf> {
+    let retired = dir.join(REVOKED_DIR).join(serial);
+    fs::create_dir_all(&retired)?;
+    fs::rename(dir.join("fullchain.pem"), retired.join("fullchain.pem"))?;
+    let key_path = keystore::key_path(dir);
+    if let Some(name) = key_path.file_name().filter(|_| key_path.exists()) {
+        fs::rename(&key_path, retired.join(name))?;
+    }
+    Ok(retired)
+}
+
+// A running server keeps the revoked certificate in memory, so it is stopped
+// rather than asked to reload files that are gone
+fn stop_server(settings: &TlsSettings, dir: &Path) -> io::Result<()> {
+    let stop = "Stop the TLS server, which is still serving the revoked certificate from memory.";
+    let Some(pid_file) = &settings.pid_file else {
+        println!("No tls.pid_file configured. {}", stop);
+        return Ok(());
+    };
+    let Some(pid) = server::read_pid(dir, pid_file)? else {
+        return Ok(());
+    };
+    if server::signal(pid, server::Request::Stop)? {
+        println!(
+            "Stopped the TLS server (PID {}). Obtain a new certificate before starting it again.",
+            pid
+        );
+    } else {
+        println!("Could not signal the TLS server (PID {}). {}", pid, stop);
+    }
+    Ok(())
+}
+
+// `defe certs revoke`: revokes the workspace certificate, or the one at
+// `cert_path`, authenticating with the ACME account key or with the
+// certificate key. A revoked workspace certificate is taken out of service.
+pub fn revoke(
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+    use_certificate_key: bool,
+    reason: RevocationReason,
+    assume_yes: bool,
+) -> io::Result<()> {
+    let dir = env::current_dir()?;
+    let config = Config::load()?;
+    let served_path = dir.join("fullchain.pem");
+    let cert_path = cert_path.unwrap_or_else(|| served_path.clone());
+
+    let chain = load_chain(&cert_path)?;
+    let (_, leaf) = X509Certificate::from_der(chain[0].as_ref()).map_err(|e| {
+        invalid_data(format!(
+            "Invalid certificate {}: {}",
+            cert_path.display(),
+            e
+        ))
+    })?;
+    let serial = hex::encode(leaf.raw_serial());
+    let domains = dns_names(&leaf);
+    // The same certificate may be given by another path
+    let served = cert_path == served_path
+        || load_chain(&served_path).is_ok_and(|served| served[0] == chain[0]);
+
+    println!("Certificate {}", cert_path.display());
+    println!("    Subject: {}", leaf.subject());
+    println!("    SANs:    {}", domains.join(", "));
+    println!("    Serial:  {}", serial);
+    println!("    Reason:  {}", reason.name());
+    if !assume_yes
+        && !Confirm::new()
+            .with_prompt("Revocation can't be undone. Revoke this certificate?")
+            .default(false)
+            .interact()
+            .map_err(io::Error::other)?
+    {
+        println!("Exiting...");
+        return Ok(());
+    }
+
+    let key = match (use_certificate_key || key_path.is_some(), key_path) {
+        (false, _) => None,
+        (true, Some(path)) => Some(certificate_key(&path, &chain)?),
+        (true, None) => Some(certificate_key(&keystore::key_path(&dir), &chain)?),
+    };
+    let revocation_key = match &key {
+        Some(key) => RevocationKey::Certificate(key.secret_der()),
+        None => RevocationKey::Account,
+    };
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let newly_revoked = rt
+        .block_on(acme::revoke(
+            &config.acme,
+            chain[0].as_ref(),
+            reason.code(),
+            revocation_key,
+        ))
+        .map_err(io::Error::other)?;
+    if newly_revoked {
+        println!("Certificate {} revoked", serial);
+    } else {
+        println!("Certificate {} had already been revoked", serial);
+    }
+
+    // The revocation cannot be undone, so it is recorded whether or not the
+    // files could be moved aside
+    let retired = served.then(|| retire(&dir, &serial));
+    let (moved_to, retire_error) = match &retired {
+        Some(Ok(path)) => (Some(path.display().to_string()), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None),
+    };
+    audit::record(
+        &dir,
+        "certificate-revoked",
+        json!({
+            "serial": serial,
+            "domains": domains,
+            "sha256": hex::encode(Sha256::digest(chain[0].as_ref())),
+            "reason": reason.name(),
+            "reason_code": reason.code(),
+            "authorized_by": if key.is_some() { "certificate-key" } else { "account-key" },
+            "directory_url": config.acme.directory().ok(),
+            "already_revoked": !newly_revoked,
+            "moved_to": moved_to,
+            "move_error": retire_error,
+        }),
+    )?;
+    if let Some(retired) = retired {
+        println!("Moved the certificate and key to {}", retired?.display());
+    }
+
+    if served {
+        stop_server(&config.tls, &dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_rfc5280_reason_codes() {
+        let codes: Vec<(String, u8)> = RevocationReason::value_variants()
+            .iter()
+            .map(|reason| (reason.name().to_string(), reason.code()))
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                ("unspecified".to_string(), 0),
+                ("keyCompromise".to_string(), 1),
+                ("affiliationChanged".to_string(), 3),
+                ("superseded".to_string(), 4),
+                ("cessationOfOperation".to_string(), 5),
+            ]
+        );
+        assert_eq!(
+            RevocationReason::from_str("key-compromise", false),
+            Ok(RevocationReason::KeyCompromise)
+        );
+        assert!(RevocationReason::from_str("ca-compromise", false).is_err());
+    }
+
+    #[test]
+    fn retires_the_chain_and_key() {
+        let dir = env::temp_dir().join(format!("defe-revoke-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("fullchain.pem"), "chain").unwrap();
+        fs::write(dir.join(keystore::STORE_FILE), "key").unwrap();
+
+        let retired = retire(&dir, "0a1b");
+        let moved = |name: &str| fs::read_to_string(dir.join(REVOKED_DIR).join("0a1b").join(name));
+        let (chain, key) = (moved("fullchain.pem"), moved(keystore::STORE_FILE));
+        let left = (
+            dir.join("fullchain.pem").exists(),
+            dir.join(keystore::STORE_FILE).exists(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(retired.unwrap(), dir.join(REVOKED_DIR).join("0a1b"));
+        assert_eq!(chain.unwrap(), "chain");
+        assert_eq!(key.unwrap(), "key");
+        assert_eq!(left, (false, false));
+    }
+}
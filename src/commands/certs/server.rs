@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// What the running TLS server is asked to do
+pub(super) enum Request {
+    // Load the certificate again (SIGHUP)
+    Reload,
+    // Shut down gracefully (SIGTERM)
+    Stop,
+}
+
+// The TLS server's process ID from `pid_file` in `dir`, or None when it isn't
+// running
+pub(super) fn read_pid(dir: &Path, pid_file: &str) -> io::Result<Option<i32>> {
+    let pid = match fs::read_to_string(dir.join(pid_file)) {
+        Ok(pid) => pid,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let pid = pid.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid process ID in {}", pid_file),
+        )
+    })?;
+    // A server that crashed leaves its PID file behind, and the PID may since
+    // have gone to an unrelated process
+    if !runs_this_program(pid) {
+        eprintln!(
+            "Ignoring {}: process {} is not a defe server",
+            pid_file, pid
+        );
+        return Ok(None);
+    }
+    Ok(Some(pid))
+}
+
+// Whether process `pid` runs the same executable as this one. Where /proc
+// isn't available the PID file has to be trusted.
+fn runs_this_program(pid: i32) -> bool {
+    let proc_dir = Path::new("/proc");
+    if !proc_dir.join("self/exe").exists() {
+        return true;
+    }
+    let Ok(exe) = fs::read_link(proc_dir.join(pid.to_string()).join("exe")) else {
+        return false;
+    };
+    let Ok(current) = std::env::current_exe() else {
+        return true;
+    };
+    // An upgraded binary shows up as "defe (deleted)"; compare by name so a
+    // server started from another install location still counts
+    let name = |path: &Path| {
+        path.file_name().map(|name| {
+            name.to_string_lossy()
+                .trim_end_matches(" (deleted)")
+                .to_string()
+        })
+    };
+    name(&exe).is_some() && name(&exe) == name(&current)
+}
+
+// Returns false if there is no such process
+#[cfg(unix)]
+pub(super) fn signal(pid: i32, request: Request) -> io::Result<bool> {
+    use rustix::io::Errno;
+    use rustix::process::{kill_process, Pid, Signal};
+
+    let pid = Pid::from_raw(pid).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid process ID {}", pid),
+        )
+    })?;
+    let signal = match request {
+        Request::Reload => Signal::Hup,
+        Request::Stop => Signal::Term,
+    };
+    match kill_process(pid, signal) {
+        Ok(()) => Ok(true),
+        Err(Errno::SRCH) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn signal(_pid: i32, _request: Request) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn recognizes_this_program_only() {
+        assert!(runs_this_program(std::process::id() as i32));
+
+        let mut other = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let is_this = runs_this_program(other.id() as i32);
+        other.kill().unwrap();
+        other.wait().unwrap();
+        assert!(!is_this);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ignores_stale_pid_files() {
+        let dir = std::env::temp_dir().join(format!("defe-pid-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(read_pid(&dir, "server.pid").unwrap(), None);
+
+        fs::write(dir.join("server.pid"), format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(
+            read_pid(&dir, "server.pid").unwrap(),
+            Some(std::process::id() as i32)
+        );
+
+        // PID 1 is init, not a defe server
+        fs::write(dir.join("server.pid"), "1\n").unwrap();
+        let stale = read_pid(&dir, "server.pid").unwrap();
+        fs::write(dir.join("server.pid"), "not a pid").unwrap();
+        let invalid = read_pid(&dir, "server.pid");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(stale, None);
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use sha2::{Digest, Sha256};
+
+// Multicodec codes
+pub const DAG_PB: u64 = 0x70;
+pub const RAW: u64 = 0x55;
+const SHA2_256: u64 = 0x12;
+const SHA2_256_LENGTH: usize = 32;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+// A content identifier for dag-pb or raw content addressed by SHA-256, the
+// kinds gateways serve files as
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cid {
+    pub codec: u64,
+    pub digest: [u8; SHA2_256_LENGTH],
+}
+
+// Reads an unsigned LEB128 varint. Returns the value and the bytes used.
+pub fn read_varint(data: &[u8]) -> Result<(u64, usize), String> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+    Err("Truncated or oversized varint".to_string())
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn base58_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| format!("Invalid base58 character {:?}", c as char))?
+            as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = text.bytes().take_while(|c| *c == b'1').count();
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes);
+    Ok(decoded)
+}
+
+fn base32_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())
+            .ok_or_else(|| format!("Invalid base32 character {:?}", c as char))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+impl Cid {
+    // Parses a CIDv0 ("Qm...") or a base32 CIDv1 ("b...")
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bytes = if text.len() == 46 && text.starts_with("Qm") {
+            base58_decode(text)?
+        } else if let Some(encoded) = text.strip_prefix('b') {
+            base32_decode(encoded)?
+        } else {
+            return Err(format!(
+                "{} is not a CIDv0 or base32 CIDv1 content identifier",
+                text
+            ));
+        };
+        let (cid, used) = Self::from_bytes(&bytes)?;
+        if used != bytes.len() {
+            return Err(format!("{} has trailing bytes", text));
+        }
+        Ok(cid)
+    }
+
+    // Reads a binary CID from the start of `data`, as found in CAR files and
+    // dag-pb links. Returns it and the bytes used.
+    pub fn from_bytes(data: &[u8]) -> Result<(Self, usize), String> {
+        // CIDv0 is a bare SHA-256 multihash of dag-pb
+        let (codec, mut offset) = if data.starts_with(&[SHA2_256 as u8, SHA2_256_LENGTH as u8]) {
+            (DAG_PB, 0)
+        } else {
+            let (version, used) = read_varint(data)?;
+            if version != 1 {
+                return Err(format!("Unsupported CID version {}", version));
+            }
+            let (codec, codec_used) = read_varint(&data[used..])?;
+            (codec, used + codec_used)
+        };
+        if codec != DAG_PB && codec != RAW {
+            return Err(format!(
+                "Unsupported codec 0x{:x}; only dag-pb and raw content can be verified",
+                codec
+            ));
+        }
+
+        let (hash, used) = read_varint(&data[offset..])?;
+        offset += used;
+        let (length, used) = read_varint(&data[offset..])?;
+        offset += used;
+        if hash != SHA2_256 || length as usize != SHA2_256_LENGTH {
+            return Err(format!(
+                "Unsupported multihash 0x{:x}; only sha2-256 can be verified",
+                hash
+            ));
+        }
+        let digest = data
+            .get(offset..offset + SHA2_256_LENGTH)
+            .and_then(|digest| digest.try_into().ok())
+            .ok_or_else(|| "Truncated CID".to_string())?;
+        Ok((Self { codec, digest }, offset + SHA2_256_LENGTH))
+    }
+
+    // The binary CIDv1 form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_varint(&mut bytes, 1);
+        put_varint(&mut bytes, self.codec);
+        put_varint(&mut bytes, SHA2_256);
+        put_varint(&mut bytes, SHA2_256_LENGTH as u64);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    // Whether `block` is the content this CID names
+    pub fn verify(&self, block: &[u8]) -> bool {
+        Sha256::digest(block).as_slice() == self.digest
+    }
+}
+
+// The base32 CIDv1 form, which every gateway accepts
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "b{}", base32_encode(&self.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The CIDs of the empty file, as `ipfs add` and raw leaves produce them
+    const EMPTY_V0: &str = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+    const EMPTY_DAG_PB_V1: &str = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+    const EMPTY_RAW_V1: &str = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    #[test]
+    fn parses_v0_as_dag_pb() {
+        let cid = Cid::parse(EMPTY_V0).unwrap();
+        assert_eq!(cid.codec, DAG_PB);
+        assert_eq!(cid.to_string(), EMPTY_DAG_PB_V1);
+        assert_eq!(Cid::parse(EMPTY_DAG_PB_V1).unwrap(), cid);
+    }
+
+    #[test]
+    fn parses_v1_raw() {
+        let cid = Cid::parse(EMPTY_RAW_V1).unwrap();
+        assert_eq!(cid.codec, RAW);
+        assert_eq!(cid.to_string(), EMPTY_RAW_V1);
+        assert!(cid.verify(b""));
+        assert!(!cid.verify(b"x"));
+    }
+
+    #[test]
+    fn round_trips_binary_cids() {
+        let cid = Cid::parse(EMPTY_RAW_V1).unwrap();
+        let mut bytes = cid.to_bytes();
+        bytes.extend(b"trailing");
+        assert_eq!(Cid::from_bytes(&bytes).unwrap(), (cid, 36));
+    }
+
+    #[test]
+    fn rejects_invalid_cids() {
+        // Not base58
+        assert!(Cid::parse("Qm0fTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n").is_err());
+        // Unsupported multibase
+        assert!(Cid::parse("zdj7WWeQ43G6JJvLWQWZpyHuAMq6uYWRjkBXFad11vE2LHhQ7").is_err());
+        // Truncated digest
+        assert!(Cid::parse(&EMPTY_RAW_V1[..40]).is_err());
+        // dag-cbor is not verifiable here
+        assert!(Cid::parse("bafyreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku").is_err());
+        assert!(Cid::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn varints() {
+        assert_eq!(read_varint(&[0x01]).unwrap(), (1, 1));
+        assert_eq!(read_varint(&[0xac, 0x02, 0xff]).unwrap(), (300, 2));
+        assert!(read_varint(&[0x80]).is_err());
+        assert!(read_varint(&[0xff; 11]).is_err());
+        let mut encoded = Vec::new();
+        put_varint(&mut encoded, u64::MAX);
+        assert_eq!(read_varint(&encoded).unwrap(), (u64::MAX, 10));
+    }
+}
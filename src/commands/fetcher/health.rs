@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+// Weight of the newest sample in the latency average
+const LATENCY_WEIGHT: f64 = 0.3;
+
+// What is remembered about one gateway across runs
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GatewayHealth {
+    // Moving average of how long verified fetches took
+    pub latency_ms: Option<f64>,
+    pub successes: u64,
+    pub failures: u64,
+    // Failures since the last success
+    pub consecutive_failures: u64,
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl GatewayHealth {
+    // Expected cost of asking this gateway, lower is better: its usual
+    // latency plus a full timeout for every failure since it last worked.
+    // Gateways never tried are assumed to be as fast as `unknown_ms`.
+    fn score(&self, timeout: Duration, unknown_ms: f64) -> f64 {
+        self.latency_ms.unwrap_or(unknown_ms)
+            + self.consecutive_failures as f64 * timeout.as_millis() as f64
+    }
+}
+
+// Health of every gateway, kept in the workspace between runs
+pub struct HealthScores {
+    path: PathBuf,
+    gateways: BTreeMap<String, GatewayHealth>,
+}
+
+impl HealthScores {
+    // A missing or unreadable file starts the history afresh
+    pub fn load(path: &Path) -> Self {
+        let gateways = fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path: path.to_path_buf(),
+            gateways,
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(&self.path, serde_json::to_vec_pretty(&self.gateways)?)
+    }
+
+    pub fn get(&self, gateway: &str) -> Option<&GatewayHealth> {
+        self.gateways.get(gateway)
+    }
+
+    // `gateways` from healthiest to least healthy; ties keep the configured order
+    pub fn order(&self, gateways: &[String], timeout: Duration) -> Vec<String> {
+        let known: Vec<f64> = self
+            .gateways
+            .values()
+            .filter_map(|health| health.latency_ms)
+            .collect();
+        let unknown_ms = if known.is_empty() {
+            0.0
+        } else {
+            known.iter().sum::<f64>() / known.len() as f64
+        };
+        let mut ordered = gateways.to_vec();
+        ordered.sort_by(|a, b| {
+            let score = |gateway: &str| {
+                self.get(gateway)
+                    .map_or(unknown_ms, |health| health.score(timeout, unknown_ms))
+            };
+            score(a).total_cmp(&score(b))
+        });
+        ordered
+    }
+
+    pub fn record_success(&mut self, gateway: &str, latency: Duration) {
+        let health = self.gateways.entry(gateway.to_string()).or_default();
+        let sample = latency.as_secs_f64() * 1000.0;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(average) => average + LATENCY_WEIGHT * (sample - average),
+            None => sample,
+        });
+        health.successes += 1;
+        health.consecutive_failures = 0;
+        health.last_success = Some(Utc::now().to_rfc3339());
+    }
+
+    pub fn record_failure(&mut self, gateway: &str, error: String) {
+        let health = self.gateways.entry(gateway.to_string()).or_default();
+        health.failures += 1;
+        health.consecutive_failures += 1;
+        health.last_error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn scores() -> HealthScores {
+        HealthScores::load(Path::new("/nonexistent/defe-health.json"))
+    }
+
+    fn gateways(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn orders_by_latency_and_failures() {
+        let mut health = scores();
+        health.record_success("slow", Duration::from_millis(900));
+        health.record_success("fast", Duration::from_millis(100));
+        health.record_success("flaky", Duration::from_millis(50));
+        health.record_failure("flaky", "timed out".to_string());
+
+        // Untried gateways are placed at the average known latency
+        assert_eq!(
+            health.order(&gateways(&["slow", "new", "flaky", "fast"]), TIMEOUT),
+            ["fast", "new", "slow", "flaky"]
+        );
+
+        // A success forgives earlier failures
+        health.record_success("flaky", Duration::from_millis(50));
+        assert_eq!(health.get("flaky").unwrap().consecutive_failures, 0);
+        assert_eq!(health.get("flaky").unwrap().failures, 1);
+        assert_eq!(
+            health.order(&gateways(&["slow", "flaky", "fast"]), TIMEOUT)[0],
+            "flaky"
+        );
+    }
+
+    #[test]
+    fn keeps_the_configured_order_without_history() {
+        assert_eq!(
+            scores().order(&gateways(&["b", "a", "c"]), TIMEOUT),
+            ["b", "a", "c"]
+        );
+    }
+
+    #[test]
+    fn averages_latency() {
+        let mut health = scores();
+        health.record_success("gateway", Duration::from_millis(100));
+        health.record_success("gateway", Duration::from_millis(200));
+        let latency = health.get("gateway").unwrap().latency_ms.unwrap();
+        assert!((latency - 130.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,256 @@
+use reqwest;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::config::{Config, FetcherSettings};
+
+mod cid;
+mod health;
+mod verified;
+
+use cid::Cid;
+use health::HealthScores;
+
+// This program will run an asyncrounous Fetch Request to IPFS to load from a git commit hash from the rust-sgx
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepoInfo {
+    pub repo_url: String,
+    pub commit_hash: String,
+}
+
+// Written into the checked-out repository so the TLS server can report what it
+// is serving
+pub const REPO_INFO_FILE: &str = "defe-repo.json";
+
+impl RepoInfo {
+    pub fn load() -> Option<Self> {
+        let contents = fs::read_to_string(REPO_INFO_FILE).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        fs::write(REPO_INFO_FILE, serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Error, Debug)]
+enum FetchError {
+    #[error("Request timed out")]
+    Timeout(#[from] tokio::time::error::Elapsed),
+    #[error("Request error")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("HTTP error with status code {0}")]
+    Http(reqwest::StatusCode),
+    #[error("Response larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("Content does not match the CID: {0}")]
+    Verification(String),
+    #[error("Invalid repository info: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// Gateways are untrusted: the content is only parsed once it has been hashed
+// back to the CID that was asked for
+async fn fetch_from_gateway(
+    client: &reqwest::Client,
+    cid: &Cid,
+    gateway: &str,
+) -> Result<RepoInfo, FetchError> {
+    println!("Fetching {} from {}", cid, gateway);
+    let content = verified::fetch_verified(client, gateway, cid).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+// Starts the gateways in health order, `stagger_ms` apart, and returns the
+// first verified answer with the gateway that gave it. The other requests are
+// cancelled; only those that finished count towards their gateway's health.
+async fn race_gateways(
+    client: &reqwest::Client,
+    cid: &Cid,
+    settings: &FetcherSettings,
+    health: &mut HealthScores,
+) -> Option<(String, Duration, RepoInfo)> {
+    let deadline = Duration::from_secs(settings.timeout_secs);
+    let mut fetches = JoinSet::new();
+    for (rank, gateway) in health
+        .order(&settings.gateways, deadline)
+        .into_iter()
+        .enumerate()
+    {
+        let (client, cid) = (client.clone(), cid.clone());
+        let delay = Duration::from_millis(settings.stagger_ms * rank as u64);
+        fetches.spawn(async move {
+            tokio::time::sleep(delay).await;
+            let started = Instant::now();
+            let result = match timeout(deadline, fetch_from_gateway(&client, &cid, &gateway)).await
+            {
+                Ok(result) => result,
+                Err(elapsed) => Err(FetchError::Timeout(elapsed)),
+            };
+            (gateway, started.elapsed(), result)
+        });
+    }
+
+    while let Some(finished) = fetches.join_next().await {
+        let Ok((gateway, elapsed, result)) = finished else {
+            continue;
+        };
+        match result {
+            Ok(info) => {
+                health.record_success(&gateway, elapsed);
+                fetches.abort_all();
+                return Some((gateway, elapsed, info));
+            }
+            Err(err) => {
+                eprintln!("Error fetching from {}: {}", gateway, err);
+                health.record_failure(&gateway, err.to_string());
+            }
+        }
+    }
+    None
+}
+
+fn run_git_command(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .expect("Failed to execute git command");
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into())
+    }
+}
+
+pub fn run() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        println!("Running defe-fetcher");
+
+        // Prompt the user for the IPFS CID
+        print!("Enter the IPFS CID: ");
+        io::stdout().flush().unwrap();
+
+        let mut cid = String::new();
+        io::stdin().read_line(&mut cid).unwrap();
+        let cid = match Cid::parse(cid.trim()) {
+            Ok(cid) => cid,
+            Err(err) => {
+                eprintln!("Invalid CID: {}", err);
+                return;
+            }
+        };
+
+        let settings = match Config::load() {
+            Ok(config) => config.fetcher,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+        if settings.gateways.is_empty() {
+            eprintln!("No IPFS gateways configured in fetcher.gateways.");
+            return;
+        }
+
+        // Race the gateways, remembering how each one did for next time
+        let client = reqwest::Client::new();
+        let mut health = HealthScores::load(Path::new(&settings.health_file));
+        let served = race_gateways(&client, &cid, &settings, &mut health).await;
+        if let Err(err) = health.save() {
+            eprintln!(
+                "Failed to record gateway health in {}: {}",
+                settings.health_file, err
+            );
+        }
+
+        let Some((gateway, elapsed, repo_info)) = served else {
+            eprintln!("Failed to fetch file from all gateways.");
+            return;
+        };
+
+        println!(
+            "Fetched and verified repository info from {} in {} ms: {:?}",
+            gateway,
+            elapsed.as_millis(),
+            repo_info
+        );
+
+        // Extract the repository name from the URL
+        let repo_name = repo_info
+            .repo_url
+            .split('/')
+            .next_back()
+            .unwrap()
+            .trim_end_matches(".git");
+
+        // Check if the repository directory exists
+        let repo_path = Path::new(repo_name);
+
+        if repo_path.exists() {
+            // Change directory to the cloned repository
+            env::set_current_dir(repo_path).unwrap();
+            println!("Changed directory to {}", repo_name);
+
+            // Fetch only the specific commit to ensure the commit is accessible
+            println!("Fetching the specific commit...");
+            if let Err(err) = run_git_command(&["fetch", "origin", &repo_info.commit_hash]) {
+                eprintln!("Git fetch failed: {}", err);
+                return;
+            }
+
+            // Reset to the specific commit to replenish missing files
+            println!("Resetting to the specific commit...");
+            if let Err(err) = run_git_command(&["reset", "--hard", &repo_info.commit_hash]) {
+                eprintln!("Git reset failed: {}", err);
+                return;
+            }
+        } else {
+            // Clone the GitHub repository with depth 1 to save space
+            println!("Cloning the repository...");
+            if let Err(err) = run_git_command(&[
+                "clone",
+                "--depth",
+                "1",
+                "--branch",
+                "main",
+                &repo_info.repo_url,
+            ]) {
+                eprintln!("Git clone failed: {}", err);
+                return;
+            }
+
+            // Change directory to the cloned repository
+            env::set_current_dir(repo_name).unwrap();
+            println!("Changed directory to {}", repo_name);
+
+            // Fetch only the specific commit
+            println!("Fetching the specific commit...");
+            if let Err(err) = run_git_command(&["fetch", "origin", &repo_info.commit_hash]) {
+                eprintln!("Git fetch failed: {}", err);
+                return;
+            }
+
+            // Reset to the specific commit
+            println!("Resetting to the specific commit...");
+            if let Err(err) = run_git_command(&["reset", "--hard", &repo_info.commit_hash]) {
+                eprintln!("Git reset failed: {}", err);
+                return;
+            }
+        }
+
+        println!("Checked out commit: {}", repo_info.commit_hash);
+
+        if let Err(err) = repo_info.save() {
+            eprintln!("Failed to record {}: {}", REPO_INFO_FILE, err);
+        }
+    });
+}
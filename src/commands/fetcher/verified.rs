@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use tokio::time::{timeout, Duration};
+
+use super::cid::{read_varint, Cid, DAG_PB, RAW};
+use super::FetchError;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Repository info is a small JSON file; anything near this is not it
+const MAX_CONTENT_LENGTH: usize = 1 << 20;
+// Largest response read from a gateway: the content plus CAR and dag-pb framing
+const MAX_RESPONSE_LENGTH: usize = 2 * MAX_CONTENT_LENGTH;
+const MAX_BLOCKS: usize = 1024;
+
+const CAR_TYPE: &str = "application/vnd.ipld.car";
+const RAW_TYPE: &str = "application/vnd.ipld.raw";
+
+// UnixFS node types that carry file content
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_FILE: u64 = 2;
+
+fn invalid(message: String) -> FetchError {
+    FetchError::Verification(message)
+}
+
+// One protobuf field: `bytes` is set for length-delimited fields and `value`
+// for varint ones, the only two wire types dag-pb and UnixFS use
+struct Field<'a> {
+    number: u64,
+    bytes: &'a [u8],
+    value: u64,
+}
+
+fn protobuf_fields(mut data: &[u8]) -> Result<Vec<Field<'_>>, FetchError> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let (key, used) = read_varint(data).map_err(invalid)?;
+        data = &data[used..];
+        let (number, wire_type) = (key >> 3, key & 7);
+        let (value, used) = read_varint(data).map_err(invalid)?;
+        data = &data[used..];
+        match wire_type {
+            0 => fields.push(Field {
+                number,
+                bytes: &[],
+                value,
+            }),
+            2 => {
+                let length = usize::try_from(value)
+                    .ok()
+                    .filter(|length| *length <= data.len())
+                    .ok_or_else(|| invalid("Truncated protobuf field".to_string()))?;
+                fields.push(Field {
+                    number,
+                    bytes: &data[..length],
+                    value: 0,
+                });
+                data = &data[length..];
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "Unexpected protobuf wire type {}",
+                    wire_type
+                )))
+            }
+        }
+    }
+    Ok(fields)
+}
+
+// A dag-pb node of a UnixFS file: its own bytes, then its children's
+#[derive(Debug)]
+struct FileNode {
+    data: Vec<u8>,
+    links: Vec<Cid>,
+}
+
+fn decode_dag_pb(block: &[u8]) -> Result<FileNode, FetchError> {
+    let mut node = FileNode {
+        data: Vec::new(),
+        links: Vec::new(),
+    };
+    let mut unixfs = None;
+    for field in protobuf_fields(block)? {
+        match field.number {
+            // PBNode.Data: the UnixFS metadata
+            1 => unixfs = Some(field.bytes),
+            // PBNode.Links: PBLink.Hash is field 1
+            2 => {
+                let hash = protobuf_fields(field.bytes)?
+                    .into_iter()
+                    .find(|link| link.number == 1)
+                    .ok_or_else(|| invalid("dag-pb link without a hash".to_string()))?;
+                let (cid, _) = Cid::from_bytes(hash.bytes).map_err(invalid)?;
+                node.links.push(cid);
+            }
+            _ => {}
+        }
+    }
+
+    let unixfs = unixfs.ok_or_else(|| invalid("dag-pb node without UnixFS data".to_string()))?;
+    let mut kind = None;
+    for field in protobuf_fields(unixfs)? {
+        match field.number {
+            1 => kind = Some(field.value),
+            2 => node.data = field.bytes.to_vec(),
+            _ => {}
+        }
+    }
+    match kind {
+        Some(UNIXFS_RAW) | Some(UNIXFS_FILE) => Ok(node),
+        Some(kind) => Err(invalid(format!(
+            "The CID names a UnixFS node of type {}, not a file",
+            kind
+        ))),
+        None => Err(invalid("UnixFS data without a type".to_string())),
+    }
+}
+
+// Reads a varint length prefix and the bytes it covers. Returns them and the
+// rest of `data`.
+fn length_prefixed<'a>(data: &'a [u8], what: &str) -> Result<(&'a [u8], &'a [u8]), FetchError> {
+    let (length, used) = read_varint(data).map_err(invalid)?;
+    let end = usize::try_from(length)
+        .ok()
+        .and_then(|length| used.checked_add(length))
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| invalid(format!("Truncated CAR {}", what)))?;
+    Ok((&data[used..end], &data[end..]))
+}
+
+// Splits a CARv1 file into its blocks, keeping only those that hash to the
+// CID they are filed under
+fn parse_car(car: &[u8]) -> Result<HashMap<Cid, Vec<u8>>, FetchError> {
+    let (_, mut data) = length_prefixed(car, "header")?;
+
+    let mut blocks = HashMap::new();
+    while !data.is_empty() {
+        let (section, rest) = length_prefixed(data, "section")?;
+        data = rest;
+
+        let (cid, cid_length) = Cid::from_bytes(section).map_err(invalid)?;
+        let block = &section[cid_length..];
+        if !cid.verify(block) {
+            return Err(invalid(format!("Block {} does not match its CID", cid)));
+        }
+        blocks.insert(cid, block.to_vec());
+        if blocks.len() > MAX_BLOCKS {
+            return Err(invalid("The CAR file has too many blocks".to_string()));
+        }
+    }
+    Ok(blocks)
+}
+
+async fn request(
+    client: &reqwest::Client,
+    url: &str,
+    accept: &str,
+) -> Result<reqwest::Response, FetchError> {
+    let response = timeout(
+        REQUEST_TIMEOUT,
+        client.get(url).header(ACCEPT, accept).send(),
+    )
+    .await??;
+    if !response.status().is_success() {
+        return Err(FetchError::Http(response.status()));
+    }
+    Ok(response)
+}
+
+// Reads the body chunk by chunk, giving up as soon as it passes the limit
+// rather than buffering whatever an untrusted gateway streams
+async fn read_body(mut response: reqwest::Response) -> Result<Vec<u8>, FetchError> {
+    let too_large = || FetchError::TooLarge(MAX_RESPONSE_LENGTH);
+    let declared = response.content_length().unwrap_or(0);
+    if declared > MAX_RESPONSE_LENGTH as u64 {
+        return Err(too_large());
+    }
+    let mut body = Vec::with_capacity(declared as usize);
+    while let Some(chunk) = timeout(REQUEST_TIMEOUT, response.chunk()).await?? {
+        if body.len() + chunk.len() > MAX_RESPONSE_LENGTH {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+// Where blocks come from: a CAR file fetched up front, or the gateway one
+// raw block at a time
+enum Blocks<'a> {
+    Car(HashMap<Cid, Vec<u8>>),
+    Gateway {
+        client: &'a reqwest::Client,
+        gateway: &'a str,
+    },
+}
+
+impl Blocks<'_> {
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>, FetchError> {
+        let block = match self {
+            Blocks::Car(blocks) => blocks
+                .get(cid)
+                .cloned()
+                .ok_or_else(|| invalid(format!("The CAR file lacks block {}", cid)))?,
+            Blocks::Gateway { client, gateway } => {
+                let url = format!("{}/ipfs/{}?format=raw", gateway, cid);
+                read_body(request(client, &url, RAW_TYPE).await?).await?
+            }
+        };
+        // CAR blocks were checked when parsed; checking again is cheap
+        if !cid.verify(&block) {
+            return Err(invalid(format!("Block {} does not match its CID", cid)));
+        }
+        Ok(block)
+    }
+}
+
+// Reassembles the file `root` names, block by block in depth-first order,
+// verifying each block against the CID that links to it
+async fn assemble(root: &Cid, blocks: &Blocks<'_>) -> Result<Vec<u8>, FetchError> {
+    let mut content = Vec::new();
+    let mut pending = vec![root.clone()];
+    let mut visited = 0;
+    while let Some(cid) = pending.pop() {
+        visited += 1;
+        if visited > MAX_BLOCKS {
+            return Err(invalid("The file has too many blocks".to_string()));
+        }
+        let block = blocks.get(&cid).await?;
+        match cid.codec {
+            RAW => content.extend_from_slice(&block),
+            DAG_PB => {
+                let node = decode_dag_pb(&block)?;
+                content.extend_from_slice(&node.data);
+                pending.extend(node.links.into_iter().rev());
+            }
+            codec => return Err(invalid(format!("Unsupported codec 0x{:x}", codec))),
+        }
+        if content.len() > MAX_CONTENT_LENGTH {
+            return Err(invalid("The file is larger than expected".to_string()));
+        }
+    }
+    Ok(content)
+}
+
+// The blocks of the CAR file `gateway` serves for `cid`, or None when it
+// doesn't serve a usable one
+async fn fetch_car(
+    client: &reqwest::Client,
+    gateway: &str,
+    cid: &Cid,
+) -> Result<Option<HashMap<Cid, Vec<u8>>>, FetchError> {
+    let url = format!("{}/ipfs/{}?format=car", gateway, cid);
+    let response = match request(client, &url, CAR_TYPE).await {
+        Ok(response) => response,
+        Err(FetchError::Http(status)) => {
+            println!("{} returned {} for a CAR file", gateway, status);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    // Gateways that ignore the format answer with the file itself
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with(CAR_TYPE) {
+        println!("{} sent {:?} instead of a CAR file", gateway, content_type);
+        return Ok(None);
+    }
+    match parse_car(&read_body(response).await?) {
+        Ok(blocks) => Ok(Some(blocks)),
+        Err(e) => {
+            println!("{} sent an unusable CAR file: {}", gateway, e);
+            Ok(None)
+        }
+    }
+}
+
+// Fetches the file `cid` names from `gateway` without trusting it: as a CAR
+// file when the gateway supports that, otherwise as raw blocks. Every block
+// is hashed and checked against its CID before its content is used.
+pub(super) async fn fetch_verified(
+    client: &reqwest::Client,
+    gateway: &str,
+    cid: &Cid,
+) -> Result<Vec<u8>, FetchError> {
+    match fetch_car(client, gateway, cid).await? {
+        Some(blocks) => assemble(cid, &Blocks::Car(blocks)).await,
+        None => {
+            println!("Fetching raw blocks from {} instead", gateway);
+            assemble(cid, &Blocks::Gateway { client, gateway }).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn raw_cid(block: &[u8]) -> Cid {
+        Cid {
+            codec: RAW,
+            digest: Sha256::digest(block).into(),
+        }
+    }
+
+    fn dag_pb_cid(block: &[u8]) -> Cid {
+        Cid {
+            codec: DAG_PB,
+            digest: Sha256::digest(block).into(),
+        }
+    }
+
+    fn length_delimited(number: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut field = vec![number << 3 | 2, bytes.len() as u8];
+        field.extend_from_slice(bytes);
+        field
+    }
+
+    // A UnixFS file node holding `data` and linking to `links`
+    fn file_node(data: &[u8], links: &[Cid]) -> Vec<u8> {
+        let mut unixfs = vec![0x08, UNIXFS_FILE as u8];
+        unixfs.extend(length_delimited(2, data));
+        let mut node = Vec::new();
+        // dag-pb puts the links first
+        for link in links {
+            node.extend(length_delimited(2, &length_delimited(1, &link.to_bytes())));
+        }
+        node.extend(length_delimited(1, &unixfs));
+        node
+    }
+
+    fn car(root: &Cid, blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        // dag-cbor {"roots": [root], "version": 1}, the root as tag 42
+        let mut root_bytes = vec![0x00];
+        root_bytes.extend(root.to_bytes());
+        let mut header = vec![0xa2, 0x65];
+        header.extend(b"roots");
+        header.extend([0x81, 0xd8, 0x2a, 0x58, root_bytes.len() as u8]);
+        header.extend(root_bytes);
+        header.push(0x67);
+        header.extend(b"version");
+        header.push(0x01);
+
+        let mut car = vec![header.len() as u8];
+        car.extend(header);
+        for (cid, block) in blocks {
+            let mut section = cid.to_bytes();
+            section.extend(block);
+            car.push(section.len() as u8);
+            car.extend(section);
+        }
+        car
+    }
+
+    #[test]
+    fn decodes_file_nodes() {
+        let leaf = raw_cid(b"world");
+        let node = decode_dag_pb(&file_node(b"hello ", std::slice::from_ref(&leaf))).unwrap();
+        assert_eq!(node.data, b"hello ");
+        assert_eq!(node.links, vec![leaf]);
+    }
+
+    #[test]
+    fn rejects_directories() {
+        // The empty UnixFS directory, QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn
+        let block = [0x0a, 0x02, 0x08, 0x01];
+        let cid = Cid::parse("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        assert!(cid.verify(&block));
+        assert!(matches!(
+            decode_dag_pb(&block),
+            Err(FetchError::Verification(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_protobuf() {
+        assert!(decode_dag_pb(&[0x0a, 0x05, 0x08]).is_err());
+        assert!(
+            decode_dag_pb(&[0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])
+                .is_err()
+        );
+        assert!(decode_dag_pb(&[0x0d, 0x00, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[tokio::test]
+    async fn assembles_a_verified_car() {
+        let leaf = b"world".to_vec();
+        let leaf_cid = raw_cid(&leaf);
+        let root = file_node(b"hello ", std::slice::from_ref(&leaf_cid));
+        let root_cid = dag_pb_cid(&root);
+        let car = car(&root_cid, &[(root_cid.clone(), root), (leaf_cid, leaf)]);
+
+        let blocks = parse_car(&car).unwrap();
+        let content = assemble(&root_cid, &Blocks::Car(blocks)).await.unwrap();
+        assert_eq!(content, b"hello world");
+    }
+
+    #[test]
+    fn rejects_a_tampered_car() {
+        let block = b"{\"repo_url\":\"https://example.com/repo.git\"}".to_vec();
+        let cid = raw_cid(&block);
+        let mut car = car(&cid, &[(cid.clone(), block)]);
+        let last = car.len() - 2;
+        car[last] ^= 1;
+        assert!(matches!(parse_car(&car), Err(FetchError::Verification(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_car_missing_blocks() {
+        let leaf_cid = raw_cid(b"world");
+        let root = file_node(b"hello ", &[leaf_cid]);
+        let root_cid = dag_pb_cid(&root);
+        let blocks = parse_car(&car(&root_cid, &[(root_cid.clone(), root)])).unwrap();
+        assert!(assemble(&root_cid, &Blocks::Car(blocks)).await.is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let huge = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(parse_car(&huge).is_err());
+
+        let cid = raw_cid(b"x");
+        let mut car = car(&cid, &[]);
+        car.extend(huge);
+        assert!(parse_car(&car).is_err());
+        assert!(parse_car(&[]).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use aws_lc_rs::encoding::{AsDer, Pkcs8V1Der};
+use aws_lc_rs::rsa::KeyPair as RsaKeyPair;
+use aws_lc_rs::signature::{
+    EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P384_SHA384_ASN1_SIGNING,
+};
+use base64::{engine::general_purpose, Engine as _};
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use rand::{thread_rng, Rng};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::commands::invalid_data;
+use crate::config::KeyStoreKind;
+
+// The TLS private key is kept only in this file, encrypted; there is no
+// plaintext `privkey.pem` for certificates DEFE obtained itself.
+pub const STORE_FILE: &str = "privkey.store.json";
+
+// Plaintext key file used by development certificates and by keys from
+// before the store existed
+pub const PLAINTEXT_FILE: &str = "privkey.pem";
+
+// Read instead of prompting, e.g. from `.env` for unattended servers
+pub const PASSPHRASE_ENV: &str = "DEFE_KEY_PASSPHRASE";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    kind: KeyStoreKind,
+    // PBKDF2 salt, or the SGX key ID for sealed keys
+    salt: String,
+    nonce: String,
+    // AES-256-GCM over the PKCS#8 DER private key
+    ciphertext: String,
+}
+
+fn passphrase(confirm: bool) -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Private key passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The passphrase must not be empty",
+        ));
+    }
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Passphrases do not match",
+        ));
+    }
+    Ok(passphrase)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> io::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key)
+        .map_err(|e| invalid_data(format!("Key derivation error: {}", e)))?;
+    Ok(key)
+}
+
+// An enclave seal key bound to MRENCLAVE, so only the same enclave build on
+// the same CPU can unseal
+#[cfg(target_env = "sgx")]
+fn seal_key(key_id: &[u8]) -> io::Result<[u8; 32]> {
+    use sha2::Digest;
+    use std::os::fortanix_sgx::arch::{egetkey, Align512};
+
+    const SEAL_KEY: u16 = 4;
+    const POLICY_MRENCLAVE: u16 = 1;
+
+    let mut request = Align512([0u8; 512]);
+    request.0[0..2].copy_from_slice(&SEAL_KEY.to_le_bytes());
+    request.0[2..4].copy_from_slice(&POLICY_MRENCLAVE.to_le_bytes());
+    // ATTRIBUTEMASK: bind to the INIT and DEBUG flags
+    request.0[24..32].copy_from_slice(&3u64.to_le_bytes());
+    request.0[40..40 + key_id.len().min(32)].copy_from_slice(&key_id[..key_id.len().min(32)]);
+    let key = egetkey(&request).map_err(|e| invalid_data(format!("EGETKEY failed: {}", e)))?;
+    Ok(Sha256::digest(key.0).into())
+}
+
+#[cfg(not(target_env = "sgx"))]
+fn seal_key(_key_id: &[u8]) -> io::Result<[u8; 32]> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Sealed key storage is only available inside an SGX enclave",
+    ))
+}
+
+fn store_key(kind: KeyStoreKind, salt: &[u8], confirm: bool) -> io::Result<[u8; 32]> {
+    match kind {
+        KeyStoreKind::Encrypted => passphrase_key(&passphrase(confirm)?, salt),
+        KeyStoreKind::Sealed => seal_key(salt),
+    }
+}
+
+// Whether keys can be stored without prompting, as the renewal daemon must
+pub fn unattended(kind: KeyStoreKind) -> bool {
+    kind == KeyStoreKind::Sealed || env::var(PASSPHRASE_ENV).is_ok()
+}
+
+// Encrypts a PKCS#8 private key into the contents of a store file. Asks for
+// the passphrase up front, so callers can do this before committing to a new
+// key and write the result with `write_private` once they have.
+pub fn encrypt(pkcs8_der: &[u8], kind: KeyStoreKind) -> io::Result<Vec<u8>> {
+    encrypt_with(pkcs8_der, kind, |kind, salt| store_key(kind, salt, true))
+}
+
+// `encrypt` with the store key derived from the salt by `store_key`
+fn encrypt_with(
+    pkcs8_der: &[u8],
+    kind: KeyStoreKind,
+    store_key: impl FnOnce(KeyStoreKind, &[u8]) -> io::Result<[u8; 32]>,
+) -> io::Result<Vec<u8>> {
+    let mut rng = thread_rng();
+    let salt: [u8; SALT_LENGTH] = rng.gen();
+    let nonce: [u8; NONCE_LENGTH] = rng.gen();
+    let key = store_key(kind, &salt)?;
+    let ciphertext = Aes256Gcm::new(&key.into())
+        .encrypt(Nonce::from_slice(&nonce), pkcs8_der)
+        .map_err(|e| invalid_data(format!("Encryption error: {}", e)))?;
+
+    let stored = StoredKey {
+        kind,
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    };
+    Ok(serde_json::to_vec_pretty(&stored)?)
+}
+
+pub fn load(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    load_with(path, |kind, salt| store_key(kind, salt, false))
+}
+
+fn load_with(
+    path: &Path,
+    store_key: impl FnOnce(KeyStoreKind, &[u8]) -> io::Result<[u8; 32]>,
+) -> io::Result<PrivateKeyDer<'static>> {
+    let stored: StoredKey = serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| invalid_data(format!("Invalid key store {}: {}", path.display(), e)))?;
+    let decode = |field: &str| {
+        general_purpose::STANDARD
+            .decode(field)
+            .map_err(|e| invalid_data(format!("Invalid key store {}: {}", path.display(), e)))
+    };
+    let salt = decode(&stored.salt)?;
+    let nonce = decode(&stored.nonce)?;
+    if nonce.len() != NONCE_LENGTH {
+        return Err(invalid_data(format!(
+            "Invalid key store {}",
+            path.display()
+        )));
+    }
+
+    let key = store_key(stored.kind, &salt)?;
+    let pkcs8 = Aes256Gcm::new(&key.into())
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            decode(&stored.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| {
+            invalid_data(format!(
+                "Could not decrypt {}: wrong passphrase or different enclave",
+                path.display()
+            ))
+        })?;
+    Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8)))
+}
+
+// Where the certificate key for the workspace in `dir` lives: the store if
+// there is one, otherwise the plaintext key file
+pub fn key_path(dir: &Path) -> PathBuf {
+    let store = dir.join(STORE_FILE);
+    if store.exists() {
+        store
+    } else {
+        dir.join(PLAINTEXT_FILE)
+    }
+}
+
+// Loads a key returned by `key_path`, decrypting it if it is a store
+pub fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    if path.file_name() == Some(STORE_FILE.as_ref()) {
+        return load(path);
+    }
+    let pem = fs::read(path)?;
+    rustls_pemfile::private_key(&mut pem.as_slice())?
+        .ok_or_else(|| invalid_data(format!("No private key found in {}", path.display())))
+}
+
+// The store holds PKCS#8, but certbot and openssl write EC keys as SEC1 and
+// older RSA keys as PKCS#1
+pub fn to_pkcs8(key: &PrivateKeyDer<'_>) -> io::Result<Vec<u8>> {
+    match key {
+        PrivateKeyDer::Pkcs8(key) => Ok(key.secret_pkcs8_der().to_vec()),
+        PrivateKeyDer::Sec1(key) => {
+            for algorithm in [
+                &ECDSA_P256_SHA256_ASN1_SIGNING,
+                &ECDSA_P384_SHA384_ASN1_SIGNING,
+            ] {
+                if let Ok(key_pair) =
+                    EcdsaKeyPair::from_private_key_der(algorithm, key.secret_sec1_der())
+                {
+                    return key_pair
+                        .to_pkcs8v1()
+                        .map(|document| document.as_ref().to_vec())
+                        .map_err(|e| invalid_data(format!("Failed to convert the EC key: {}", e)));
+                }
+            }
+            Err(invalid_data("Unsupported EC private key curve".to_string()))
+        }
+        PrivateKeyDer::Pkcs1(key) => {
+            let key_pair = RsaKeyPair::from_der(key.secret_pkcs1_der())
+                .map_err(|e| invalid_data(format!("Invalid RSA private key: {}", e)))?;
+            let der: Pkcs8V1Der = AsDer::as_der(&key_pair)
+                .map_err(|e| invalid_data(format!("Failed to convert the RSA key: {}", e)))?;
+            Ok(der.as_ref().to_vec())
+        }
+        _ => Err(invalid_data("Unsupported private key format".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_round_trips() {
+        let store_key = |kind, salt: &[u8]| {
+            assert_eq!(kind, KeyStoreKind::Encrypted);
+            passphrase_key("correct horse battery staple", salt)
+        };
+        let pkcs8 = rcgen::KeyPair::generate().unwrap().serialize_der();
+        let dir = env::temp_dir().join(format!("defe-keystore-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = encrypt_with(&pkcs8, KeyStoreKind::Encrypted, store_key).unwrap();
+        fs::write(dir.join(STORE_FILE), &store).unwrap();
+        assert_eq!(key_path(&dir), dir.join(STORE_FILE));
+        let loaded = load_with(&dir.join(STORE_FILE), store_key);
+        let wrong = load_with(&dir.join(STORE_FILE), |_, salt| {
+            passphrase_key("wrong passphrase", salt)
+        });
+
+        // A malformed store is rejected before any key is derived
+        let mut stored: StoredKey = serde_json::from_slice(&store).unwrap();
+        stored.nonce = general_purpose::STANDARD.encode([0u8; 8]);
+        fs::write(dir.join(STORE_FILE), serde_json::to_vec(&stored).unwrap()).unwrap();
+        let malformed = load_with(&dir.join(STORE_FILE), |_, _| {
+            panic!("derived a key for a malformed store")
+        });
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap().secret_der(), pkcs8.as_slice());
+        assert!(wrong.is_err());
+        assert!(malformed.is_err());
+    }
+
+    // Generated with `openssl ecparam -genkey -noout` and `openssl genrsa -traditional`
+    #[test]
+    fn converts_keys_to_pkcs8() {
+        for pem in [
+            &include_bytes!("testdata/keystore/ec-sec1.pem")[..],
+            include_bytes!("testdata/keystore/rsa-pkcs1.pem"),
+        ] {
+            let key = rustls_pemfile::private_key(&mut &pem[..]).unwrap().unwrap();
+            assert!(!matches!(key, PrivateKeyDer::Pkcs8(_)));
+            let pkcs8 = to_pkcs8(&key).unwrap();
+            let converted = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.clone()));
+            assert!(rustls::crypto::aws_lc_rs::sign::any_supported_type(&converted).is_ok());
+            assert_eq!(to_pkcs8(&converted).unwrap(), pkcs8);
+        }
+
+        let garbage = PrivateKeyDer::Sec1(vec![0x30, 0x00].into());
+        assert!(to_pkcs8(&garbage).is_err());
+    }
+
+    #[cfg(not(target_env = "sgx"))]
+    #[test]
+    fn sealing_needs_an_enclave() {
+        assert!(encrypt(b"key", KeyStoreKind::Sealed).is_err());
+    }
+}
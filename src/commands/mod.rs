@@ -1,6 +1,16 @@
+use std::io;
+
+pub mod acme;
+pub mod audit;
 pub mod certbot;
+pub mod certs;
 pub mod fetcher;
 pub mod jsframe;
+pub mod keystore;
 pub mod mpc;
 pub mod ros;
 pub mod tls;
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
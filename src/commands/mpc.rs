@@ -4,14 +4,27 @@ use serde::{Deserialize, Serialize};
 use sharks::{Share, Sharks};
 use std::fs;
 use std::io;
+use std::path::Path;
 use threshold_crypto::ff::Field;
 use threshold_crypto::ff::PrimeField;
+use threshold_crypto::serde_impl::SerdeSecret;
 use threshold_crypto::{Fr, FrRepr, PublicKey, PublicKeySet, SecretKey, SecretKeySet, PK_SIZE};
 
+pub(crate) const KEY_SHARES_FILE: &str = "key_shares.json";
+
 #[derive(Serialize, Deserialize)]
-struct KeyShares {
-    public_key_set: PublicKeySet,
+pub(crate) struct KeyShares {
+    pub(crate) public_key_set: PublicKeySet,
     encrypted_shares: Vec<Vec<u8>>,
+    // Participant i's threshold_crypto secret key share, encrypted to their
+    // public key, for threshold decryption. Missing from older share files.
+    #[serde(default)]
+    pub(crate) encrypted_key_shares: Vec<Vec<u8>>,
+}
+
+pub(crate) fn load_key_shares(path: &Path) -> io::Result<KeyShares> {
+    let serialized = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&serialized)?)
 }
 
 pub fn run() -> io::Result<()> {
@@ -23,7 +36,7 @@ pub fn run() -> io::Result<()> {
             .with_prompt("Choose an operation:")
             .items(&selections)
             .interact()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| io::Error::other(e.to_string()))?;
 
         match selection {
             0 => shamir()?,
@@ -41,7 +54,7 @@ fn get_threshold_and_total() -> io::Result<(usize, usize)> {
         .with_prompt("Enter the threshold number (minimum number of shares required to reconstruct the secret)")
         .default(3)
         .interact()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
 
     let total_shares = Input::<usize>::new()
         .with_prompt("Enter the total number of shares to create")
@@ -54,7 +67,7 @@ fn get_threshold_and_total() -> io::Result<(usize, usize)> {
             }
         })
         .interact()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
 
     Ok((threshold, total_shares))
 }
@@ -86,7 +99,7 @@ fn shamir() -> io::Result<()> {
         let public_key: String = Input::new()
             .with_prompt(format!("Enter public key {} (hex-encoded)", i + 1))
             .interact()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| io::Error::other(e.to_string()))?;
 
         let public_key_bytes: [u8; PK_SIZE] = decode(public_key)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
@@ -96,7 +109,7 @@ fn shamir() -> io::Result<()> {
             })?;
 
         public_keys.push(
-            PublicKey::from_bytes(&public_key_bytes)
+            PublicKey::from_bytes(public_key_bytes)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
         );
     }
@@ -106,21 +119,37 @@ fn shamir() -> io::Result<()> {
         .iter()
         .zip(public_keys.iter())
         .map(|(share, pub_key)| {
-            let ciphertext = pub_key.encrypt(&Vec::from(share));
+            let ciphertext = pub_key.encrypt(Vec::from(share));
             bincode::serialize(&ciphertext).expect("Failed to serialize ciphertext")
         })
         .collect();
 
+    // Each participant also gets their share of the key set, so t+1 of them
+    // can jointly decrypt what is encrypted to the set's public key
+    let encrypted_key_shares: Vec<Vec<u8>> = public_keys
+        .iter()
+        .enumerate()
+        .map(|(i, pub_key)| {
+            let key_share = SerdeSecret(secret_key_set.secret_key_share(i));
+            let plaintext = bincode::serialize(&key_share).expect("Failed to serialize key share");
+            bincode::serialize(&pub_key.encrypt(plaintext)).expect("Failed to serialize ciphertext")
+        })
+        .collect();
+
     // Save shares
     let key_shares = KeyShares {
         public_key_set: secret_key_set.public_keys(),
         encrypted_shares,
+        encrypted_key_shares,
     };
 
     let serialized = serde_json::to_string(&key_shares)?;
-    fs::write("key_shares.json", serialized)?;
+    fs::write(KEY_SHARES_FILE, serialized)?;
 
-    println!("Shamir Secret Sharing completed. Shares saved to 'key_shares.json'.");
+    println!(
+        "Shamir Secret Sharing completed. Shares saved to '{}'.",
+        KEY_SHARES_FILE
+    );
     Ok(())
 }
 
@@ -128,14 +157,13 @@ fn threshval() -> io::Result<()> {
     println!("Starting Threshold Validation process...");
 
     // Load shares
-    let serialized = fs::read_to_string("key_shares.json")?;
-    let key_shares: KeyShares = serde_json::from_str(&serialized)?;
+    let key_shares = load_key_shares(Path::new(KEY_SHARES_FILE))?;
 
     let threshold = Input::<usize>::new()
         .with_prompt("Enter the threshold number (minimum number of shares required to reconstruct the secret)")
         .default(3)
         .interact()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
 
     // Collect shares from users
     let mut collected_shares = Vec::new();
@@ -147,7 +175,7 @@ fn threshval() -> io::Result<()> {
             ))
             .allow_empty(true)
             .interact()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| io::Error::other(e.to_string()))?;
 
         if share.is_empty() {
             if collected_shares.len() >= threshold {
@@ -172,7 +200,7 @@ fn threshval() -> io::Result<()> {
             && Confirm::new()
                 .with_prompt("Do you want to proceed with reconstruction?")
                 .interact()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+                .map_err(|e| io::Error::other(e.to_string()))?
         {
             break;
         }
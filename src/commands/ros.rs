@@ -135,7 +135,7 @@ impl Config {
         let nonce = thread_rng().gen::<[u8; NONCE_LENGTH]>();
         self.nonce = general_purpose::STANDARD.encode(nonce);
         let encrypted_password = cipher
-            .encrypt(&Nonce::from_slice(&nonce), password.as_bytes())
+            .encrypt(Nonce::from_slice(&nonce), password.as_bytes())
             .map_err(|e| format!("Encryption error: {}", e))?;
         self.encrypted_password = general_purpose::STANDARD.encode(encrypted_password);
         Ok(())
@@ -224,7 +224,7 @@ fn generate_alphanumeric_grid(password: &str, index: usize) -> (Vec<Vec<char>>,
     (grid, password_char)
 }
 
-fn display_grid(grid: &Vec<Vec<char>>) -> Result<()> {
+fn display_grid(grid: &[Vec<char>]) -> Result<()> {
     let mut stdout = io::stdout();
     let top_padding = 2;
     let left_padding = (TERMINAL_WIDTH as usize - 19) / 2;
@@ -0,0 +1,215 @@
+use std::io;
+
+use base64::{engine::general_purpose, Engine as _};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::SignatureScheme;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::fetcher::RepoInfo;
+use crate::commands::invalid_data;
+
+pub const WELL_KNOWN_PATH: &str = "/.well-known/defe.json";
+
+// Schemes tried, in order, when signing the document with the TLS key
+const SIGNATURE_SCHEMES: [SignatureScheme; 5] = [
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+#[derive(Serialize)]
+struct Identity {
+    server: &'static str,
+    version: &'static str,
+    repo: Option<RepoInfo>,
+    manifest_root_hash: Option<String>,
+    bundle_sha256: Option<String>,
+    certificate: CertificateIdentity,
+    sgx: Option<SgxIdentity>,
+    issued_at: String,
+}
+
+#[derive(Serialize)]
+struct CertificateIdentity {
+    subject: String,
+    spki_sha256: String,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(not(target_env = "sgx"), allow(dead_code))]
+struct SgxIdentity {
+    mrenclave: String,
+    mrsigner: String,
+    // Raw EREPORT; its report data starts with the SPKI SHA-256, binding the
+    // enclave measurement to the TLS key. The host's quoting enclave can turn
+    // it into a remotely verifiable quote.
+    report: String,
+}
+
+// What clients get from /.well-known/defe.json. `payload` is the base64 of the
+// identity document; `signature` is over those exact bytes, made with the TLS
+// private key, so it verifies against the public key in the certificate the
+// client was just shown.
+#[derive(Serialize)]
+struct SignedDocument {
+    payload: String,
+    signature: String,
+    algorithm: String,
+}
+
+pub fn spki_sha256(leaf: &CertificateDer<'_>) -> io::Result<[u8; 32]> {
+    let (_, certificate) = X509Certificate::from_der(leaf.as_ref())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Sha256::digest(certificate.public_key().raw).into())
+}
+
+// Builds and signs the identity document, at startup and again whenever the
+// certificate is reloaded
+pub fn well_known_document(
+    provider: &CryptoProvider,
+    certs: &[CertificateDer<'static>],
+    key: &PrivateKeyDer<'static>,
+    manifest_root_hash: Option<[u8; 32]>,
+    bundle_sha256: Option<&str>,
+) -> io::Result<Vec<u8>> {
+    let leaf = certs
+        .first()
+        .ok_or_else(|| invalid_data("Certificate chain is empty".to_string()))?;
+    let (_, certificate) =
+        X509Certificate::from_der(leaf.as_ref()).map_err(|e| invalid_data(e.to_string()))?;
+    let spki_hash = spki_sha256(leaf)?;
+
+    let identity = Identity {
+        server: "defe",
+        version: env!("CARGO_PKG_VERSION"),
+        repo: RepoInfo::load(),
+        manifest_root_hash: manifest_root_hash.map(hex::encode),
+        bundle_sha256: bundle_sha256.map(str::to_string),
+        certificate: CertificateIdentity {
+            subject: certificate.subject().to_string(),
+            spki_sha256: hex::encode(spki_hash),
+        },
+        sgx: sgx_identity(&spki_hash),
+        issued_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let payload = serde_json::to_vec(&identity)?;
+
+    let signing_key = provider
+        .key_provider
+        .load_private_key(key.clone_key())
+        .map_err(|e| invalid_data(format!("Unsupported private key: {}", e)))?;
+    let signer = signing_key
+        .choose_scheme(&SIGNATURE_SCHEMES)
+        .ok_or_else(|| invalid_data("No supported signature scheme for the TLS key".to_string()))?;
+    let signature = signer
+        .sign(&payload)
+        .map_err(|e| invalid_data(format!("Failed to sign identity document: {}", e)))?;
+
+    let document = SignedDocument {
+        payload: general_purpose::STANDARD.encode(&payload),
+        signature: general_purpose::STANDARD.encode(signature),
+        algorithm: format!("{:?}", signer.scheme()),
+    };
+    Ok(serde_json::to_vec_pretty(&document)?)
+}
+
+#[cfg(target_env = "sgx")]
+fn sgx_identity(spki_hash: &[u8; 32]) -> Option<SgxIdentity> {
+    use std::os::fortanix_sgx::arch::{ereport, Align128, Align512};
+
+    let target_info = Align512([0u8; 512]);
+    let mut report_data = Align128([0u8; 64]);
+    report_data.0[..32].copy_from_slice(spki_hash);
+    let report = ereport(&target_info, &report_data);
+
+    // Offsets of MRENCLAVE and MRSIGNER in the SGX REPORT structure
+    Some(SgxIdentity {
+        mrenclave: hex::encode(&report.0[64..96]),
+        mrsigner: hex::encode(&report.0[128..160]),
+        report: general_purpose::STANDARD.encode(report.0),
+    })
+}
+
+#[cfg(not(target_env = "sgx"))]
+fn sgx_identity(_spki_hash: &[u8; 32]) -> Option<SgxIdentity> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lc_rs::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+    use rcgen::{
+        CertificateParams, KeyPair, SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ED25519,
+    };
+    use serde_json::Value;
+
+    #[test]
+    fn signs_with_the_certificate_key() {
+        let cases: [(&SignatureAlgorithm, &dyn VerificationAlgorithm, &str); 2] = [
+            (
+                &PKCS_ECDSA_P256_SHA256,
+                &signature::ECDSA_P256_SHA256_ASN1,
+                "ECDSA_NISTP256_SHA256",
+            ),
+            (&PKCS_ED25519, &signature::ED25519, "ED25519"),
+        ];
+        for (algorithm, verification, scheme) in cases {
+            let key = KeyPair::generate_for(algorithm).unwrap();
+            let leaf = CertificateParams::new(vec!["example.com".to_string()])
+                .unwrap()
+                .self_signed(&key)
+                .unwrap();
+            let certs = vec![leaf.der().clone()];
+            let private_key = PrivateKeyDer::try_from(key.serialize_der()).unwrap();
+            let provider = rustls::crypto::aws_lc_rs::default_provider();
+
+            let document: Value = serde_json::from_slice(
+                &well_known_document(&provider, &certs, &private_key, Some([7; 32]), Some("abc"))
+                    .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(document["algorithm"], scheme);
+            let decode = |field: &str| {
+                general_purpose::STANDARD
+                    .decode(document[field].as_str().unwrap())
+                    .unwrap()
+            };
+            let payload = decode("payload");
+
+            // Verifies against the public key the certificate carries
+            let (_, certificate) = X509Certificate::from_der(&certs[0]).unwrap();
+            UnparsedPublicKey::new(
+                verification,
+                &certificate.public_key().subject_public_key.data,
+            )
+            .verify(&payload, &decode("signature"))
+            .unwrap();
+
+            let identity: Value = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(
+                identity["certificate"]["spki_sha256"],
+                hex::encode(spki_sha256(&certs[0]).unwrap())
+            );
+            assert_eq!(
+                identity["certificate"]["spki_sha256"],
+                hex::encode(Sha256::digest(certificate.public_key().raw))
+            );
+            assert_eq!(identity["manifest_root_hash"], hex::encode([7; 32]));
+            assert_eq!(identity["bundle_sha256"], "abc");
+        }
+    }
+
+    #[test]
+    fn needs_a_certificate() {
+        let key = KeyPair::generate().unwrap();
+        let private_key = PrivateKeyDer::try_from(key.serialize_der()).unwrap();
+        let provider = rustls::crypto::aws_lc_rs::default_provider();
+        assert!(well_known_document(&provider, &[], &private_key, None, None).is_err());
+    }
+}
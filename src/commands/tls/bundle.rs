@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::commands::invalid_data;
+
+use super::manifest::{list_files, sha256_hex};
+
+// A frontend build held entirely in memory. It is read from a single tar
+// archive at startup and checked against the expected SHA-256 before anything
+// in it is used, so requests never reach the host filesystem, which inside an
+// enclave is controlled by the untrusted host.
+pub struct Bundle {
+    pub files: BTreeMap<String, Vec<u8>>,
+    pub sha256: String,
+}
+
+impl Bundle {
+    pub fn load(path: &Path, expected_sha256: &str) -> io::Result<Self> {
+        let archive = fs::read(path)?;
+        let sha256 = sha256_hex(&archive);
+        if !sha256.eq_ignore_ascii_case(expected_sha256.trim()) {
+            return Err(invalid_data(format!(
+                "Bundle {} has SHA-256 {}, expected {}",
+                path.display(),
+                sha256,
+                expected_sha256.trim()
+            )));
+        }
+        Ok(Self {
+            files: unpack(&archive)?,
+            sha256,
+        })
+    }
+
+    pub fn get(&self, relative: &str) -> Option<&[u8]> {
+        self.files.get(relative).map(Vec::as_slice)
+    }
+}
+
+// Reads every regular file out of a tar archive. Directories are skipped;
+// links, special files and paths that could escape the root are rejected.
+fn unpack(archive: &[u8]) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    let mut archive = Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        match entry.header().entry_type() {
+            EntryType::Directory => continue,
+            EntryType::Regular | EntryType::Continuous => {}
+            other => {
+                return Err(invalid_data(format!(
+                    "Unsupported {:?} entry in bundle: {}",
+                    other,
+                    path.display()
+                )))
+            }
+        }
+
+        let mut parts = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+                Component::CurDir => {}
+                _ => {
+                    return Err(invalid_data(format!(
+                        "Bundle entry escapes the site root: {}",
+                        path.display()
+                    )))
+                }
+            }
+        }
+        let relative = parts.join("/");
+
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content)?;
+        if files.insert(relative.clone(), content).is_some() {
+            return Err(invalid_data(format!(
+                "Duplicate bundle entry: {}",
+                relative
+            )));
+        }
+    }
+    Ok(files)
+}
+
+// Packs every regular file under `dir` into a tar archive at `output` and
+// returns its SHA-256. Entries are sorted and carry no timestamps or owners,
+// so the same build always gives the same hash.
+pub fn create(dir: &Path, output: &Path) -> io::Result<(usize, String)> {
+    let relatives = list_files(dir)?;
+    let mut builder = Builder::new(Vec::new());
+    for relative in &relatives {
+        let content = fs::read(dir.join(relative))?;
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        builder.append_data(&mut header, relative, content.as_slice())?;
+    }
+    let archive = builder.into_inner()?;
+    fs::write(output, &archive)?;
+    Ok((relatives.len(), sha256_hex(&archive)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A tar archive of `entries`, with names written as given so that unsafe
+    // paths can be tested
+    fn archive(entries: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (name, kind, content) in entries {
+            let mut header = Header::new_ustar();
+            header.as_mut_bytes()[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn unpacks_regular_files() {
+        let files = unpack(&archive(&[
+            ("./assets/", EntryType::Directory, b""),
+            ("./assets/app.js", EntryType::Regular, b"console.log(1)"),
+            ("index.html", EntryType::Regular, b"<html></html>"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["assets/app.js", "index.html"]
+        );
+        assert_eq!(files["index.html"], b"<html></html>");
+    }
+
+    #[test]
+    fn rejects_unsafe_entries() {
+        for entries in [
+            &[("../secret", EntryType::Regular, &b"x"[..])][..],
+            &[("/etc/passwd", EntryType::Regular, b"x")],
+            &[("link", EntryType::Symlink, b"")],
+            &[
+                ("a", EntryType::Regular, b"1"),
+                ("./a", EntryType::Regular, b"2"),
+            ],
+        ] {
+            assert!(unpack(&archive(entries)).is_err(), "{:?}", entries[0].0);
+        }
+    }
+
+    #[test]
+    fn load_checks_the_hash() {
+        let path = std::env::temp_dir().join(format!("defe-bundle-{}.tar", std::process::id()));
+        let contents = archive(&[("index.html", EntryType::Regular, b"hi")]);
+        fs::write(&path, &contents).unwrap();
+        let loaded = Bundle::load(&path, &sha256_hex(&contents).to_uppercase());
+        let mismatched = Bundle::load(&path, &sha256_hex(b"other"));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().get("index.html"), Some(&b"hi"[..]));
+        assert!(mismatched.is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose,
+};
+use time::{Duration, OffsetDateTime};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::acme::write_private;
+
+pub const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca-key.pem";
+pub const CERT_FILE: &str = "fullchain.pem";
+pub const KEY_FILE: &str = "privkey.pem";
+
+const CA_COMMON_NAME: &str = "DEFE Development CA";
+const CA_VALIDITY_DAYS: i64 = 3650;
+const LEAF_VALIDITY_DAYS: i64 = 90;
+// A leaf closer than this to expiry is reissued
+const LEAF_RENEW_DAYS: i64 = 7;
+
+// Names every development certificate covers in addition to the configured ones
+const DEFAULT_SANS: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+fn cert_error(e: rcgen::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// The CA is always built from the same parameters, so re-signing it with the
+// persisted key gives an issuer that matches the CA certificate on disk.
+fn ca_params() -> io::Result<CertificateParams> {
+    let mut params = CertificateParams::new(Vec::<String>::new()).map_err(cert_error)?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, CA_COMMON_NAME);
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "DEFE local development");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(CA_VALIDITY_DAYS);
+    Ok(params)
+}
+
+// Loads the development CA key, creating the CA on first use. Returns whether
+// it was created.
+fn ensure_ca(dir: &Path) -> io::Result<(KeyPair, bool)> {
+    let ca_key_path = dir.join(CA_KEY_FILE);
+    let ca_cert_path = dir.join(CA_CERT_FILE);
+
+    if ca_key_path.exists() && ca_cert_path.exists() {
+        let pem = fs::read_to_string(&ca_key_path)?;
+        return Ok((KeyPair::from_pem(&pem).map_err(cert_error)?, false));
+    }
+
+    let ca_key = KeyPair::generate().map_err(cert_error)?;
+    let ca_cert = ca_params()?.self_signed(&ca_key).map_err(cert_error)?;
+    write_private(&ca_key_path, ca_key.serialize_pem().as_bytes())?;
+    fs::write(&ca_cert_path, ca_cert.pem())?;
+    Ok((ca_key, true))
+}
+
+// Whether the existing leaf covers exactly `sans` and isn't about to expire
+fn leaf_is_current(cert_path: &Path, sans: &[String]) -> bool {
+    let Ok(pem) = fs::read(cert_path) else {
+        return false;
+    };
+    let Some(Ok(der)) = rustls_pemfile::certs(&mut pem.as_slice()).next() else {
+        return false;
+    };
+    let Ok((_, certificate)) = X509Certificate::from_der(der.as_ref()) else {
+        return false;
+    };
+
+    let renew_at = OffsetDateTime::now_utc() + Duration::days(LEAF_RENEW_DAYS);
+    if certificate.validity().not_after.to_datetime() < renew_at {
+        return false;
+    }
+
+    let mut current: Vec<String> = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                GeneralName::IPAddress(bytes) => match bytes.len() {
+                    4 => <[u8; 4]>::try_from(*bytes)
+                        .ok()
+                        .map(|ip| std::net::IpAddr::from(ip).to_string()),
+                    16 => <[u8; 16]>::try_from(*bytes)
+                        .ok()
+                        .map(|ip| std::net::IpAddr::from(ip).to_string()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect(),
+        _ => return false,
+    };
+    let mut wanted = sans.to_vec();
+    current.sort();
+    wanted.sort();
+    current == wanted
+}
+
+// Makes sure `dir` holds a development CA and a current leaf certificate for
+// localhost plus `extra_sans`. Returns the certificate chain and key paths.
+pub fn ensure_dev_certificates(
+    dir: &Path,
+    extra_sans: &[String],
+) -> io::Result<(PathBuf, PathBuf)> {
+    fs::create_dir_all(dir)?;
+    let cert_path = dir.join(CERT_FILE);
+    let key_path = dir.join(KEY_FILE);
+
+    let mut sans: Vec<String> = DEFAULT_SANS.iter().map(|san| san.to_string()).collect();
+    for san in extra_sans {
+        if !sans.contains(san) {
+            sans.push(san.clone());
+        }
+    }
+
+    let (ca_key, ca_created) = ensure_ca(dir)?;
+    if !ca_created && key_path.exists() && leaf_is_current(&cert_path, &sans) {
+        return Ok((cert_path, key_path));
+    }
+
+    let ca_cert = ca_params()?.self_signed(&ca_key).map_err(cert_error)?;
+    let leaf_key = KeyPair::generate().map_err(cert_error)?;
+    let mut params = CertificateParams::new(sans.clone()).map_err(cert_error)?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, sans[0].as_str());
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(LEAF_VALIDITY_DAYS);
+    let leaf = params
+        .signed_by(&leaf_key, &ca_cert, &ca_key)
+        .map_err(cert_error)?;
+
+    let ca_pem = fs::read_to_string(dir.join(CA_CERT_FILE))?;
+    fs::write(&cert_path, format!("{}{}", leaf.pem(), ca_pem))?;
+    write_private(&key_path, leaf_key.serialize_pem().as_bytes())?;
+
+    println!(
+        "Generated development certificate for {} in {}",
+        sans.join(", "),
+        dir.display()
+    );
+    if ca_created {
+        print_trust_instructions(&dir.join(CA_CERT_FILE));
+    }
+    Ok((cert_path, key_path))
+}
+
+pub fn print_trust_instructions(ca_path: &Path) {
+    let ca = ca_path.display();
+    println!("A new development CA was created: {}", ca);
+    println!("Browsers will warn about the certificate until this CA is trusted:");
+    println!("  Debian/Ubuntu: sudo cp {} /usr/local/share/ca-certificates/defe-dev-ca.crt && sudo update-ca-certificates", ca);
+    println!("  Fedora/RHEL:   sudo cp {} /etc/pki/ca-trust/source/anchors/defe-dev-ca.pem && sudo update-ca-trust", ca);
+    println!("  macOS:         sudo security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain {}", ca);
+    println!("  Firefox:       Settings > Privacy & Security > Certificates > View Certificates > Authorities > Import");
+    println!("  curl/CI:       curl --cacert {} https://localhost/", ca);
+    println!(
+        "Keep ca-key.pem private; anyone holding it can impersonate any site to this machine."
+    );
+}
@@ -0,0 +1,293 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::watch;
+use tokio::time::{timeout, Duration};
+
+use super::proxy::{self, Route};
+use super::site::Response;
+use super::{stopping, App};
+
+// Limits that keep a single client from holding unbounded memory
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+// How long an idle keep-alive connection is kept open between requests
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Request {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    keep_alive: bool,
+    content_length: u64,
+    // Any Transfer-Encoding, whose body only the proxy relays
+    chunked: bool,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn is_upgrade(&self) -> bool {
+        self.header("upgrade").is_some()
+            && self.header("connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+            })
+    }
+}
+
+// Serves HTTP/1.x requests on a connection until the client closes it, asks for
+// `Connection: close`, or the server starts draining.
+pub async fn serve<T>(
+    io: T,
+    app: Arc<App>,
+    peer: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(io);
+
+    loop {
+        // An idle connection is closed on shutdown instead of holding up the drain
+        let request = tokio::select! {
+            request = timeout(KEEP_ALIVE_TIMEOUT, read_request(&mut reader)) => match request {
+                Ok(request) => request?,
+                Err(_) => None,
+            },
+            _ = stopping(&mut shutdown) => None,
+        };
+        let Some(request) = request else {
+            break;
+        };
+
+        // A proxied request takes over the connection until the upstream is done
+        if let Some(route) = app.proxy.route(&request.target) {
+            let head = upstream_request_head(&request, route, peer);
+            if let Err(e) = proxy::forward_http1(&mut reader, head, route).await {
+                eprintln!("Error proxying {}: {}", request.target, e);
+                let response = Response::text(e.status(), &e.to_string());
+                write_response(reader.get_mut(), &response, request.method == "HEAD", false)
+                    .await?;
+            }
+            break;
+        }
+
+        // The static site doesn't decode chunked bodies. Left unread, one would
+        // be parsed as the next request, so the connection is closed instead.
+        if request.chunked {
+            let response = Response::text(411, "Request bodies must have a Content-Length");
+            write_response(reader.get_mut(), &response, request.method == "HEAD", false).await?;
+            break;
+        }
+
+        // Request bodies aren't used by the static site, but must be consumed
+        // before the next request on the same connection can be read.
+        tokio::io::copy(
+            &mut (&mut reader).take(request.content_length),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+
+        let response = app.respond(&request.method, &request.target).await;
+        let keep_alive = request.keep_alive && !*shutdown.borrow();
+        write_response(
+            reader.get_mut(),
+            &response,
+            request.method == "HEAD",
+            keep_alive,
+        )
+        .await?;
+
+        if !keep_alive {
+            break;
+        }
+    }
+
+    // Send close_notify
+    reader.into_inner().shutdown().await
+}
+
+async fn read_line<R>(reader: &mut R) -> io::Result<String>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH).read_line(&mut line).await?;
+    if !line.is_empty() && !line.ends_with('\n') && line.len() as u64 >= MAX_LINE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HTTP header line too long",
+        ));
+    }
+    Ok(line)
+}
+
+// Reads the request line and headers. Returns None when the client closed the
+// connection before sending another request.
+async fn read_request<R>(reader: &mut R) -> io::Result<Option<Request>>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let request_line = read_line(reader).await?;
+    if request_line.is_empty() {
+        return Ok(None);
+    }
+
+    let request_parts: Vec<&str> = request_line.split_whitespace().collect();
+    if request_parts.len() < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid HTTP request",
+        ));
+    }
+    let version = request_parts.get(2).copied().unwrap_or("HTTP/1.0");
+
+    let mut request = Request {
+        method: request_parts[0].to_string(),
+        target: request_parts[1].to_string(),
+        version: version.to_string(),
+        headers: Vec::new(),
+        keep_alive: version == "HTTP/1.1",
+        content_length: 0,
+        chunked: false,
+    };
+
+    for _ in 0..MAX_HEADERS {
+        let line = read_line(reader).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(Some(request));
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        request
+            .headers
+            .push((name.trim().to_string(), value.to_string()));
+        if name.eq_ignore_ascii_case("connection") {
+            if value.eq_ignore_ascii_case("close") {
+                request.keep_alive = false;
+            } else if value.eq_ignore_ascii_case("keep-alive") {
+                request.keep_alive = true;
+            }
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            request.chunked = true;
+        } else if name.eq_ignore_ascii_case("content-length") {
+            request.content_length = value.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length")
+            })?;
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Too many HTTP headers",
+    ))
+}
+
+// The request head sent upstream. Hop-by-hop headers are dropped, except the
+// body framing and WebSocket upgrade headers, since everything after the head is
+// relayed unchanged.
+fn upstream_request_head(request: &Request, route: &Route, peer: SocketAddr) -> String {
+    let upgrade = request.is_upgrade();
+    let mut head = format!(
+        "{} {} {}\r\n",
+        request.method,
+        route.upstream_target(&request.target),
+        request.version
+    );
+    for (name, value) in &request.headers {
+        let keep = name.eq_ignore_ascii_case("transfer-encoding")
+            || (upgrade && name.eq_ignore_ascii_case("upgrade"));
+        if (proxy::is_hop_by_hop(name) && !keep) || proxy::is_forwarded(name) {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    for (name, value) in proxy::forwarded_headers(peer, request.header("host")) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(if upgrade {
+        "Connection: upgrade\r\n\r\n"
+    } else {
+        "Connection: close\r\n\r\n"
+    });
+    head
+}
+
+async fn write_response<W>(
+    writer: &mut W,
+    response: &Response,
+    head_only: bool,
+    keep_alive: bool,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    writer.write_all(head.as_bytes()).await?;
+    if !head_only {
+        writer.write_all(&response.body).await?;
+    }
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(raw: &str) -> io::Result<Option<Request>> {
+        read_request(&mut BufReader::new(raw.as_bytes())).await
+    }
+
+    #[tokio::test]
+    async fn reads_body_framing() {
+        let request = parse("POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.content_length, 5);
+        assert!(!request.chunked);
+        assert!(request.keep_alive);
+
+        let request = parse("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(request.chunked);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_requests() {
+        assert!(parse("").await.unwrap().is_none());
+        assert!(parse("GET\r\n\r\n").await.is_err());
+        assert!(parse("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n")
+            .await
+            .is_err());
+        let long = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_LINE_LENGTH as usize)
+        );
+        assert!(parse(&long).await.is_err());
+    }
+}
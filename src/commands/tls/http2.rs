@@ -0,0 +1,290 @@
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+use super::proxy::{self, ProxyError, Route};
+use super::site::Response;
+use super::{stopping, App};
+
+// Largest request body buffered for a proxied HTTP/2 request
+const MAX_PROXY_BODY: usize = 16 * 1024 * 1024;
+
+fn h2_error(e: h2::Error) -> io::Error {
+    if e.is_io() {
+        e.into_io().unwrap()
+    } else {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+// Serves HTTP/2 streams on a connection. On shutdown a GOAWAY is sent and the
+// streams already in progress are allowed to finish.
+pub async fn serve<T>(
+    io: T,
+    app: Arc<App>,
+    peer: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut connection = h2::server::handshake(io).await.map_err(h2_error)?;
+    let mut draining = false;
+
+    loop {
+        let next = if draining {
+            connection.accept().await
+        } else {
+            tokio::select! {
+                next = connection.accept() => next,
+                _ = stopping(&mut shutdown) => {
+                    connection.graceful_shutdown();
+                    draining = true;
+                    continue;
+                }
+            }
+        };
+
+        match next {
+            Some(Ok((request, respond))) => {
+                let app = Arc::clone(&app);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_request(request, respond, &app, peer).await {
+                        eprintln!("Error in HTTP/2 stream: {:?}", e);
+                    }
+                });
+            }
+            Some(Err(e)) => return Err(h2_error(e)),
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_request(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    app: &App,
+    peer: SocketAddr,
+) -> io::Result<()> {
+    let target = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/")
+        .to_string();
+    let head_only = request.method() == http::Method::HEAD;
+
+    if let Some(route) = app.proxy.route(&target) {
+        return match forward(request, route, app.proxy.client(), peer).await {
+            Ok(upstream) => send_upstream_response(&mut respond, upstream, head_only).await,
+            Err(e) => {
+                eprintln!("Error proxying {}: {}", target, e);
+                let response = Response::text(e.status(), &e.to_string());
+                send_response(&mut respond, response, head_only).await
+            }
+        };
+    }
+
+    let response = app.respond(request.method().as_str(), &target).await;
+    send_response(&mut respond, response, head_only).await
+}
+
+// Forwards an HTTP/2 request to the upstream over HTTP/1.1. The request body is
+// buffered; the response body is streamed back by the caller.
+async fn forward(
+    request: http::Request<RecvStream>,
+    route: &Route,
+    client: &reqwest::Client,
+    peer: SocketAddr,
+) -> Result<reqwest::Response, ProxyError> {
+    if !route.is_healthy() {
+        return Err(ProxyError::Unavailable);
+    }
+
+    let (parts, mut body) = request.into_parts();
+    let target = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+        .map_err(|e| ProxyError::BadGateway(e.to_string()))?;
+
+    let mut upstream = client.request(method, route.url(target));
+    for (name, value) in parts.headers.iter() {
+        if proxy::is_hop_by_hop(name.as_str()) || proxy::is_forwarded(name.as_str()) {
+            continue;
+        }
+        upstream = upstream.header(name.as_str(), value.as_bytes());
+    }
+    let host = parts.uri.authority().map(|authority| authority.as_str());
+    if let Some(host) = host {
+        upstream = upstream.header("host", host);
+    }
+    for (name, value) in proxy::forwarded_headers(peer, host) {
+        upstream = upstream.header(name, value);
+    }
+
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| ProxyError::BadGateway(e.to_string()))?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        if buffer.len() + chunk.len() > MAX_PROXY_BODY {
+            return Err(ProxyError::PayloadTooLarge);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    match timeout(route.timeout(), upstream.body(buffer).send()).await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(e)) if e.is_timeout() => Err(ProxyError::Timeout),
+        Ok(Err(e)) => Err(ProxyError::BadGateway(e.to_string())),
+        Err(_) => Err(ProxyError::Timeout),
+    }
+}
+
+async fn send_upstream_response(
+    respond: &mut SendResponse<Bytes>,
+    mut upstream: reqwest::Response,
+    head_only: bool,
+) -> io::Result<()> {
+    let mut head = http::Response::builder().status(upstream.status().as_u16());
+    for (name, value) in upstream.headers() {
+        if !proxy::is_hop_by_hop(name.as_str()) {
+            head = head.header(name.as_str(), value.as_bytes());
+        }
+    }
+    let head = head
+        .body(())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut stream = respond.send_response(head, head_only).map_err(h2_error)?;
+    if head_only {
+        return Ok(());
+    }
+    while let Some(chunk) = upstream.chunk().await.map_err(io::Error::other)? {
+        send_body(&mut stream, chunk, false).await?;
+    }
+    stream.send_data(Bytes::new(), true).map_err(h2_error)
+}
+
+async fn send_response(
+    respond: &mut SendResponse<Bytes>,
+    response: Response,
+    head_only: bool,
+) -> io::Result<()> {
+    let head = http::Response::builder()
+        .status(response.status)
+        .header(http::header::CONTENT_TYPE, response.content_type)
+        .header(http::header::CONTENT_LENGTH, response.body.len())
+        .body(())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let end_of_stream = head_only || response.body.is_empty();
+    let mut stream = respond
+        .send_response(head, end_of_stream)
+        .map_err(h2_error)?;
+    if end_of_stream {
+        return Ok(());
+    }
+
+    send_body(&mut stream, Bytes::from(response.body), true).await
+}
+
+// Sends data as flow control allows instead of buffering it all in h2
+async fn send_body(
+    stream: &mut SendStream<Bytes>,
+    mut body: Bytes,
+    end_of_stream: bool,
+) -> io::Result<()> {
+    while !body.is_empty() {
+        stream.reserve_capacity(body.len());
+        let capacity = match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(capacity) => capacity.map_err(h2_error)?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "HTTP/2 stream closed before the response was sent",
+                ))
+            }
+        };
+        let chunk = body.split_to(capacity.min(body.len()));
+        stream
+            .send_data(chunk, end_of_stream && body.is_empty())
+            .map_err(h2_error)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{app, workspace, INDEX};
+    use super::*;
+    use crate::config::TlsSettings;
+
+    // Sends one request over an in-memory connection to `serve`. Returns the
+    // status and body.
+    async fn request(app: Arc<App>, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let peer = "127.0.0.1:40000".parse().unwrap();
+        let server = tokio::spawn(serve(server_io, app, peer, shutdown_rx));
+
+        let (mut client, connection) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(connection);
+        let request = http::Request::builder()
+            .method(method)
+            .uri(format!("https://localhost{}", path))
+            .body(())
+            .unwrap();
+        let (response, mut send) = client.send_request(request, body.is_empty()).unwrap();
+        if !body.is_empty() {
+            send.send_data(Bytes::copy_from_slice(body), true).unwrap();
+        }
+
+        let response = response.await.unwrap();
+        let status = response.status().as_u16();
+        let mut received = Vec::new();
+        let mut stream = response.into_body();
+        while let Some(chunk) = stream.data().await {
+            let chunk = chunk.unwrap();
+            let _ = stream.flow_control().release_capacity(chunk.len());
+            received.extend_from_slice(&chunk);
+        }
+        server.abort();
+        (status, received)
+    }
+
+    #[tokio::test]
+    async fn serves_static_files() {
+        let workspace = workspace("h2");
+        let (_, app) = app(&workspace, &TlsSettings::default());
+        let app = Arc::new(app);
+
+        let (status, body) = request(Arc::clone(&app), "GET", "/", b"").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, INDEX.as_bytes());
+
+        let (status, body) = request(Arc::clone(&app), "HEAD", "/index.html", b"").await;
+        assert_eq!(status, 200);
+        assert!(body.is_empty());
+
+        let (status, _) = request(Arc::clone(&app), "GET", "/missing.js", b"").await;
+        assert_eq!(status, 404);
+
+        // Static paths take no request bodies
+        let (status, _) = request(app, "POST", "/", b"data").await;
+        assert_eq!(status, 405);
+    }
+}
@@ -0,0 +1,148 @@
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::{Domain, Socket, Type};
+use tokio::net::TcpListener;
+
+// systemd passes activated sockets starting at this descriptor
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+// Sockets handed over by systemd socket activation (`LISTEN_PID`/`LISTEN_FDS`),
+// if this process is the one they were meant for. The variables are removed so
+// child processes don't try to use the same descriptors, which is only sound
+// while the process has a single thread: call this before starting the runtime.
+#[cfg(unix)]
+pub fn activated_listeners() -> io::Result<Vec<std::net::TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if !for_us {
+        return Ok(Vec::new());
+    }
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd guarantees these descriptors are open, and they
+            // are only taken over once because the variables are now unset
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.local_addr().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Activated descriptor {} is not a TCP socket: {}", fd, e),
+                )
+            })?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn activated_listeners() -> io::Result<Vec<std::net::TcpListener>> {
+    Ok(Vec::new())
+}
+
+// Binds one address. IPv6 sockets accept IPv4 connections as well unless
+// `dual_stack` is off, in which case an IPv4 address can be listed separately
+// with the same port.
+fn bind(address: &str, dual_stack: bool) -> io::Result<std::net::TcpListener> {
+    let address: SocketAddr = address.parse().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid listen address '{}': {}", address, e),
+        )
+    })?;
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket
+        .bind(&address.into())
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to bind {}: {}", address, e)))?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+// The sockets to accept connections on: the `activated` ones passed in by
+// systemd when the server was socket-activated, otherwise `addresses` bound
+// here. Must be called from within the runtime.
+pub fn listeners(
+    activated: Vec<std::net::TcpListener>,
+    addresses: &[String],
+    dual_stack: bool,
+) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = activated;
+    if listeners.is_empty() {
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No listen addresses configured",
+            ));
+        }
+        listeners = addresses
+            .iter()
+            .map(|address| bind(address, dual_stack))
+            .collect::<io::Result<_>>()?;
+    } else {
+        println!(
+            "Using {} socket(s) from systemd socket activation",
+            listeners.len()
+        );
+    }
+
+    listeners
+        .into_iter()
+        .map(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_addresses() {
+        let listener = bind("127.0.0.1:0", false).unwrap();
+        assert_ne!(listener.local_addr().unwrap().port(), 0);
+
+        for address in ["localhost:443", "127.0.0.1", "127.0.0.1:99999", ""] {
+            let error = bind(address, false).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn prefers_activated_sockets() {
+        let bound = listeners(
+            Vec::new(),
+            &["127.0.0.1:0".to_string(), "127.0.0.1:0".to_string()],
+            false,
+        )
+        .unwrap();
+        assert_eq!(bound.len(), 2);
+
+        // The configured addresses aren't bound when systemd passed sockets
+        let activated = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = activated.local_addr().unwrap();
+        let taken = listeners(vec![activated], &["invalid".to_string()], false).unwrap();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].local_addr().unwrap(), address);
+
+        assert!(listeners(Vec::new(), &[], false).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use threshold_crypto::{PublicKey, Signature, PK_SIZE, SIG_SIZE};
+
+use crate::commands::invalid_data;
+
+// Maps every file of a frontend build to its SHA-256, so the server only hands
+// out the exact bytes that were approved. The optional signature is a
+// threshold_crypto signature over the root hash, e.g. combined from governance
+// signature shares.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetManifest {
+    pub files: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+impl AssetManifest {
+    // Hashes every regular file under `dir`
+    pub fn generate(dir: &Path) -> io::Result<Self> {
+        let mut files = BTreeMap::new();
+        for relative in list_files(dir)? {
+            let content = fs::read(dir.join(&relative))?;
+            files.insert(relative, sha256_hex(&content));
+        }
+        Ok(Self {
+            files,
+            signature: None,
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid asset manifest {}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(self)?;
+        fs::write(path, serialized)
+    }
+
+    // SHA-256 of the manifest in `sha256sum` format, one "<hash>  <path>" line
+    // per file in path order. This is what gets signed and published.
+    pub fn root_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for (path, hash) in &self.files {
+            hasher.update(format!("{}  {}\n", hash, path).as_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    pub fn expected_hash(&self, relative: &str) -> Option<&str> {
+        self.files.get(relative).map(String::as_str)
+    }
+
+    // Checks the signature against a hex-encoded threshold_crypto public key
+    pub fn verify_signature(&self, public_key_hex: &str) -> io::Result<()> {
+        let public_key_bytes: [u8; PK_SIZE] = hex::decode(public_key_hex.trim())
+            .map_err(|e| invalid_data(format!("Invalid manifest public key: {}", e)))?
+            .try_into()
+            .map_err(|_| invalid_data("Invalid manifest public key length".to_string()))?;
+        let public_key = PublicKey::from_bytes(public_key_bytes)
+            .map_err(|e| invalid_data(format!("Invalid manifest public key: {}", e)))?;
+
+        let signature_hex = self
+            .signature
+            .as_deref()
+            .ok_or_else(|| invalid_data("Asset manifest is not signed".to_string()))?;
+        let signature_bytes: [u8; SIG_SIZE] = hex::decode(signature_hex.trim())
+            .map_err(|e| invalid_data(format!("Invalid manifest signature: {}", e)))?
+            .try_into()
+            .map_err(|_| invalid_data("Invalid manifest signature length".to_string()))?;
+        let signature = Signature::from_bytes(signature_bytes)
+            .map_err(|e| invalid_data(format!("Invalid manifest signature: {}", e)))?;
+
+        if public_key.verify(&signature, self.root_hash()) {
+            Ok(())
+        } else {
+            Err(invalid_data(
+                "Asset manifest signature does not match its contents".to_string(),
+            ))
+        }
+    }
+
+    // Lists every difference between `dir` and the manifest: files that are
+    // missing, modified, or not covered by the manifest at all.
+    pub fn verify_directory(&self, dir: &Path) -> io::Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let on_disk = list_files(dir)?;
+
+        for (relative, expected) in &self.files {
+            match fs::read(dir.join(relative)) {
+                Ok(content) if sha256_hex(&content) == *expected => {}
+                Ok(_) => problems.push(format!("modified: {}", relative)),
+                Err(_) => problems.push(format!("missing: {}", relative)),
+            }
+        }
+        for relative in on_disk {
+            if !self.files.contains_key(&relative) {
+                problems.push(format!("not in manifest: {}", relative));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    // Same as `verify_directory`, for files already held in memory
+    pub fn verify_files(&self, files: &BTreeMap<String, Vec<u8>>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for (relative, expected) in &self.files {
+            match files.get(relative) {
+                Some(content) if sha256_hex(content) == *expected => {}
+                Some(_) => problems.push(format!("modified: {}", relative)),
+                None => problems.push(format!("missing: {}", relative)),
+            }
+        }
+        for relative in files.keys() {
+            if !self.files.contains_key(relative) {
+                problems.push(format!("not in manifest: {}", relative));
+            }
+        }
+
+        problems
+    }
+}
+
+// Relative paths, with '/' separators, of every regular file under `dir`
+pub fn list_files(dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            let metadata = fs::metadata(&path)?;
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() {
+                let relative = path
+                    .strip_prefix(dir)
+                    .map_err(io::Error::other)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if relative.contains('\n') {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("File name contains a newline: {:?}", relative),
+                    ));
+                }
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use threshold_crypto::SecretKey;
+
+    fn manifest() -> AssetManifest {
+        let mut files = BTreeMap::new();
+        files.insert("index.html".to_string(), sha256_hex(b"<html></html>"));
+        files.insert("app.js".to_string(), sha256_hex(b"run()"));
+        AssetManifest {
+            files,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn root_hash_is_the_sha256sum_listing() {
+        let listing = format!(
+            "{}  app.js\n{}  index.html\n",
+            sha256_hex(b"run()"),
+            sha256_hex(b"<html></html>")
+        );
+        assert_eq!(
+            hex::encode(manifest().root_hash()),
+            sha256_hex(listing.as_bytes())
+        );
+    }
+
+    #[test]
+    fn reports_every_difference() {
+        let mut files = BTreeMap::new();
+        files.insert("index.html".to_string(), b"<html>changed</html>".to_vec());
+        files.insert("extra.css".to_string(), Vec::new());
+        assert_eq!(
+            manifest().verify_files(&files),
+            [
+                "missing: app.js",
+                "modified: index.html",
+                "not in manifest: extra.css"
+            ]
+        );
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let key = SecretKey::random();
+        let public_key_hex = hex::encode(key.public_key().to_bytes());
+
+        let mut signed = manifest();
+        assert!(signed.verify_signature(&public_key_hex).is_err());
+        signed.signature = Some(hex::encode(key.sign(signed.root_hash()).to_bytes()));
+        signed.verify_signature(&public_key_hex).unwrap();
+
+        signed.files.remove("app.js");
+        assert!(signed.verify_signature(&public_key_hex).is_err());
+        assert!(manifest().verify_signature("00").is_err());
+    }
+}
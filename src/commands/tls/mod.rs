@@ -0,0 +1,716 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use dialoguer::{Confirm, Input};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateDer;
+use rustls::server::ResolvesServerCertUsingSni;
+use rustls::ServerConfig;
+use rustls_pemfile::certs;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::TlsAcceptor;
+
+use crate::commands::keystore;
+use crate::config::{Config, DevSettings, ManifestSettings, TlsSettings, CONFIG_FILE};
+
+mod attestation;
+mod bundle;
+mod devcert;
+mod http1;
+mod http2;
+mod listen;
+mod manifest;
+mod ocsp;
+mod policy;
+mod proxy;
+mod site;
+mod sri;
+
+use bundle::Bundle;
+use manifest::AssetManifest;
+use ocsp::Stapler;
+use proxy::Proxy;
+use site::{Response, Site};
+
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP11: &[u8] = b"http/1.1";
+
+// Function to load certificates from a file
+fn load_certs(filename: &PathBuf) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certfile = fs::File::open(filename)?;
+    let mut reader = BufReader::new(certfile);
+    certs(&mut reader).collect()
+}
+
+// Loads the asset manifest and checks its signature, then checks the site's
+// files against it with `check`. Any mismatch keeps the server from starting.
+fn load_verified_manifest(
+    settings: &ManifestSettings,
+    source: &str,
+    check: impl FnOnce(&AssetManifest) -> io::Result<Vec<String>>,
+) -> io::Result<AssetManifest> {
+    let manifest_path = env::current_dir()?.join(&settings.path);
+    if !manifest_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Asset manifest {} not found. Please generate it first.",
+                manifest_path.display()
+            ),
+        ));
+    }
+    let manifest = AssetManifest::load(&manifest_path)?;
+
+    match &settings.public_key {
+        Some(public_key) => {
+            manifest.verify_signature(public_key)?;
+            println!("Asset manifest signature verified.");
+        }
+        None => println!("Warning: no manifest public key configured, signature not checked."),
+    }
+
+    let problems = check(&manifest)?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("  {}", problem);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} does not match the asset manifest ({} problem(s))",
+                source,
+                problems.len()
+            ),
+        ));
+    }
+
+    println!(
+        "Verified {} file(s) against asset manifest {} (root hash {})",
+        manifest.files.len(),
+        manifest_path.display(),
+        hex::encode(manifest.root_hash())
+    );
+    Ok(manifest)
+}
+
+// Loads the configured bundle into memory, or asks for a project directory to
+// serve from disk. Returns the site and the root hash of the manifest it was
+// verified against, if any.
+fn load_site(settings: &TlsSettings, current_dir: &Path) -> io::Result<(Site, Option<[u8; 32]>)> {
+    if let Some(bundle_settings) = &settings.bundle {
+        if bundle_settings.sha256.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls.bundle.sha256 is not set. Pack the bundle first and configure its hash.",
+            ));
+        }
+        let bundle = Bundle::load(
+            &current_dir.join(&bundle_settings.path),
+            &bundle_settings.sha256,
+        )?;
+        println!(
+            "Loaded {} file(s) from bundle {} (SHA-256 {})",
+            bundle.files.len(),
+            bundle_settings.path,
+            bundle.sha256
+        );
+
+        let manifest = match &settings.manifest {
+            Some(manifest_settings) => Some(load_verified_manifest(
+                manifest_settings,
+                "Bundle",
+                |manifest| Ok(manifest.verify_files(&bundle.files)),
+            )?),
+            None => None,
+        };
+        let site = Site::from_bundle(bundle_settings.path.clone(), bundle);
+        return Ok((site, manifest.as_ref().map(AssetManifest::root_hash)));
+    }
+
+    // Ask for the project directory name
+    let project_dir = Input::<String>::new()
+        .with_prompt("Enter the name of the project directory to serve")
+        .interact_text()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    // Check if the project directory exists
+    let project_path = current_dir.join(&project_dir);
+    if !project_path.exists() || !project_path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Project directory '{}' not found", project_dir),
+        ));
+    }
+
+    let manifest = match &settings.manifest {
+        Some(manifest_settings) => Some(load_verified_manifest(
+            manifest_settings,
+            "Project directory",
+            |manifest| manifest.verify_directory(&project_path),
+        )?),
+        None => None,
+    };
+    let root_hash = manifest.as_ref().map(AssetManifest::root_hash);
+    Ok((Site::new(project_path, manifest), root_hash))
+}
+
+// Rewrites the HTML pages of a build directory with Subresource Integrity
+// attributes, so the hashes taken afterwards cover the final pages
+fn add_integrity_attributes(project_path: &Path) -> io::Result<()> {
+    let changed = sri::inject_directory(project_path)?;
+    if changed.is_empty() {
+        println!("Subresource Integrity attributes are up to date.");
+    } else {
+        println!(
+            "Added Subresource Integrity attributes to {} page(s): {}",
+            changed.len(),
+            changed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// Hashes a build directory into an asset manifest for `tls.manifest`
+pub fn generate_manifest() -> io::Result<()> {
+    let tls = Config::load()?.tls;
+    let sri_enabled = tls.sri;
+    let settings = tls.manifest.unwrap_or_default();
+
+    let project_dir = Input::<String>::new()
+        .with_prompt("Enter the name of the project directory to hash")
+        .interact_text()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let project_path = env::current_dir()?.join(&project_dir);
+    if !project_path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Project directory '{}' not found", project_dir),
+        ));
+    }
+
+    if sri_enabled {
+        add_integrity_attributes(&project_path)?;
+    }
+    let manifest = AssetManifest::generate(&project_path)?;
+    manifest.save(Path::new(&settings.path))?;
+
+    println!(
+        "Asset manifest with {} file(s) written to {}",
+        manifest.files.len(),
+        settings.path
+    );
+    println!("Root hash: {}", hex::encode(manifest.root_hash()));
+    println!("Sign the root hash and add it as \"signature\" before serving with a public key.");
+    Ok(())
+}
+
+// Packs a build directory into the archive served from memory by `tls.bundle`
+pub fn pack_bundle() -> io::Result<()> {
+    let tls = Config::load()?.tls;
+    let sri_enabled = tls.sri;
+    let settings = tls.bundle.unwrap_or_default();
+
+    let project_dir = Input::<String>::new()
+        .with_prompt("Enter the name of the project directory to pack")
+        .interact_text()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let project_path = env::current_dir()?.join(&project_dir);
+    if !project_path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Project directory '{}' not found", project_dir),
+        ));
+    }
+
+    if sri_enabled {
+        add_integrity_attributes(&project_path)?;
+    }
+    let (count, sha256) = bundle::create(&project_path, Path::new(&settings.path))?;
+    println!("Bundle with {} file(s) written to {}", count, settings.path);
+    println!("SHA-256: {}", sha256);
+    println!(
+        "Set this as \"sha256\" under tls.bundle in {} to serve it.",
+        CONFIG_FILE
+    );
+    Ok(())
+}
+
+// Where the served certificate is loaded from, and what else goes into the
+// identity document, so both can be rebuilt when it is reloaded
+struct CertificateFiles {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    // The key is looked up again in the workspace on reload, since renewal
+    // may have moved it into the key store
+    workspace_key: bool,
+    provider: Arc<CryptoProvider>,
+    manifest_root_hash: Option<[u8; 32]>,
+}
+
+// Everything a connection needs to answer requests
+struct App {
+    site: Site,
+    proxy: Proxy,
+    stapler: Arc<Stapler>,
+    certificate: CertificateFiles,
+    // Signed /.well-known/defe.json document
+    well_known: RwLock<Vec<u8>>,
+    metrics_path: Option<String>,
+}
+
+impl App {
+    // Loads the certificate and key again, e.g. after renewal. On failure the
+    // current ones stay in use.
+    fn reload_certificate(&self) -> io::Result<()> {
+        let files = &self.certificate;
+        let key_path = match files.cert_path.parent() {
+            Some(dir) if files.workspace_key => keystore::key_path(dir),
+            _ => files.key_path.clone(),
+        };
+        let certs = load_certs(&files.cert_path)?;
+        let key = keystore::load_key(&key_path)?;
+        let well_known = attestation::well_known_document(
+            &files.provider,
+            &certs,
+            &key,
+            files.manifest_root_hash,
+            self.site.bundle_sha256(),
+        )?;
+        self.stapler.reload(certs, &key)?;
+        *self.well_known.write().unwrap_or_else(|e| e.into_inner()) = well_known;
+        Ok(())
+    }
+
+    // Answers requests that aren't proxied
+    async fn respond(&self, method: &str, target: &str) -> Response {
+        let path = target.split('?').next().unwrap_or_default();
+        if path == attestation::WELL_KNOWN_PATH && (method == "GET" || method == "HEAD") {
+            return Response {
+                status: 200,
+                content_type: "application/json",
+                body: self
+                    .well_known
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+            };
+        }
+        if self.metrics_path.as_deref() == Some(path) && (method == "GET" || method == "HEAD") {
+            return Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4; charset=utf-8",
+                body: self.stapler.metrics().into_bytes(),
+            };
+        }
+        self.site.respond(method, target).await
+    }
+}
+
+// Function to handle client connections. The protocol is picked by ALPN:
+// HTTP/2 when the client offered `h2`, HTTP/1.1 otherwise.
+async fn handle_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    acceptor: TlsAcceptor,
+    app: Arc<App>,
+    shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let tls = acceptor.accept(stream).await?;
+    if tls.get_ref().1.alpn_protocol() == Some(ALPN_H2) {
+        http2::serve(tls, app, peer, shutdown).await
+    } else {
+        http1::serve(tls, app, peer, shutdown).await
+    }
+}
+
+// Resolves once the server has started draining
+async fn stopping(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
+
+// Resolves once the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Reloads the certificate each time the process receives SIGHUP, as sent by
+// `defe certs renew`. Decrypting the key can't prompt here, so an encrypted
+// store needs DEFE_KEY_PASSPHRASE.
+#[cfg(unix)]
+fn spawn_reload_on_hangup(app: &Arc<App>) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let app = Arc::clone(app);
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let reloading = Arc::clone(&app);
+            match tokio::task::spawn_blocking(move || reloading.reload_certificate()).await {
+                Ok(Ok(())) => println!("Certificate reloaded. {}", app.stapler.describe()),
+                Ok(Err(e)) => eprintln!(
+                    "Failed to reload the certificate, keeping the current one: {}",
+                    e
+                ),
+                Err(e) => eprintln!("Certificate reload failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_on_hangup(_app: &Arc<App>) -> io::Result<()> {
+    Ok(())
+}
+
+// Records the server's process ID for as long as it runs. Removed however
+// `serve` returns, so an error or panic doesn't leave a stale PID for the
+// renewal daemon and hooks to signal.
+struct PidFile(PathBuf);
+
+impl PidFile {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        fs::write(&path, format!("{}\n", std::process::id()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Accepts connections until a shutdown signal arrives, then stops accepting and
+// gives in-flight connections up to `shutdown_grace_secs` to finish.
+async fn serve(
+    config: Arc<ServerConfig>,
+    app: App,
+    settings: &TlsSettings,
+    activated: Vec<std::net::TcpListener>,
+    listen: &[String],
+) -> io::Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+    let app = Arc::new(app);
+    app.proxy.spawn_health_checks();
+    app.stapler.spawn_refresh();
+    spawn_reload_on_hangup(&app)?;
+
+    // Accept on every socket in its own task and funnel the connections here
+    let (accepted_tx, mut accepted_rx) = mpsc::channel::<(TcpStream, SocketAddr)>(64);
+    let mut acceptors = JoinSet::new();
+    for listener in listen::listeners(activated, listen, settings.dual_stack)? {
+        println!(
+            "HTTPS server started. Listening on {}",
+            listener.local_addr()?
+        );
+        let accepted_tx = accepted_tx.clone();
+        acceptors.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok(accepted) => {
+                        if accepted_tx.send(accepted).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Error accepting connection: {}", e),
+                }
+            }
+        });
+    }
+    drop(accepted_tx);
+    println!("Serving project from {}", app.site.describe());
+    println!("{}", app.stapler.describe());
+    for route in app.proxy.describe() {
+        println!("Proxying {}", route);
+    }
+    println!("Press Ctrl-C or send SIGTERM to stop the server, SIGHUP to reload the certificate.");
+
+    let _pid_file = settings
+        .pid_file
+        .as_ref()
+        .map(|path| PidFile::create(PathBuf::from(path)))
+        .transpose()?;
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    // Accept incoming connections
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some((stream, peer)) = accepted_rx.recv() => {
+                println!("New connection accepted");
+                let acceptor = acceptor.clone();
+                let app = Arc::clone(&app);
+                let stop_rx = stop_rx.clone();
+
+                // Handle each client connection in a separate task
+                connections.spawn(async move {
+                    if let Err(e) = handle_client(stream, peer, acceptor, app, stop_rx).await {
+                        eprintln!("Error in client connection: {:?}", e);
+                    }
+                    println!("Connection closed");
+                });
+            }
+            // Reap finished connections so the set only holds in-flight ones
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+
+    // Stop accepting new connections before draining
+    acceptors.shutdown().await;
+    let _ = stop_tx.send(true);
+    println!(
+        "Shutdown signal received. Draining {} in-flight connection(s)...",
+        connections.len()
+    );
+
+    let grace = Duration::from_secs(settings.shutdown_grace_secs);
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if timeout(grace, drain).await.is_err() {
+        eprintln!(
+            "Grace period of {}s elapsed, aborting {} connection(s)",
+            settings.shutdown_grace_secs,
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+
+    println!("HTTPS server stopped.");
+    Ok(())
+}
+
+// Loads the certificate and key and builds the TLS configuration and
+// everything connections share
+fn prepare(
+    settings: &TlsSettings,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    workspace_key: bool,
+    site: Site,
+    manifest_root_hash: Option<[u8; 32]>,
+) -> io::Result<(Arc<ServerConfig>, App)> {
+    let certs = load_certs(&cert_path)?;
+    let key = keystore::load_key(&key_path)?;
+
+    let stapler = Arc::new(Stapler::new(certs.clone(), &key, &settings.ocsp)?);
+    let mut config = policy::builder(&settings.policy)?.with_cert_resolver(stapler.clone());
+    policy::apply(&mut config, &settings.policy)?;
+
+    let well_known = attestation::well_known_document(
+        config.crypto_provider(),
+        &certs,
+        &key,
+        manifest_root_hash,
+        site.bundle_sha256(),
+    )?;
+
+    let certificate = CertificateFiles {
+        cert_path,
+        key_path,
+        workspace_key,
+        provider: Arc::clone(config.crypto_provider()),
+        manifest_root_hash,
+    };
+    let app = App {
+        site,
+        proxy: Proxy::new(&settings.routes)?,
+        stapler,
+        certificate,
+        well_known: RwLock::new(well_known),
+        metrics_path: settings.metrics_path.clone(),
+    };
+    Ok((Arc::new(config), app))
+}
+
+// Prints the TLS settings the server would run with, for auditing
+pub fn print_policy() -> io::Result<()> {
+    let settings = Config::load()?.tls;
+    let mut config = policy::builder(&settings.policy)?
+        .with_cert_resolver(Arc::new(ResolvesServerCertUsingSni::new()));
+    policy::apply(&mut config, &settings.policy)?;
+
+    println!("Effective TLS policy (from {}):", CONFIG_FILE);
+    for line in policy::describe(&config, &settings.policy) {
+        println!("  {}", line);
+    }
+    Ok(())
+}
+
+pub fn run() -> io::Result<()> {
+    // Get the current directory
+    let current_dir = env::current_dir()?;
+    let mut settings = Config::load()?.tls;
+
+    // Construct paths for the certificate and key files
+    let mut cert_path = current_dir.join("fullchain.pem");
+    let mut key_path = keystore::key_path(&current_dir);
+
+    // Without a certbot certificate, offer a development one instead
+    if settings.dev.is_none() && (!cert_path.exists() || !key_path.exists()) {
+        let use_dev = Confirm::new()
+            .with_prompt(
+                "Certificate or key file not found. Use a self-signed development certificate?",
+            )
+            .default(false)
+            .interact()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if !use_dev {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Certificate or key file not found. Please run the certbot program first.",
+            ));
+        }
+        settings.dev = Some(DevSettings::default());
+    }
+
+    let mut listen = settings.listen.clone();
+    let workspace_key = settings.dev.is_none();
+    if let Some(dev) = &settings.dev {
+        (cert_path, key_path) =
+            devcert::ensure_dev_certificates(&current_dir.join(&dev.dir), &dev.sans)?;
+        listen = vec![dev.listen.clone()];
+    }
+
+    let (site, manifest_root_hash) = load_site(&settings, &current_dir)?;
+    let (config, app) = prepare(
+        &settings,
+        cert_path,
+        key_path,
+        workspace_key,
+        site,
+        manifest_root_hash,
+    )?;
+
+    // Taken before the runtime starts any threads, as it clears LISTEN_*
+    let activated = listen::activated_listeners()?;
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(serve(config, app, &settings, activated, &listen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    // A development workspace with a one-page site, removed on drop. Shared
+    // with the protocol modules' tests.
+    pub(super) struct Workspace(pub(super) PathBuf);
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub(super) const INDEX: &str = "<h1>hello</h1>";
+
+    pub(super) fn workspace(name: &str) -> Workspace {
+        let dir = env::temp_dir().join(format!("defe-tls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("site")).unwrap();
+        fs::write(dir.join("site/index.html"), INDEX).unwrap();
+        Workspace(dir)
+    }
+
+    // The server for a workspace, with a development certificate
+    pub(super) fn app(workspace: &Workspace, settings: &TlsSettings) -> (Arc<ServerConfig>, App) {
+        let dir = &workspace.0;
+        let (cert_path, key_path) =
+            devcert::ensure_dev_certificates(&dir.join("dev"), &[]).unwrap();
+        let site = Site::new(dir.join("site"), None);
+        prepare(settings, cert_path, key_path, false, site, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_with_the_development_certificate() {
+        let workspace = workspace("serve");
+        let dir = &workspace.0;
+        let settings = TlsSettings {
+            pid_file: None,
+            ..TlsSettings::default()
+        };
+        let (config, app) = app(&workspace, &settings);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server =
+            tokio::spawn(async move { serve(config, app, &settings, vec![listener], &[]).await });
+
+        // Trusting only the development CA
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&dir.join("dev").join(devcert::CA_CERT_FILE)).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let client = ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut tls = TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+
+        tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.unwrap();
+        server.abort();
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with(INDEX));
+
+        #[cfg(unix)]
+        for key in [devcert::KEY_FILE, "ca-key.pem"] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("dev").join(key))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", key);
+        }
+    }
+}
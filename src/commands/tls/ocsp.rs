@@ -0,0 +1,733 @@
+use std::fmt::Write as _;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use sha1::{Digest, Sha1};
+use tokio::sync::Notify;
+use tokio::time::Duration;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::commands::invalid_data;
+use crate::config::OcspSettings;
+
+const OID_AD_OCSP: &str = "1.3.6.1.5.5.7.48.1";
+// DER contents of the sha1 and id-pkix-ocsp-basic object identifiers
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+
+// Responses are refreshed no more often than this, however short their validity
+const MIN_REFRESH_SECS: u64 = 60;
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let length = content.len().to_be_bytes();
+        let skip = length.iter().take_while(|byte| **byte == 0).count();
+        out.push(0x80 | (length.len() - skip) as u8);
+        out.extend_from_slice(&length[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+// Just enough of a DER reader to walk an OCSP response
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    fn read_any(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let length = rest[..count]
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, &rest[count..])
+        };
+        if rest.len() < length {
+            return None;
+        }
+        let (content, rest) = rest.split_at(length);
+        self.data = rest;
+        Some((tag, content))
+    }
+
+    fn read(&mut self, tag: u8) -> io::Result<&'a [u8]> {
+        match self.read_any() {
+            Some((found, content)) if found == tag => Ok(content),
+            Some((found, _)) => Err(invalid_data(format!(
+                "Malformed OCSP response: expected tag {:#04x}, found {:#04x}",
+                tag, found
+            ))),
+            None => Err(invalid_data("Truncated OCSP response".to_string())),
+        }
+    }
+
+    fn skip(&mut self) -> io::Result<()> {
+        self.read_any()
+            .map(|_| ())
+            .ok_or_else(|| invalid_data("Truncated OCSP response".to_string()))
+    }
+}
+
+fn generalized_time(content: &[u8]) -> io::Result<DateTime<Utc>> {
+    let text = std::str::from_utf8(content)
+        .map_err(|_| invalid_data("Invalid time in OCSP response".to_string()))?;
+    // Fractional seconds are allowed but of no use here
+    let whole = match text.find('.') {
+        Some(dot) => format!("{}Z", &text[..dot]),
+        None => text.to_string(),
+    };
+    NaiveDateTime::parse_from_str(&whole, "%Y%m%d%H%M%SZ")
+        .map(|time| time.and_utc())
+        .map_err(|_| invalid_data(format!("Invalid time in OCSP response: {}", text)))
+}
+
+// Identifies the leaf to the responder, hashed with SHA-1 as RFC 6960 responders
+// universally support
+#[derive(Debug, Clone)]
+struct CertId {
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: Vec<u8>,
+}
+
+impl CertId {
+    fn encode(&self) -> Vec<u8> {
+        let algorithm = der(
+            TAG_SEQUENCE,
+            &[der(TAG_OID, OID_SHA1), der(TAG_NULL, &[])].concat(),
+        );
+        der(
+            TAG_SEQUENCE,
+            &[
+                algorithm,
+                der(TAG_OCTET_STRING, &self.issuer_name_hash),
+                der(TAG_OCTET_STRING, &self.issuer_key_hash),
+                der(TAG_INTEGER, &self.serial),
+            ]
+            .concat(),
+        )
+    }
+
+    // A DER OCSPRequest for this certificate, without nonce or signature
+    fn request(&self) -> Vec<u8> {
+        let request = der(TAG_SEQUENCE, &self.encode());
+        let request_list = der(TAG_SEQUENCE, &request);
+        let tbs_request = der(TAG_SEQUENCE, &request_list);
+        der(TAG_SEQUENCE, &tbs_request)
+    }
+
+    fn matches(&self, content: &[u8]) -> io::Result<bool> {
+        let mut fields = Reader::new(content);
+        fields.skip()?;
+        Ok(
+            fields.read(TAG_OCTET_STRING)? == self.issuer_name_hash.as_slice()
+                && fields.read(TAG_OCTET_STRING)? == self.issuer_key_hash.as_slice()
+                && fields.read(TAG_INTEGER)? == self.serial.as_slice(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+impl CertStatus {
+    fn name(self) -> &'static str {
+        match self {
+            CertStatus::Good => "good",
+            CertStatus::Revoked => "revoked",
+            CertStatus::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OcspResponse {
+    status: CertStatus,
+    this_update: DateTime<Utc>,
+    next_update: Option<DateTime<Utc>>,
+}
+
+// Checks that `der` is a successful basic response about `cert_id` and pulls
+// out its status and validity window. The responder's signature is left to the
+// client, which has to verify it anyway.
+fn parse_response(der: &[u8], cert_id: &CertId) -> io::Result<OcspResponse> {
+    let mut outer = Reader::new(Reader::new(der).read(TAG_SEQUENCE)?);
+    let status = outer.read(TAG_ENUMERATED)?;
+    if status != [0] {
+        let reason = match status {
+            [1] => "malformed request",
+            [2] => "internal error",
+            [3] => "try later",
+            [5] => "signature required",
+            [6] => "unauthorized",
+            _ => "unknown status",
+        };
+        return Err(invalid_data(format!("OCSP responder returned: {}", reason)));
+    }
+
+    let mut response_bytes = Reader::new(Reader::new(outer.read(0xa0)?).read(TAG_SEQUENCE)?);
+    if response_bytes.read(TAG_OID)? != OID_OCSP_BASIC {
+        return Err(invalid_data("Unsupported OCSP response type".to_string()));
+    }
+    let basic = response_bytes.read(TAG_OCTET_STRING)?;
+    let mut basic = Reader::new(Reader::new(basic).read(TAG_SEQUENCE)?);
+    let mut data = Reader::new(basic.read(TAG_SEQUENCE)?);
+    if data.peek_tag() == Some(0xa0) {
+        data.skip()?; // version
+    }
+    data.skip()?; // responderID
+    data.read(TAG_GENERALIZED_TIME)?; // producedAt
+
+    let mut responses = Reader::new(data.read(TAG_SEQUENCE)?);
+    while responses.peek_tag().is_some() {
+        let mut single = Reader::new(responses.read(TAG_SEQUENCE)?);
+        if !cert_id.matches(single.read(TAG_SEQUENCE)?)? {
+            continue;
+        }
+        let status = match single.read_any().map(|(tag, _)| tag) {
+            Some(0x80) => CertStatus::Good,
+            Some(0xa1) => CertStatus::Revoked,
+            Some(0x82) => CertStatus::Unknown,
+            _ => {
+                return Err(invalid_data(
+                    "Invalid certificate status in OCSP response".to_string(),
+                ))
+            }
+        };
+        let this_update = generalized_time(single.read(TAG_GENERALIZED_TIME)?)?;
+        let next_update = if single.peek_tag() == Some(0xa0) {
+            let explicit = single.read(0xa0)?;
+            Some(generalized_time(
+                Reader::new(explicit).read(TAG_GENERALIZED_TIME)?,
+            )?)
+        } else {
+            None
+        };
+        return Ok(OcspResponse {
+            status,
+            this_update,
+            next_update,
+        });
+    }
+    Err(invalid_data(
+        "OCSP response does not cover the server certificate".to_string(),
+    ))
+}
+
+// The OCSP responder named in the certificate's Authority Information Access
+fn responder_url(leaf: &X509Certificate<'_>) -> Option<String> {
+    leaf.extensions()
+        .iter()
+        .filter_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })
+        .flat_map(|aia| aia.accessdescs.iter())
+        .filter(|description| description.access_method.to_id_string() == OID_AD_OCSP)
+        .find_map(|description| match &description.access_location {
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+}
+
+fn cert_id(leaf: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> io::Result<CertId> {
+    // The hash covers the subjectPublicKey bits, without the unused-bits byte
+    let mut spki = Reader::new(Reader::new(issuer.public_key().raw).read(TAG_SEQUENCE)?);
+    spki.skip()?;
+    let key_bits = spki.read(0x03)?;
+    let key = key_bits
+        .get(1..)
+        .ok_or_else(|| invalid_data("Invalid issuer public key".to_string()))?;
+
+    Ok(CertId {
+        issuer_name_hash: Sha1::digest(leaf.issuer().as_raw()).to_vec(),
+        issuer_key_hash: Sha1::digest(key).to_vec(),
+        serial: leaf.raw_serial().to_vec(),
+    })
+}
+
+// Where and what to ask for the leaf's status
+#[derive(Debug)]
+struct Target {
+    url: String,
+    cert_id: CertId,
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    status: Option<CertStatus>,
+    this_update: Option<DateTime<Utc>>,
+    next_update: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    failures: u64,
+}
+
+// Serves the certificate with the latest OCSP response stapled to it. A
+// background task keeps the response fresh; until the first one arrives, or
+// once the cached one has expired, the certificate is served without one.
+#[derive(Debug)]
+pub struct Stapler {
+    certified: RwLock<Arc<CertifiedKey>>,
+    target: RwLock<Option<Arc<Target>>>,
+    settings: OcspSettings,
+    // Wakes the refresh task when the certificate is replaced
+    reloaded: Notify,
+    timeout: Duration,
+    retry: Duration,
+    refresh: Duration,
+    status: Mutex<Status>,
+}
+
+impl Stapler {
+    pub fn new(
+        certs: Vec<CertificateDer<'static>>,
+        key: &PrivateKeyDer<'static>,
+        settings: &OcspSettings,
+    ) -> io::Result<Self> {
+        let (certified, target) = Self::prepare(certs, key, settings)?;
+        Ok(Self {
+            certified: RwLock::new(certified),
+            target: RwLock::new(target),
+            settings: settings.clone(),
+            reloaded: Notify::new(),
+            timeout: Duration::from_secs(settings.timeout_secs),
+            retry: Duration::from_secs(settings.retry_secs.max(1)),
+            refresh: Duration::from_secs(settings.refresh_secs.max(MIN_REFRESH_SECS)),
+            status: Mutex::new(Status::default()),
+        })
+    }
+
+    fn prepare(
+        certs: Vec<CertificateDer<'static>>,
+        key: &PrivateKeyDer<'static>,
+        settings: &OcspSettings,
+    ) -> io::Result<(Arc<CertifiedKey>, Option<Arc<Target>>)> {
+        let signing_key = rustls::crypto::aws_lc_rs::sign::any_supported_type(key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let target = if settings.enabled {
+            Self::target(&certs, settings)?.map(Arc::new)
+        } else {
+            None
+        };
+        let certified = CertifiedKey::new(certs, signing_key);
+        certified
+            .keys_match()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok((Arc::new(certified), target))
+    }
+
+    // Starts serving a new certificate, e.g. after renewal. Its OCSP response
+    // is fetched right away; until then it is served without a staple.
+    pub fn reload(
+        &self,
+        certs: Vec<CertificateDer<'static>>,
+        key: &PrivateKeyDer<'static>,
+    ) -> io::Result<()> {
+        let (certified, target) = Self::prepare(certs, key, &self.settings)?;
+        *self.certified.write().unwrap_or_else(|e| e.into_inner()) = certified;
+        *self.target.write().unwrap_or_else(|e| e.into_inner()) = target;
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        *status = Status {
+            failures: status.failures,
+            ..Status::default()
+        };
+        self.reloaded.notify_one();
+        Ok(())
+    }
+
+    fn current_target(&self) -> Option<Arc<Target>> {
+        self.target
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn target(
+        certs: &[CertificateDer<'static>],
+        settings: &OcspSettings,
+    ) -> io::Result<Option<Target>> {
+        fn parse<'a>(cert: &'a CertificateDer<'_>) -> io::Result<X509Certificate<'a>> {
+            X509Certificate::from_der(cert.as_ref())
+                .map(|(_, certificate)| certificate)
+                .map_err(|e| invalid_data(e.to_string()))
+        }
+        let Some(leaf) = certs.first() else {
+            return Ok(None);
+        };
+        let leaf = parse(leaf)?;
+
+        let Some(url) = settings.responder.clone().or_else(|| responder_url(&leaf)) else {
+            println!("Certificate names no OCSP responder; stapling disabled.");
+            return Ok(None);
+        };
+        let Some(issuer) = certs.get(1) else {
+            println!("Certificate chain has no issuer certificate; OCSP stapling disabled.");
+            return Ok(None);
+        };
+        let issuer = parse(issuer)?;
+
+        Ok(Some(Target {
+            url,
+            cert_id: cert_id(&leaf, &issuer)?,
+        }))
+    }
+
+    pub fn describe(&self) -> String {
+        match self.current_target() {
+            Some(target) => format!("OCSP stapling enabled, responder {}", target.url),
+            None => "OCSP stapling disabled".to_string(),
+        }
+    }
+
+    fn set_staple(&self, ocsp: Option<Vec<u8>>) {
+        let mut certified = self.certified.write().unwrap_or_else(|e| e.into_inner());
+        let mut updated = CertifiedKey::clone(&certified);
+        updated.ocsp = ocsp;
+        *certified = Arc::new(updated);
+    }
+
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        target: &Target,
+    ) -> io::Result<(Vec<u8>, OcspResponse)> {
+        let response = client
+            .post(&target.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/ocsp-request")
+            .body(target.cert_id.request())
+            .timeout(self.timeout)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(io::Error::other)?;
+        let body = response.bytes().await.map_err(io::Error::other)?.to_vec();
+        let parsed = parse_response(&body, &target.cert_id)?;
+
+        let now = Utc::now();
+        if parsed.this_update > now + chrono::Duration::minutes(5) {
+            return Err(invalid_data("OCSP response is not yet valid".to_string()));
+        }
+        if parsed
+            .next_update
+            .is_some_and(|next_update| next_update <= now)
+        {
+            return Err(invalid_data(
+                "OCSP response has already expired".to_string(),
+            ));
+        }
+        Ok((body, parsed))
+    }
+
+    // Fetches a new response and returns how long to wait before the next one.
+    // On success that is halfway to `nextUpdate`; on failure the cached
+    // response is kept until it expires and the fetch is retried sooner.
+    async fn refresh_once(&self, client: &reqwest::Client, target: &Arc<Target>) -> Duration {
+        let now = Utc::now();
+        let fetched = self.fetch(client, target).await;
+        // A response for a certificate replaced in the meantime is no use
+        if !self
+            .current_target()
+            .is_some_and(|current| Arc::ptr_eq(&current, target))
+        {
+            return Duration::ZERO;
+        }
+        match fetched {
+            Ok((body, response)) => {
+                if response.status != CertStatus::Good {
+                    eprintln!(
+                        "OCSP responder reports the server certificate as {}",
+                        response.status.name()
+                    );
+                }
+                self.set_staple(Some(body));
+                let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+                status.status = Some(response.status);
+                status.this_update = Some(response.this_update);
+                status.next_update = response.next_update;
+                status.last_success = Some(now);
+
+                match response.next_update {
+                    Some(next_update) => (next_update - now)
+                        .to_std()
+                        .map(|remaining| remaining / 2)
+                        .unwrap_or_default()
+                        .max(Duration::from_secs(MIN_REFRESH_SECS)),
+                    None => self.refresh,
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to fetch OCSP response from {}: {}", target.url, e);
+                let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+                status.failures += 1;
+                let next_update = status.next_update;
+                match next_update {
+                    Some(next_update) if next_update <= now => {
+                        eprintln!("Cached OCSP response expired; serving without a staple");
+                        self.set_staple(None);
+                        status.status = None;
+                        status.this_update = None;
+                        status.next_update = None;
+                        self.retry
+                    }
+                    Some(next_update) => (next_update - now)
+                        .to_std()
+                        .unwrap_or_default()
+                        .min(self.retry)
+                        .max(Duration::from_secs(1)),
+                    None => self.retry,
+                }
+            }
+        }
+    }
+
+    // Keeps the stapled response fresh for as long as the server runs
+    pub fn spawn_refresh(self: &Arc<Self>) {
+        if !self.settings.enabled {
+            return;
+        }
+        let stapler = Arc::clone(self);
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            loop {
+                let Some(target) = stapler.current_target() else {
+                    stapler.reloaded.notified().await;
+                    continue;
+                };
+                let wait = stapler.refresh_once(&client, &target).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = stapler.reloaded.notified() => {}
+                }
+            }
+        });
+    }
+
+    // Prometheus text exposition of the stapling state
+    pub fn metrics(&self) -> String {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Utc::now();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, sample: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{}{}", name, sample);
+        };
+
+        metric(
+            "defe_ocsp_enabled",
+            "gauge",
+            "Whether OCSP stapling is configured",
+            format!(" {}", u8::from(self.current_target().is_some())),
+        );
+        metric(
+            "defe_ocsp_stapled",
+            "gauge",
+            "Whether a response is currently stapled",
+            format!(" {}", u8::from(status.this_update.is_some())),
+        );
+        if let Some(this_update) = status.this_update {
+            metric(
+                "defe_ocsp_response_age_seconds",
+                "gauge",
+                "Time since the thisUpdate of the stapled response",
+                format!(" {}", (now - this_update).num_seconds()),
+            );
+        }
+        if let Some(next_update) = status.next_update {
+            metric(
+                "defe_ocsp_response_expires_in_seconds",
+                "gauge",
+                "Time until the nextUpdate of the stapled response",
+                format!(" {}", (next_update - now).num_seconds()),
+            );
+        }
+        if let Some(cert_status) = status.status {
+            metric(
+                "defe_ocsp_certificate_status",
+                "gauge",
+                "Certificate status reported by the responder",
+                format!("{{status=\"{}\"}} 1", cert_status.name()),
+            );
+        }
+        if let Some(last_success) = status.last_success {
+            metric(
+                "defe_ocsp_last_success_timestamp_seconds",
+                "gauge",
+                "When a response was last fetched",
+                format!(" {}", last_success.timestamp()),
+            );
+        }
+        metric(
+            "defe_ocsp_fetch_failures_total",
+            "counter",
+            "Failed response fetches",
+            format!(" {}", status.failures),
+        );
+        out
+    }
+}
+
+impl ResolvesServerCert for Stapler {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(
+            &self.certified.read().unwrap_or_else(|e| e.into_inner()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated with `openssl ocsp` acting as the responder for a test CA:
+    // good.der was issued and is valid, revoked.der was revoked, other.der
+    // is unknown to the responder. good.req is openssl's request for good.der.
+    const ISSUER: &[u8] = include_bytes!("testdata/ocsp/issuer.der");
+    const GOOD_CERT: &[u8] = include_bytes!("testdata/ocsp/good.der");
+    const REVOKED_CERT: &[u8] = include_bytes!("testdata/ocsp/revoked.der");
+    const OTHER_CERT: &[u8] = include_bytes!("testdata/ocsp/other.der");
+    const GOOD_REQUEST: &[u8] = include_bytes!("testdata/ocsp/good.req");
+    const GOOD: &[u8] = include_bytes!("testdata/ocsp/good.ocsp");
+    const REVOKED: &[u8] = include_bytes!("testdata/ocsp/revoked.ocsp");
+    const UNKNOWN: &[u8] = include_bytes!("testdata/ocsp/unknown.ocsp");
+    const NO_NEXT_UPDATE: &[u8] = include_bytes!("testdata/ocsp/no_next_update.ocsp");
+
+    fn id_for(leaf: &[u8]) -> CertId {
+        let (_, leaf) = X509Certificate::from_der(leaf).unwrap();
+        let (_, issuer) = X509Certificate::from_der(ISSUER).unwrap();
+        cert_id(&leaf, &issuer).unwrap()
+    }
+
+    #[test]
+    fn encodes_requests_like_openssl() {
+        assert_eq!(id_for(GOOD_CERT).request(), GOOD_REQUEST);
+    }
+
+    #[test]
+    fn finds_the_responder() {
+        let (_, leaf) = X509Certificate::from_der(GOOD_CERT).unwrap();
+        assert_eq!(
+            responder_url(&leaf).as_deref(),
+            Some("http://ocsp.example.com")
+        );
+    }
+
+    #[test]
+    fn parses_statuses() {
+        let good = parse_response(GOOD, &id_for(GOOD_CERT)).unwrap();
+        assert_eq!(good.status, CertStatus::Good);
+        let next_update = good.next_update.unwrap();
+        assert_eq!((next_update - good.this_update).num_days(), 7);
+
+        let revoked = parse_response(REVOKED, &id_for(REVOKED_CERT)).unwrap();
+        assert_eq!(revoked.status, CertStatus::Revoked);
+
+        let unknown = parse_response(UNKNOWN, &id_for(OTHER_CERT)).unwrap();
+        assert_eq!(unknown.status, CertStatus::Unknown);
+    }
+
+    #[test]
+    fn next_update_is_optional() {
+        let response = parse_response(NO_NEXT_UPDATE, &id_for(GOOD_CERT)).unwrap();
+        assert_eq!(response.status, CertStatus::Good);
+        assert!(response.next_update.is_none());
+    }
+
+    #[test]
+    fn rejects_responses_about_other_certificates() {
+        assert!(parse_response(GOOD, &id_for(REVOKED_CERT)).is_err());
+    }
+
+    #[test]
+    fn rejects_unsuccessful_responses() {
+        // OCSPResponse { responseStatus: tryLater }
+        let error =
+            parse_response(&[0x30, 0x03, 0x0a, 0x01, 0x03], &id_for(GOOD_CERT)).unwrap_err();
+        assert!(error.to_string().contains("try later"));
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let id = id_for(GOOD_CERT);
+        for length in 0..GOOD.len() {
+            assert!(parse_response(&GOOD[..length], &id).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_lengths_strictly() {
+        // Long form
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend([0xaa; 0x80]);
+        assert_eq!(
+            Reader::new(&long).read(TAG_OCTET_STRING).unwrap().len(),
+            0x80
+        );
+        // Longer than the data
+        assert!(Reader::new(&[0x04, 0x05, 0x00]).read_any().is_none());
+        assert!(Reader::new(&[0x04, 0x82, 0x01, 0x00, 0x00])
+            .read_any()
+            .is_none());
+        // Indefinite and oversized length forms
+        assert!(Reader::new(&[0x30, 0x80, 0x00, 0x00]).read_any().is_none());
+        assert!(Reader::new(&[0x04, 0x85, 0xff, 0xff, 0xff, 0xff, 0xff])
+            .read_any()
+            .is_none());
+        assert!(Reader::new(&[0x04]).read_any().is_none());
+        // Wrong tag
+        assert!(Reader::new(&[0x04, 0x00]).read(TAG_SEQUENCE).is_err());
+    }
+
+    #[test]
+    fn encodes_lengths() {
+        assert_eq!(der(TAG_NULL, &[]), [0x05, 0x00]);
+        let long = der(TAG_OCTET_STRING, &[0; 0x100]);
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x00]);
+        assert_eq!(long.len(), 4 + 0x100);
+    }
+
+    #[test]
+    fn parses_generalized_time() {
+        let time = generalized_time(b"20240102030405Z").unwrap();
+        assert_eq!(time.to_rfc3339(), "2024-01-02T03:04:05+00:00");
+        let fractional = generalized_time(b"20240102030405.123Z").unwrap();
+        assert_eq!(fractional, time);
+        assert!(generalized_time(b"2024").is_err());
+    }
+}
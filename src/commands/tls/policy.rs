@@ -0,0 +1,347 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use rand::{thread_rng, Rng};
+use rustls::crypto::{aws_lc_rs, CryptoProvider, SupportedKxGroup};
+use rustls::server::{
+    NoServerSessionStorage, ProducesTickets, ServerSessionMemoryCache, WantsServerCert,
+};
+use rustls::{ConfigBuilder, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+
+use crate::config::TlsPolicy;
+
+use super::{ALPN_H2, ALPN_HTTP11};
+
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+const KEY_NAME_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn suite_name(suite: &SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+fn group_name(group: &&'static dyn SupportedKxGroup) -> String {
+    format!("{:?}", group.name())
+}
+
+// Picks the allowlisted entries out of `all`, in allowlist order. An empty
+// allowlist keeps the provider defaults.
+fn select<T: Copy>(
+    kind: &str,
+    allowlist: &[String],
+    all: &[T],
+    defaults: &[T],
+    name: fn(&T) -> String,
+) -> io::Result<Vec<T>> {
+    if allowlist.is_empty() {
+        return Ok(defaults.to_vec());
+    }
+    allowlist
+        .iter()
+        .map(|wanted| {
+            all.iter()
+                .find(|item| name(item).eq_ignore_ascii_case(wanted))
+                .copied()
+                .ok_or_else(|| {
+                    let known: Vec<String> = all.iter().map(name).collect();
+                    invalid(format!(
+                        "Unknown {} '{}'. Supported: {}",
+                        kind,
+                        wanted,
+                        known.join(", ")
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn protocol_versions(policy: &TlsPolicy) -> &'static [&'static SupportedProtocolVersion] {
+    if policy.tls13_only {
+        TLS13_ONLY
+    } else {
+        rustls::DEFAULT_VERSIONS
+    }
+}
+
+fn provider(policy: &TlsPolicy) -> io::Result<CryptoProvider> {
+    let mut cipher_suites = select(
+        "cipher suite",
+        &policy.cipher_suites,
+        aws_lc_rs::ALL_CIPHER_SUITES,
+        aws_lc_rs::DEFAULT_CIPHER_SUITES,
+        suite_name,
+    )?;
+    if policy.tls13_only {
+        cipher_suites.retain(|suite| matches!(suite, SupportedCipherSuite::Tls13(_)));
+        if cipher_suites.is_empty() {
+            return Err(invalid(
+                "TLS 1.3-only mode needs at least one TLS 1.3 cipher suite".to_string(),
+            ));
+        }
+    }
+    let kx_groups = select(
+        "key exchange group",
+        &policy.kx_groups,
+        aws_lc_rs::ALL_KX_GROUPS,
+        aws_lc_rs::DEFAULT_KX_GROUPS,
+        group_name,
+    )?;
+    Ok(CryptoProvider {
+        cipher_suites,
+        kx_groups,
+        ..aws_lc_rs::default_provider()
+    })
+}
+
+// The builder with versions, cipher suites and key exchange groups fixed by
+// the policy; the caller supplies the certificate.
+pub fn builder(policy: &TlsPolicy) -> io::Result<ConfigBuilder<ServerConfig, WantsServerCert>> {
+    Ok(
+        ServerConfig::builder_with_provider(Arc::new(provider(policy)?))
+            .with_protocol_versions(protocol_versions(policy))
+            .map_err(|e| invalid(e.to_string()))?
+            .with_no_client_auth(),
+    )
+}
+
+// Applies the resumption, early data and ALPN settings to a built config
+pub fn apply(config: &mut ServerConfig, policy: &TlsPolicy) -> io::Result<()> {
+    config.session_storage = if policy.session_cache_size == 0 {
+        Arc::new(NoServerSessionStorage {})
+    } else {
+        ServerSessionMemoryCache::new(policy.session_cache_size)
+    };
+    if policy.session_tickets {
+        let lifetime = u32::try_from(policy.ticket_rotation_secs.max(1))
+            .map_err(|_| invalid("ticket_rotation_secs is too large".to_string()))?;
+        config.ticketer = Arc::new(
+            rustls::TicketRotator::new(lifetime, AeadTicketer::generate)
+                .map_err(|e| invalid(e.to_string()))?,
+        );
+    } else if policy.session_cache_size == 0 {
+        // Nothing to resume from, so don't hand out TLS 1.3 session IDs either
+        config.send_tls13_tickets = 0;
+    }
+    config.max_early_data_size = policy.max_early_data;
+    config.alpn_protocols = vec![ALPN_H2.to_vec(), ALPN_HTTP11.to_vec()];
+    Ok(())
+}
+
+// The effective settings of a config built from `policy`, one per line
+pub fn describe(config: &ServerConfig, policy: &TlsPolicy) -> Vec<String> {
+    let provider = config.crypto_provider();
+    let versions: Vec<String> = protocol_versions(policy)
+        .iter()
+        .map(|version| format!("{:?}", version.version))
+        .collect();
+    let suites: Vec<String> = provider.cipher_suites.iter().map(suite_name).collect();
+    let groups: Vec<String> = provider.kx_groups.iter().map(group_name).collect();
+    let alpn: Vec<String> = config
+        .alpn_protocols
+        .iter()
+        .map(|protocol| String::from_utf8_lossy(protocol).into_owned())
+        .collect();
+
+    let tickets = if config.ticketer.enabled() {
+        format!(
+            "on (key rotated every {}s, accepted for {}s)",
+            policy.ticket_rotation_secs,
+            config.ticketer.lifetime()
+        )
+    } else {
+        "off".to_string()
+    };
+    let cache = if policy.session_cache_size == 0 {
+        "off".to_string()
+    } else {
+        format!("{} sessions", policy.session_cache_size)
+    };
+
+    vec![
+        "Crypto provider: aws-lc-rs".to_string(),
+        format!("FIPS mode: {}", if config.fips() { "yes" } else { "no" }),
+        format!("Protocol versions: {}", versions.join(", ")),
+        format!("Cipher suites (in preference order): {}", suites.join(", ")),
+        format!(
+            "Key exchange groups (in preference order): {}",
+            groups.join(", ")
+        ),
+        format!("Session tickets: {}", tickets),
+        format!(
+            "TLS 1.3 tickets per handshake: {}",
+            config.send_tls13_tickets
+        ),
+        format!("Session cache: {}", cache),
+        format!(
+            "Max early data (0-RTT): {} bytes",
+            config.max_early_data_size
+        ),
+        format!(
+            "Server cipher suite preference: {}",
+            if config.ignore_client_order {
+                "server"
+            } else {
+                "client"
+            }
+        ),
+        format!("ALPN protocols: {}", alpn.join(", ")),
+        "Client authentication: none".to_string(),
+    ]
+}
+
+// Session ticket encryption with a random AES-256-GCM key. Tickets are
+// "key name || nonce || ciphertext"; the key name lets a ticket from the
+// previous key be recognised as such.
+struct AeadTicketer {
+    key_name: [u8; KEY_NAME_LENGTH],
+    cipher: Aes256Gcm,
+}
+
+impl AeadTicketer {
+    fn generate() -> Result<Box<dyn ProducesTickets>, rustls::crypto::GetRandomFailed> {
+        let mut rng = thread_rng();
+        let key: [u8; 32] = rng.gen();
+        Ok(Box::new(Self {
+            key_name: rng.gen(),
+            cipher: Aes256Gcm::new(&key.into()),
+        }))
+    }
+}
+
+impl fmt::Debug for AeadTicketer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AeadTicketer")
+            .field("key_name", &hex::encode(self.key_name))
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProducesTickets for AeadTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    // Overridden by the rotator, which knows how long keys are kept
+    fn lifetime(&self) -> u32 {
+        0
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let nonce: [u8; NONCE_LENGTH] = thread_rng().gen();
+        let ciphertext = self.cipher.encrypt(Nonce::from_slice(&nonce), plain).ok()?;
+        let mut ticket = Vec::with_capacity(KEY_NAME_LENGTH + NONCE_LENGTH + ciphertext.len());
+        ticket.extend_from_slice(&self.key_name);
+        ticket.extend_from_slice(&nonce);
+        ticket.extend_from_slice(&ciphertext);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let rest = ticket.strip_prefix(&self.key_name)?;
+        if rest.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn selects_allowlisted_suites_in_order() {
+        let policy = TlsPolicy {
+            cipher_suites: names(&["tls13_chacha20_poly1305_sha256", "TLS13_AES_256_GCM_SHA384"]),
+            kx_groups: names(&["X25519"]),
+            ..TlsPolicy::default()
+        };
+        let provider = provider(&policy).unwrap();
+        let suites: Vec<String> = provider.cipher_suites.iter().map(suite_name).collect();
+        assert_eq!(
+            suites,
+            ["TLS13_CHACHA20_POLY1305_SHA256", "TLS13_AES_256_GCM_SHA384"]
+        );
+        assert_eq!(provider.kx_groups.len(), 1);
+
+        // No allowlist keeps the defaults
+        let provider = super::provider(&TlsPolicy::default()).unwrap();
+        assert_eq!(
+            provider.cipher_suites.len(),
+            aws_lc_rs::DEFAULT_CIPHER_SUITES.len()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_selections() {
+        for policy in [
+            TlsPolicy {
+                cipher_suites: names(&["TLS13_AES_256_GCM_SHA384", "TLS_RSA_WITH_RC4_128_MD5"]),
+                ..TlsPolicy::default()
+            },
+            TlsPolicy {
+                kx_groups: names(&["ffdhe1024"]),
+                ..TlsPolicy::default()
+            },
+            // Only TLS 1.2 suites left once 1.3 is required
+            TlsPolicy {
+                tls13_only: true,
+                cipher_suites: names(&["TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"]),
+                ..TlsPolicy::default()
+            },
+        ] {
+            assert!(provider(&policy).is_err(), "{:?}", policy);
+        }
+
+        let tls13 = provider(&TlsPolicy {
+            tls13_only: true,
+            ..TlsPolicy::default()
+        })
+        .unwrap();
+        assert!(tls13
+            .cipher_suites
+            .iter()
+            .all(|suite| matches!(suite, SupportedCipherSuite::Tls13(_))));
+    }
+
+    #[test]
+    fn tickets_round_trip() {
+        let ticketer = AeadTicketer::generate().unwrap();
+        let ticket = ticketer.encrypt(b"session state").unwrap();
+        assert_eq!(ticketer.decrypt(&ticket).unwrap(), b"session state");
+        // A fresh nonce every time
+        assert_ne!(ticketer.encrypt(b"session state").unwrap(), ticket);
+
+        // Another key's ticket
+        let other = AeadTicketer::generate().unwrap();
+        assert!(other.decrypt(&ticket).is_none());
+
+        let mut tampered = ticket.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(ticketer.decrypt(&tampered).is_none());
+
+        let mut renamed = ticket.clone();
+        renamed[0] ^= 1;
+        assert!(ticketer.decrypt(&renamed).is_none());
+
+        assert!(ticketer.decrypt(&ticket[..KEY_NAME_LENGTH + 4]).is_none());
+        assert!(ticketer.decrypt(b"").is_none());
+    }
+}
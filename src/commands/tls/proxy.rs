@@ -0,0 +1,483 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use thiserror::Error;
+
+use crate::config::ProxyRoute;
+
+// Headers that only apply to a single connection and must not be forwarded
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|header| name.eq_ignore_ascii_case(header))
+}
+
+pub fn is_forwarded(name: &str) -> bool {
+    // Compared as bytes: a client-chosen name may split a UTF-8 character
+    name.len() > 12
+        && name
+            .as_bytes()
+            .get(..12)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(b"x-forwarded-"))
+}
+
+// Why a request could not be forwarded, mapped to the status sent to the client
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    #[error("Upstream is failing its health check")]
+    Unavailable,
+    #[error("Upstream error: {0}")]
+    BadGateway(String),
+    #[error("Upstream timed out")]
+    Timeout,
+    #[error("Request body too large to proxy")]
+    PayloadTooLarge,
+}
+
+impl ProxyError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ProxyError::Unavailable => 503,
+            ProxyError::BadGateway(_) => 502,
+            ProxyError::Timeout => 504,
+            ProxyError::PayloadTooLarge => 413,
+        }
+    }
+}
+
+pub struct Route {
+    prefix: String,
+    strip_prefix: bool,
+    // host:port of the upstream, always a loopback address
+    address: String,
+    timeout: Duration,
+    health_check_path: Option<String>,
+    health_check_interval: Duration,
+    healthy: AtomicBool,
+}
+
+impl Route {
+    fn new(config: &ProxyRoute) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        let prefix = config.prefix.trim_end_matches('/').to_string();
+        if !config.prefix.starts_with('/') {
+            return Err(invalid(format!(
+                "Route prefix '{}' must start with '/'",
+                config.prefix
+            )));
+        }
+
+        let upstream = reqwest::Url::parse(&config.upstream)
+            .map_err(|e| invalid(format!("Invalid upstream '{}': {}", config.upstream, e)))?;
+        if upstream.scheme() != "http" {
+            return Err(invalid(format!(
+                "Upstream '{}' must use http://",
+                config.upstream
+            )));
+        }
+        // host_str() keeps the brackets around IPv6 addresses
+        let host = upstream.host_str().unwrap_or_default();
+        let loopback = host == "localhost"
+            || host
+                .trim_matches(['[', ']'])
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback());
+        if !loopback {
+            return Err(invalid(format!(
+                "Upstream '{}' must be on localhost",
+                config.upstream
+            )));
+        }
+        let address = format!(
+            "{}:{}",
+            host,
+            upstream.port_or_known_default().unwrap_or(80)
+        );
+
+        Ok(Self {
+            prefix,
+            strip_prefix: config.strip_prefix,
+            address,
+            timeout: Duration::from_secs(config.timeout_secs),
+            health_check_path: config.health_check_path.clone(),
+            health_check_interval: Duration::from_secs(config.health_check_interval_secs.max(1)),
+            healthy: AtomicBool::new(true),
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with(['/', '?']),
+            None => false,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn url(&self, target: &str) -> String {
+        format!("http://{}{}", self.address, self.upstream_target(target))
+    }
+
+    // The request target as sent to the upstream
+    pub fn upstream_target(&self, target: &str) -> String {
+        if !self.strip_prefix {
+            return target.to_string();
+        }
+        let rest = &target[self.prefix.len()..];
+        if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{}", rest)
+        }
+    }
+
+    pub async fn connect(&self) -> Result<TcpStream, ProxyError> {
+        if !self.is_healthy() {
+            return Err(ProxyError::Unavailable);
+        }
+        match timeout(self.timeout, TcpStream::connect(&self.address)).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => Err(ProxyError::BadGateway(e.to_string())),
+            Err(_) => Err(ProxyError::Timeout),
+        }
+    }
+
+    async fn check_health(&self) -> bool {
+        let probe = async {
+            let mut stream = TcpStream::connect(&self.address).await?;
+            let Some(path) = &self.health_check_path else {
+                return Ok(true);
+            };
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                path, self.address
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let mut status_line = String::new();
+            BufReader::new(stream).read_line(&mut status_line).await?;
+            let status = status_line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse::<u16>().ok())
+                .unwrap_or(0);
+            Ok::<_, io::Error>((200..400).contains(&status))
+        };
+        matches!(timeout(self.timeout, probe).await, Ok(Ok(true)))
+    }
+}
+
+// Forwards requests under configured path prefixes to local upstream servers
+pub struct Proxy {
+    routes: Vec<Arc<Route>>,
+    client: reqwest::Client,
+}
+
+impl Proxy {
+    pub fn new(routes: &[ProxyRoute]) -> io::Result<Self> {
+        let routes = routes
+            .iter()
+            .map(|route| Route::new(route).map(Arc::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(Self { routes, client })
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    // The longest configured prefix matching the request target
+    pub fn route(&self, target: &str) -> Option<&Arc<Route>> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(target))
+            .max_by_key(|route| route.prefix.len())
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        self.routes
+            .iter()
+            .map(|route| format!("{} -> http://{}", route.prefix, route.address))
+            .collect()
+    }
+
+    // Periodically probes every upstream and marks it healthy or unhealthy.
+    // Requests for an unhealthy upstream get 503 without a connection attempt.
+    pub fn spawn_health_checks(&self) {
+        for route in &self.routes {
+            let route = Arc::clone(route);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(route.health_check_interval);
+                loop {
+                    interval.tick().await;
+                    let healthy = route.check_health().await;
+                    if route.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                        if healthy {
+                            println!("Upstream for {} is healthy again", route.prefix);
+                        } else {
+                            eprintln!(
+                                "Upstream for {} failed its health check ({})",
+                                route.prefix, route.address
+                            );
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+// The X-Forwarded-* headers added to every proxied request. This server is the
+// TLS edge, so any X-Forwarded-* headers sent by the client are replaced, not
+// extended.
+pub fn forwarded_headers(peer: SocketAddr, host: Option<&str>) -> Vec<(&'static str, String)> {
+    let client = match peer.ip() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => ip.to_canonical().to_string(),
+    };
+    let mut headers = vec![
+        ("X-Forwarded-For", client),
+        ("X-Forwarded-Proto", "https".to_string()),
+    ];
+    if let Some(host) = host {
+        headers.push(("X-Forwarded-Host", host.to_string()));
+    }
+    headers
+}
+
+// Relays an HTTP/1.1 exchange over raw sockets. The request head has already
+// been read from the client; the body (or, after a 101 response, WebSocket
+// frames) is copied as-is in both directions. An error is only returned if
+// nothing has been written to the client yet.
+pub async fn forward_http1<C>(
+    client: C,
+    request_head: String,
+    route: &Route,
+) -> Result<(), ProxyError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let upstream = route.connect().await?;
+    let (upstream_read, mut upstream_write) = upstream.into_split();
+    upstream_write
+        .write_all(request_head.as_bytes())
+        .await
+        .map_err(|e| ProxyError::BadGateway(e.to_string()))?;
+
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let upload = async {
+        tokio::io::copy(&mut client_read, &mut upstream_write).await?;
+        upstream_write.shutdown().await
+    };
+
+    let mut upstream_read = BufReader::new(upstream_read);
+    let download = async {
+        let head = match timeout(route.timeout, read_response_head(&mut upstream_read)).await {
+            Ok(Ok(head)) => head,
+            Ok(Err(e)) => return Err(ProxyError::BadGateway(e.to_string())),
+            Err(_) => return Err(ProxyError::Timeout),
+        };
+        let relay = async {
+            client_write.write_all(head.as_bytes()).await?;
+            tokio::io::copy(&mut upstream_read, &mut client_write).await?;
+            client_write.flush().await
+        };
+        // Once the head is sent, errors can only be reported by closing
+        if let Err(e) = relay.await {
+            eprintln!("Proxied connection ended with error: {}", e);
+        }
+        Ok(())
+    };
+
+    tokio::pin!(upload);
+    tokio::pin!(download);
+    let mut uploading = true;
+    loop {
+        tokio::select! {
+            result = &mut download => return result,
+            result = &mut upload, if uploading => {
+                uploading = false;
+                if let Err(e) = result {
+                    eprintln!("Error forwarding request body: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// Reads the upstream status line and headers. Connection management headers
+// are replaced because the client connection is closed after the response,
+// except for `101 Switching Protocols`, which is passed through unchanged.
+async fn read_response_head<R>(reader: &mut R) -> io::Result<String>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut status_line = String::new();
+    if reader.read_line(&mut status_line).await? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Upstream closed the connection without a response",
+        ));
+    }
+    let switching = status_line.split_whitespace().nth(1) == Some("101");
+
+    let mut head = status_line;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Upstream closed the connection mid-response",
+            ));
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+        let name = line.split(':').next().unwrap_or_default().trim();
+        if !switching
+            && (name.eq_ignore_ascii_case("connection") || name.eq_ignore_ascii_case("keep-alive"))
+        {
+            continue;
+        }
+        head.push_str(&line);
+    }
+    if !switching {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(prefix: &str, upstream: &str, strip_prefix: bool) -> ProxyRoute {
+        serde_json::from_value(serde_json::json!({
+            "prefix": prefix,
+            "upstream": upstream,
+            "strip_prefix": strip_prefix,
+        }))
+        .unwrap()
+    }
+
+    fn route(prefix: &str, strip_prefix: bool) -> Route {
+        Route::new(&config(prefix, "http://127.0.0.1:3000", strip_prefix)).unwrap()
+    }
+
+    #[test]
+    fn matches_whole_segments() {
+        let api = route("/api/", false);
+        for path in ["/api", "/api/", "/api/users", "/api?page=2"] {
+            assert!(api.matches(path), "{}", path);
+        }
+        for path in ["/apix", "/ap", "/", "/v1/api"] {
+            assert!(!api.matches(path), "{}", path);
+        }
+
+        let proxy = Proxy::new(&[
+            config("/api", "http://127.0.0.1:3000", false),
+            config("/api/admin", "http://localhost:4000", false),
+        ])
+        .unwrap();
+        let address = |target: &str| proxy.route(target).map(|route| route.address.as_str());
+        assert_eq!(address("/api/admin/users"), Some("localhost:4000"));
+        assert_eq!(address("/api/administrators"), Some("127.0.0.1:3000"));
+        assert_eq!(address("/static/app.js"), None);
+    }
+
+    #[test]
+    fn rewrites_targets() {
+        let stripped = route("/api", true);
+        assert_eq!(
+            stripped.upstream_target("/api/users?page=2"),
+            "/users?page=2"
+        );
+        assert_eq!(stripped.upstream_target("/api?page=2"), "/?page=2");
+        assert_eq!(stripped.upstream_target("/api"), "/");
+        assert_eq!(stripped.url("/api/users"), "http://127.0.0.1:3000/users");
+
+        let kept = route("/api", false);
+        assert_eq!(
+            kept.upstream_target("/api/users?page=2"),
+            "/api/users?page=2"
+        );
+    }
+
+    #[test]
+    fn only_proxies_to_loopback() {
+        for upstream in [
+            "http://127.0.0.1:3000",
+            "http://127.5.0.1",
+            "http://localhost:8080/",
+            "http://[::1]:3000",
+        ] {
+            assert!(
+                Route::new(&config("/api", upstream, false)).is_ok(),
+                "{}",
+                upstream
+            );
+        }
+        for upstream in [
+            "http://192.168.1.10:3000",
+            "http://example.com",
+            "http://localhost.example.com",
+            "https://127.0.0.1:3000",
+            "127.0.0.1:3000",
+        ] {
+            assert!(
+                Route::new(&config("/api", upstream, false)).is_err(),
+                "{}",
+                upstream
+            );
+        }
+        assert!(Route::new(&config("api", "http://127.0.0.1", false)).is_err());
+        assert_eq!(route("/api", false).address, "127.0.0.1:3000");
+        assert_eq!(
+            Route::new(&config("/", "http://localhost", false))
+                .unwrap()
+                .address,
+            "localhost:80"
+        );
+    }
+
+    #[test]
+    fn forwarded_headers() {
+        assert!(is_forwarded("X-Forwarded-For"));
+        assert!(is_forwarded("x-forwarded-proto"));
+        assert!(!is_forwarded("x-forwarded-"));
+        assert!(!is_forwarded("x-forward"));
+        assert!(!is_forwarded("forwarded"));
+        // The 12th byte falls inside a multi-byte character
+        assert!(!is_forwarded("x-forwarded\u{e9}x"));
+        assert!(!is_forwarded("\u{1f600}\u{1f600}\u{1f600}\u{1f600}"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::{Component, Path, PathBuf};
+
+use super::bundle::Bundle;
+use super::manifest::{sha256_hex, AssetManifest};
+
+// A response produced by the site, independent of the HTTP version it is sent over
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            _ => "",
+        }
+    }
+}
+
+// Where a site's files come from
+enum Files {
+    // A build directory read at request time. With a manifest, files it
+    // doesn't list are treated as missing and every file is re-hashed before
+    // it is sent.
+    Directory {
+        root: PathBuf,
+        manifest: Option<AssetManifest>,
+    },
+    // An archive verified and loaded into memory at startup
+    Bundle {
+        name: String,
+        bundle: Bundle,
+    },
+}
+
+// Static file routing for a frontend build. Paths without a file extension
+// that don't exist fall back to `index.html` so client-side routers (React
+// Router, Vue Router) can handle them.
+pub struct Site {
+    files: Files,
+}
+
+impl Site {
+    pub fn new(root: PathBuf, manifest: Option<AssetManifest>) -> Self {
+        Self {
+            files: Files::Directory { root, manifest },
+        }
+    }
+
+    pub fn from_bundle(name: String, bundle: Bundle) -> Self {
+        Self {
+            files: Files::Bundle { name, bundle },
+        }
+    }
+
+    pub fn bundle_sha256(&self) -> Option<&str> {
+        match &self.files {
+            Files::Directory { .. } => None,
+            Files::Bundle { bundle, .. } => Some(bundle.sha256.as_str()),
+        }
+    }
+
+    // Where files are served from, for the startup banner
+    pub fn describe(&self) -> String {
+        match &self.files {
+            Files::Directory { root, .. } => format!("directory {}", root.display()),
+            Files::Bundle { name, bundle } => format!(
+                "in-memory bundle {} ({} file(s), SHA-256 {})",
+                name,
+                bundle.files.len(),
+                bundle.sha256
+            ),
+        }
+    }
+
+    pub async fn respond(&self, method: &str, target: &str) -> Response {
+        if method != "GET" && method != "HEAD" {
+            return Response::text(405, "405 - Method Not Allowed");
+        }
+
+        let Some((relative, directory)) = request_path(target) else {
+            return Response::text(400, "400 - Bad Request");
+        };
+
+        let file = if directory {
+            relative.join("index.html")
+        } else {
+            relative.clone()
+        };
+        if let Some(response) = self.serve_file(&file).await {
+            return response;
+        }
+
+        if directory || relative.extension().is_none() {
+            if let Some(response) = self.serve_file(Path::new("index.html")).await {
+                return response;
+            }
+        }
+
+        Response::text(404, "404 - Not Found")
+    }
+
+    async fn serve_file(&self, relative: &Path) -> Option<Response> {
+        let key = manifest_key(relative);
+        let (root, manifest) = match &self.files {
+            Files::Directory { root, manifest } => (root, manifest),
+            Files::Bundle { bundle, .. } => {
+                return bundle.get(&key).map(|body| Response {
+                    status: 200,
+                    content_type: content_type(relative),
+                    body: body.to_vec(),
+                });
+            }
+        };
+
+        let expected = match manifest {
+            Some(manifest) => Some(manifest.expected_hash(&key)?),
+            None => None,
+        };
+
+        let path = root.join(relative);
+        if !path.is_file() {
+            return None;
+        }
+        let body = tokio::fs::read(&path).await.ok()?;
+        if expected.is_some_and(|expected| sha256_hex(&body) != expected) {
+            eprintln!(
+                "Refusing to serve {}: contents changed since the manifest was verified",
+                key
+            );
+            return Some(Response::text(500, "500 - Asset failed integrity check"));
+        }
+        Some(Response {
+            status: 200,
+            content_type: content_type(&path),
+            body,
+        })
+    }
+}
+
+// Maps a request target to a path relative to the site root, and whether it
+// names a directory. The query string is dropped and anything that could escape
+// the root is rejected.
+fn request_path(target: &str) -> Option<(PathBuf, bool)> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(&path[1..]).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    let directory = path.ends_with('/');
+    Some((relative, directory))
+}
+
+// The manifest key for a site-relative path: components joined with '/'
+fn manifest_key(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
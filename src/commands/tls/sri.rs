@@ -0,0 +1,405 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha384};
+
+use super::manifest::list_files;
+
+// An attribute inside a start tag; `start..end` is its byte range in the tag
+struct Attribute {
+    name: String,
+    value: Option<String>,
+    start: usize,
+    end: usize,
+}
+
+// Attributes of a start tag such as `<script src="a.js" defer>`
+fn parse_attributes(tag: &str) -> Vec<Attribute> {
+    let bytes = tag.as_bytes();
+    let mut attributes = Vec::new();
+    // Skip '<' and the tag name
+    let mut i = 1;
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+        i += 1;
+    }
+
+    loop {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] == b'>' {
+            break;
+        }
+
+        let start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+        {
+            i += 1;
+        }
+        let name = tag[start..i].to_ascii_lowercase();
+
+        let mut j = i;
+        while j < bytes.len() && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        let mut value = None;
+        if j < bytes.len() && bytes[j] == b'=' {
+            j += 1;
+            while j < bytes.len() && bytes[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            if j < bytes.len() && (bytes[j] == b'"' || bytes[j] == b'\'') {
+                let quote = bytes[j];
+                let value_start = j + 1;
+                j = value_start;
+                while j < bytes.len() && bytes[j] != quote {
+                    j += 1;
+                }
+                value = Some(tag[value_start..j.min(bytes.len())].to_string());
+                j = (j + 1).min(bytes.len());
+            } else {
+                let value_start = j;
+                while j < bytes.len() && !bytes[j].is_ascii_whitespace() && bytes[j] != b'>' {
+                    j += 1;
+                }
+                value = Some(tag[value_start..j].to_string());
+            }
+            i = j;
+        }
+
+        if name.is_empty() {
+            // Past the stray character, which need not be a single byte
+            i += tag[i..].chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+        attributes.push(Attribute {
+            name,
+            value,
+            start,
+            end: i,
+        });
+    }
+    attributes
+}
+
+// End of the tag starting at `start`, skipping '>' inside quoted values
+fn tag_end(html: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (offset, byte) in html.as_bytes()[start..].iter().enumerate() {
+        match (quote, byte) {
+            (Some(q), _) if *byte == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(*byte),
+            (None, b'>') => return Some(start + offset + 1),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+// The tag name at `start` if it is one of `names`, matched case-insensitively
+fn tag_name<'a>(html: &str, start: usize, names: &[&'a str]) -> Option<&'a str> {
+    let rest = &html.as_bytes()[start + 1..];
+    names.iter().copied().find(|name| {
+        rest.len() > name.len()
+            && rest[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+            && (rest[name.len()].is_ascii_whitespace() || matches!(rest[name.len()], b'>' | b'/'))
+    })
+}
+
+fn find_ignore_case(haystack: &str, needle: &str, from: usize) -> Option<usize> {
+    haystack.as_bytes()[from..]
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+        .map(|position| from + position)
+}
+
+// Resolves a script or stylesheet URL against the page to a file under `root`.
+// Remote, protocol-relative and data URLs give None.
+fn local_asset(root: &Path, page_dir: &Path, url: &str) -> Option<PathBuf> {
+    let url = url.trim();
+    if url.is_empty() || url.starts_with("//") || url.contains(':') {
+        return None;
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let (base, path) = match path.strip_prefix('/') {
+        Some(absolute) => (PathBuf::new(), absolute),
+        None => (page_dir.to_path_buf(), path),
+    };
+
+    let mut resolved = base;
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(root.join(resolved))
+}
+
+fn integrity(content: &[u8]) -> String {
+    format!(
+        "sha384-{}",
+        general_purpose::STANDARD.encode(Sha384::digest(content))
+    )
+}
+
+// Adds `integrity` and `crossorigin` to the tag, replacing any integrity value
+// already there
+fn rewrite_tag(tag: &str, attributes: &[Attribute], integrity: &str) -> String {
+    let mut rewritten = String::with_capacity(tag.len() + 80);
+    let mut copied = 0;
+    for attribute in attributes.iter().filter(|a| a.name == "integrity") {
+        rewritten.push_str(tag[copied..attribute.start].trim_end());
+        copied = attribute.end;
+    }
+    let rest = &tag[copied..];
+    let close = if rest.ends_with("/>") { "/>" } else { ">" };
+    rewritten.push_str(rest[..rest.len() - close.len()].trim_end());
+
+    rewritten.push_str(&format!(" integrity=\"{}\"", integrity));
+    if !attributes.iter().any(|a| a.name == "crossorigin") {
+        rewritten.push_str(" crossorigin=\"anonymous\"");
+    }
+    if close == "/>" {
+        rewritten.push(' ');
+    }
+    rewritten.push_str(close);
+    rewritten
+}
+
+// Rewrites one HTML page. Returns the new contents, or None if nothing changed.
+fn inject_page(root: &Path, page: &str, html: &str) -> Option<String> {
+    let page_dir = Path::new(page).parent().unwrap_or(Path::new(""));
+    let mut out = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut position = 0;
+
+    while let Some(offset) = html[position..].find('<') {
+        let start = position + offset;
+        if html[start..].starts_with("<!--") {
+            position = html[start..]
+                .find("-->")
+                .map_or(html.len(), |end| start + end + 3);
+            continue;
+        }
+        let Some(name) = tag_name(html, start, &["script", "link"]) else {
+            position = start + 1;
+            continue;
+        };
+        let Some(end) = tag_end(html, start) else {
+            break;
+        };
+        position = end;
+        let tag = &html[start..end];
+        let attributes = parse_attributes(tag);
+        let value = |wanted: &str| {
+            attributes
+                .iter()
+                .find(|a| a.name == wanted)
+                .and_then(|a| a.value.as_deref())
+        };
+
+        let url = if name == "script" {
+            // Inline script bodies may contain anything, including "<script"
+            position = find_ignore_case(html, "</script", end).unwrap_or(html.len());
+            value("src")
+        } else if value("rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|token| token.eq_ignore_ascii_case("stylesheet"))
+        }) {
+            value("href")
+        } else {
+            None
+        };
+        let Some(url) = url else {
+            continue;
+        };
+        let Some(asset) = local_asset(root, page_dir, url) else {
+            continue;
+        };
+        let content = match fs::read(&asset) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!(
+                    "Warning: {} references {} which can't be read: {}",
+                    page, url, e
+                );
+                continue;
+            }
+        };
+
+        let hash = integrity(&content);
+        if value("integrity") == Some(hash.as_str()) && value("crossorigin").is_some() {
+            continue;
+        }
+
+        out.push_str(&html[copied..start]);
+        out.push_str(&rewrite_tag(tag, &attributes, &hash));
+        copied = end;
+    }
+
+    out.push_str(&html[copied..]);
+    (out != html).then_some(out)
+}
+
+// Adds Subresource Integrity attributes to every local script and stylesheet
+// referenced from the HTML pages under `dir`, in place. Run before the asset
+// manifest or bundle is generated so they cover the rewritten pages. Returns
+// the pages that changed.
+pub fn inject_directory(dir: &Path) -> io::Result<Vec<String>> {
+    let mut changed = Vec::new();
+    for relative in list_files(dir)? {
+        let lower = relative.to_ascii_lowercase();
+        if !lower.ends_with(".html") && !lower.ends_with(".htm") {
+            continue;
+        }
+        let path = dir.join(&relative);
+        let Ok(html) = fs::read_to_string(&path) else {
+            eprintln!("Warning: skipping {}, not valid UTF-8", relative);
+            continue;
+        };
+        if let Some(rewritten) = inject_page(dir, &relative, &html) {
+            fs::write(&path, rewritten)?;
+            changed.push(relative);
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &[u8] = b"console.log('hi');\n";
+    const STYLE: &[u8] = b"body { color: red; }\n";
+
+    // A build directory with one script and one stylesheet, removed on drop
+    struct Site(PathBuf);
+
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn site(name: &str) -> Site {
+        let root = std::env::temp_dir().join(format!("defe-sri-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::write(root.join("app.js"), SCRIPT).unwrap();
+        fs::write(root.join("css/style.css"), STYLE).unwrap();
+        Site(root)
+    }
+
+    fn attributes(hash: &str) -> String {
+        format!("integrity=\"{}\" crossorigin=\"anonymous\"", hash)
+    }
+
+    #[test]
+    fn tolerates_quoted_angle_brackets() {
+        let site = site("quoted");
+        let root = &site.0;
+        let html = r#"<script data-note="a>b" src="app.js"></script>"#;
+        assert_eq!(
+            inject_page(root, "index.html", html).unwrap(),
+            format!(
+                r#"<script data-note="a>b" src="app.js" {}></script>"#,
+                attributes(&integrity(SCRIPT))
+            )
+        );
+    }
+
+    #[test]
+    fn skips_stray_characters() {
+        let attributes = parse_attributes(r#"<script ="x"é src=a.js>"#);
+        let names: Vec<&str> = attributes.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["src"]);
+        assert_eq!(attributes[0].value.as_deref(), Some("a.js"));
+        assert!(parse_attributes("<script =é=é>")
+            .iter()
+            .all(|a| a.name == "é"));
+    }
+
+    #[test]
+    fn replaces_a_stale_integrity() {
+        let site = site("stale");
+        let root = &site.0;
+        let html = r#"<script src="/app.js" integrity="sha384-stale" crossorigin="use-credentials"></script>"#;
+        let rewritten = inject_page(root, "docs/index.html", html).unwrap();
+        assert_eq!(
+            rewritten,
+            format!(
+                r#"<script src="/app.js" crossorigin="use-credentials" integrity="{}"></script>"#,
+                integrity(SCRIPT)
+            )
+        );
+        // Already current: left alone
+        assert!(inject_page(root, "docs/index.html", &rewritten).is_none());
+    }
+
+    #[test]
+    fn keeps_self_closing_links() {
+        let site = site("self-closing");
+        let root = &site.0;
+        let html = r#"<LINK rel="preload stylesheet" href="../css/style.css?v=2"/>"#;
+        assert_eq!(
+            inject_page(root, "pages/index.html", html).unwrap(),
+            format!(
+                r#"<LINK rel="preload stylesheet" href="../css/style.css?v=2" {} />"#,
+                attributes(&integrity(STYLE))
+            )
+        );
+    }
+
+    #[test]
+    fn skips_inline_script_bodies() {
+        let site = site("inline");
+        let root = &site.0;
+        let html = r#"<script>var tag = "<script src='app.js'>";</script><p>x</p>"#;
+        assert!(inject_page(root, "index.html", html).is_none());
+    }
+
+    #[test]
+    fn skips_comments() {
+        let site = site("comments");
+        let root = &site.0;
+        let html = r#"<!-- <script src="app.js"></script> --><link rel="icon" href="app.js">"#;
+        assert!(inject_page(root, "index.html", html).is_none());
+    }
+
+    #[test]
+    fn skips_remote_and_escaping_urls() {
+        let site = site("remote");
+        let root = &site.0;
+        let html = concat!(
+            r#"<script src="https://cdn.example.com/app.js"></script>"#,
+            r#"<script src="//cdn.example.com/app.js"></script>"#,
+            r#"<script src="../../etc/app.js"></script>"#,
+            r#"<script src="missing.js"></script>"#,
+        );
+        assert!(inject_page(root, "index.html", html).is_none());
+    }
+
+    #[test]
+    fn rewrites_pages_in_place() {
+        let site = site("directory");
+        let root = &site.0;
+        fs::write(root.join("index.html"), r#"<script src="app.js"></script>"#).unwrap();
+        assert_eq!(inject_directory(root).unwrap(), vec!["index.html"]);
+        assert!(fs::read_to_string(root.join("index.html"))
+            .unwrap()
+            .contains(&integrity(SCRIPT)));
+        assert!(inject_directory(root).unwrap().is_empty());
+    }
+}
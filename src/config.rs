@@ -0,0 +1,630 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Workspace configuration, read from `defe.json` in the current directory.
+// Every section is optional so an empty or missing file gives the defaults.
+pub const CONFIG_FILE: &str = "defe.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub tls: TlsSettings,
+    pub acme: AcmeSettings,
+    pub fetcher: FetcherSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TlsSettings {
+    // Socket addresses to accept connections on, e.g. "0.0.0.0:443" or
+    // "[::1]:8443". Ignored when the server is started by systemd socket
+    // activation.
+    pub listen: Vec<String>,
+    // Let IPv6 sockets accept IPv4 connections too. Turn off to list an IPv4
+    // and an IPv6 address on the same port.
+    pub dual_stack: bool,
+    // How long in-flight connections may keep running after a shutdown signal
+    pub shutdown_grace_secs: u64,
+    // Path prefixes forwarded to local upstream servers instead of the static site
+    pub routes: Vec<ProxyRoute>,
+    // When set, only files listed in the asset manifest are served
+    pub manifest: Option<ManifestSettings>,
+    // Add integrity attributes for local scripts and stylesheets to HTML pages
+    // when generating the asset manifest or bundle. Off unless asked for, as
+    // the pages are rewritten in place.
+    pub sri: bool,
+    // When set, the site is served from this archive in memory instead of a
+    // project directory
+    pub bundle: Option<BundleSettings>,
+    // When set, the server uses a locally generated development certificate
+    // instead of the certbot one
+    pub dev: Option<DevSettings>,
+    pub policy: TlsPolicy,
+    pub ocsp: OcspSettings,
+    // Where Prometheus metrics are served; unset disables the endpoint
+    pub metrics_path: Option<String>,
+    // Records the server's process ID while it runs, so `defe certs renew` can
+    // send it SIGHUP to reload the certificate
+    pub pid_file: Option<String>,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            listen: vec!["0.0.0.0:443".to_string()],
+            dual_stack: true,
+            shutdown_grace_secs: 30,
+            routes: Vec::new(),
+            manifest: None,
+            sri: false,
+            bundle: None,
+            dev: None,
+            policy: TlsPolicy::default(),
+            ocsp: OcspSettings::default(),
+            metrics_path: Some("/.well-known/defe-metrics".to_string()),
+            pid_file: Some("defe-tls.pid".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProxyRoute {
+    // e.g. "/api"; matched on whole path segments
+    pub prefix: String,
+    // e.g. "http://127.0.0.1:3000"; must be a loopback address
+    pub upstream: String,
+    // Remove `prefix` from the path before forwarding
+    #[serde(default)]
+    pub strip_prefix: bool,
+    // Time allowed for connecting and receiving the response headers
+    #[serde(default = "default_proxy_timeout_secs")]
+    pub timeout_secs: u64,
+    // Path requested to check the upstream; without it only the TCP connect is checked
+    #[serde(default)]
+    pub health_check_path: Option<String>,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub health_check_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestSettings {
+    // Relative to the workspace directory
+    #[serde(default = "default_manifest_path")]
+    pub path: String,
+    // Hex-encoded threshold_crypto public key; when set the manifest must carry
+    // a valid signature from it
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+impl Default for ManifestSettings {
+    fn default() -> Self {
+        Self {
+            path: default_manifest_path(),
+            public_key: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleSettings {
+    // Tar archive of the build output, relative to the workspace directory
+    #[serde(default = "default_bundle_path")]
+    pub path: String,
+    // Hex SHA-256 of the archive file; the server refuses to start on a mismatch
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl Default for BundleSettings {
+    fn default() -> Self {
+        Self {
+            path: default_bundle_path(),
+            sha256: String::new(),
+        }
+    }
+}
+
+// Protocol and cipher restrictions for the TLS server. Empty allowlists keep
+// the crypto provider's defaults; names are as printed by `defe tls policy`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TlsPolicy {
+    pub tls13_only: bool,
+    // e.g. "TLS13_AES_256_GCM_SHA384"
+    pub cipher_suites: Vec<String>,
+    // e.g. "X25519", "secp256r1"
+    pub kx_groups: Vec<String>,
+    pub session_tickets: bool,
+    // How often the ticket encryption key is replaced; tickets stay valid for
+    // twice this long
+    pub ticket_rotation_secs: u64,
+    // Stateful resumption cache entries; 0 disables the cache
+    pub session_cache_size: usize,
+    // Bytes of TLS 1.3 0-RTT data accepted; 0 disables early data, which is
+    // replayable
+    pub max_early_data: u32,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        Self {
+            tls13_only: false,
+            cipher_suites: Vec::new(),
+            kx_groups: Vec::new(),
+            session_tickets: true,
+            ticket_rotation_secs: 6 * 60 * 60,
+            session_cache_size: 256,
+            max_early_data: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OcspSettings {
+    pub enabled: bool,
+    // Used instead of the responder named in the certificate, e.g. a local
+    // responder for testing
+    pub responder: Option<String>,
+    pub timeout_secs: u64,
+    // Delay before retrying a failed fetch
+    pub retry_secs: u64,
+    // Refresh interval for responses without a nextUpdate
+    pub refresh_secs: u64,
+}
+
+impl Default for OcspSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            responder: None,
+            timeout_secs: 10,
+            retry_secs: 300,
+            refresh_secs: 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DevSettings {
+    // Where the development CA and certificate are kept, relative to the workspace
+    pub dir: String,
+    // Names covered in addition to localhost, 127.0.0.1 and ::1
+    pub sans: Vec<String>,
+    // Port 0 picks a free port, which is printed on startup
+    pub listen: String,
+}
+
+impl Default for DevSettings {
+    fn default() -> Self {
+        Self {
+            dir: "defe-dev-certs".to_string(),
+            sans: Vec::new(),
+            listen: "127.0.0.1:8443".to_string(),
+        }
+    }
+}
+
+// How certificates are obtained
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AcmeSettings {
+    pub client: AcmeClientKind,
+    // ACME v2 directory, e.g. "https://localhost:14000/dir" for a local Pebble,
+    // "https://acme.zerossl.com/v2/DV90" or a step-ca provisioner
+    pub directory_url: String,
+    // Use the CA's staging directory, whose certificates aren't trusted but
+    // whose rate limits are generous, to try out a setup
+    pub staging: bool,
+    // Staging directory for a CA other than Let's Encrypt
+    pub staging_directory_url: Option<String>,
+    // External Account Binding, for CAs that only serve accounts registered
+    // with them out of band, e.g. ZeroSSL
+    pub eab: Option<EabSettings>,
+    // PEM certificates trusted for the directory's TLS endpoint in addition
+    // to the system roots
+    pub root_bundle: Option<String>,
+    // Created on first use; relative to the workspace directory
+    pub account_key: String,
+    // Certbot's configuration directory, whose live/ lineages certificates
+    // certbot obtained are copied from
+    pub certbot_dir: String,
+    // How control of the domains is proved to the CA
+    pub challenge: ChallengeKind,
+    // Where HTTP-01 challenges are answered; the CA connects to port 80
+    pub http01_listen: String,
+    // Required for DNS-01 challenges
+    pub dns: Option<DnsSettings>,
+    // Time allowed for each validation and for issuance
+    pub timeout_secs: u64,
+    // Certificate key generated for each issuance
+    pub key_type: KeyType,
+    // How the certificate key is protected at rest
+    pub key_store: KeyStoreKind,
+    pub renewal: RenewalSettings,
+    pub preflight: PreflightSettings,
+    pub hooks: HookSettings,
+}
+
+impl Default for AcmeSettings {
+    fn default() -> Self {
+        Self {
+            client: AcmeClientKind::default(),
+            directory_url: LETS_ENCRYPT_DIRECTORY.to_string(),
+            staging: false,
+            staging_directory_url: None,
+            eab: None,
+            root_bundle: None,
+            account_key: "acme-account-key.pem".to_string(),
+            certbot_dir: "/etc/letsencrypt".to_string(),
+            challenge: ChallengeKind::default(),
+            http01_listen: "0.0.0.0:80".to_string(),
+            dns: None,
+            timeout_secs: 120,
+            key_type: KeyType::default(),
+            key_store: KeyStoreKind::default(),
+            renewal: RenewalSettings::default(),
+            preflight: PreflightSettings::default(),
+            hooks: HookSettings::default(),
+        }
+    }
+}
+
+// Checks run before a certificate is requested, so a misconfigured domain
+// fails with a diagnosis instead of a rejected validation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PreflightSettings {
+    // Resolvers asked for the domains' A, AAAA and CAA records
+    pub resolvers: Vec<String>,
+    // This host's public addresses. When empty they are detected by asking
+    // `ip_echo_urls`, which answer with the caller's address.
+    pub public_ips: Vec<String>,
+    pub ip_echo_urls: Vec<String>,
+    // Issuer domains the CA accepts in CAA records, e.g. "letsencrypt.org".
+    // When empty they are read from the ACME directory.
+    pub caa_identities: Vec<String>,
+    // Fetch a probe token through the domain from the HTTP-01 listener
+    pub check_port_80: bool,
+}
+
+impl Default for PreflightSettings {
+    fn default() -> Self {
+        Self {
+            resolvers: default_dns_resolvers(),
+            public_ips: Vec::new(),
+            ip_echo_urls: vec![
+                "https://api.ipify.org".to_string(),
+                "https://api6.ipify.org".to_string(),
+            ],
+            caa_identities: Vec::new(),
+            check_port_80: true,
+        }
+    }
+}
+
+// When `defe certs renew` replaces the certificate
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RenewalSettings {
+    // Renew once fewer than this many days remain. When unset, renew once
+    // `lifetime_fraction` of the certificate's lifetime has passed.
+    pub before_days: Option<u64>,
+    pub lifetime_fraction: f64,
+    // Longest the daemon sleeps between looking at the certificate
+    pub check_interval_secs: u64,
+    // Delay after the first failed attempt, doubled after each further
+    // failure up to `max_retry_secs`
+    pub retry_secs: u64,
+    pub max_retry_secs: u64,
+}
+
+impl Default for RenewalSettings {
+    fn default() -> Self {
+        Self {
+            before_days: None,
+            lifetime_fraction: 2.0 / 3.0,
+            check_interval_secs: 12 * 60 * 60,
+            retry_secs: 15 * 60,
+            max_retry_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl RenewalSettings {
+    // Settings that would make a certificate due again as soon as it is
+    // issued are rejected, as the daemon would then renew in a loop
+    pub fn validate(&self) -> io::Result<()> {
+        if self.before_days.is_none()
+            && !(self.lifetime_fraction > 0.0 && self.lifetime_fraction < 1.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "acme.renewal.lifetime_fraction must be between 0 and 1, not {}",
+                    self.lifetime_fraction
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Run around every issuance and renewal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HookSettings {
+    // Before the certificate is requested; a failure cancels the request
+    pub pre: Vec<Hook>,
+    // After the new certificate is installed; failures are reported but the
+    // certificate stays. Leaving out "reload-server" means a running server
+    // keeps the old certificate until restarted.
+    pub post: Vec<Hook>,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            pre: Vec::new(),
+            post: vec![Hook::ReloadServer],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Hook {
+    // Run with `sh -c` in the workspace directory, with DEFE_* environment
+    // variables describing the certificate
+    Command {
+        command: String,
+        #[serde(default = "default_hook_timeout_secs")]
+        timeout_secs: u64,
+    },
+    // Send the running TLS server SIGHUP so it loads the new certificate
+    ReloadServer,
+    // POST the domains and SPKI SHA-256 of the new certificate as JSON to
+    // `url`, e.g. a governance service that pins the fingerprint
+    PublishFingerprint {
+        url: String,
+    },
+}
+
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const LETS_ENCRYPT_STAGING_DIRECTORY: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+impl AcmeSettings {
+    // The directory to use, taking `staging` into account
+    pub fn directory(&self) -> io::Result<&str> {
+        if !self.staging {
+            return Ok(&self.directory_url);
+        }
+        match &self.staging_directory_url {
+            Some(url) => Ok(url),
+            None if self.directory_url == LETS_ENCRYPT_DIRECTORY => {
+                Ok(LETS_ENCRYPT_STAGING_DIRECTORY)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "acme.staging is set but {} has no known staging directory; set acme.staging_directory_url",
+                    self.directory_url
+                ),
+            )),
+        }
+    }
+}
+
+// The MAC key, base64url as the CA hands it out, comes from DEFE_EAB_HMAC_KEY
+// rather than this file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EabSettings {
+    pub key_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AcmeClientKind {
+    // The built-in ACME client
+    #[default]
+    Native,
+    // The certbot command-line tool
+    Certbot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChallengeKind {
+    // Answered on `http01_listen`; the domain must point at this server
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    // A TXT record published through the DNS provider; works for servers not
+    // reachable on port 80 and for wildcard names
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+
+impl ChallengeKind {
+    // The challenge type as named by ACME
+    pub fn name(self) -> &'static str {
+        match self {
+            ChallengeKind::Http01 => "http-01",
+            ChallengeKind::Dns01 => "dns-01",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DnsSettings {
+    pub provider: DnsProviderSettings,
+    // Resolvers polled until all of them return the challenge record before
+    // the CA is asked to validate, e.g. "1.1.1.1:53". Empty skips the check.
+    #[serde(default = "default_dns_resolvers")]
+    pub resolvers: Vec<String>,
+    #[serde(default = "default_propagation_timeout_secs")]
+    pub propagation_timeout_secs: u64,
+    #[serde(default = "default_propagation_interval_secs")]
+    pub propagation_interval_secs: u64,
+    // TTL of the challenge records
+    #[serde(default = "default_dns_ttl")]
+    pub ttl: u32,
+}
+
+// Where challenge records are published. Credentials come from the
+// environment rather than this file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DnsProviderSettings {
+    // The Cloudflare API, with a token from CLOUDFLARE_API_TOKEN that may
+    // edit DNS in the zone
+    Cloudflare {
+        // Looked up from the record name when unset
+        #[serde(default)]
+        zone_id: Option<String>,
+    },
+    // RFC 2136 dynamic updates signed with TSIG HMAC-SHA256, with the base64
+    // secret from DEFE_TSIG_SECRET
+    Rfc2136 {
+        // Primary server for the zone, e.g. "192.0.2.53:53"
+        server: String,
+        // e.g. "example.com"
+        zone: String,
+        key_name: String,
+    },
+    // Records are kept in a JSON file, for tests against a local CA whose
+    // DNS server is fed from it
+    File {
+        path: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    #[default]
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+    Rsa2048,
+    Rsa4096,
+}
+
+impl KeyType {
+    pub const ALL: [KeyType; 5] = [
+        KeyType::EcdsaP256,
+        KeyType::EcdsaP384,
+        KeyType::Ed25519,
+        KeyType::Rsa2048,
+        KeyType::Rsa4096,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            KeyType::EcdsaP256 => "ECDSA P-256",
+            KeyType::EcdsaP384 => "ECDSA P-384",
+            KeyType::Ed25519 => "Ed25519",
+            KeyType::Rsa2048 => "RSA 2048",
+            KeyType::Rsa4096 => "RSA 4096",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStoreKind {
+    // AES-256-GCM under a passphrase-derived key
+    #[default]
+    Encrypted,
+    // AES-256-GCM under the SGX seal key of this enclave
+    Sealed,
+}
+
+fn default_manifest_path() -> String {
+    "defe-manifest.json".to_string()
+}
+
+fn default_bundle_path() -> String {
+    "defe-bundle.tar".to_string()
+}
+
+fn default_dns_resolvers() -> Vec<String> {
+    vec!["1.1.1.1:53".to_string(), "8.8.8.8:53".to_string()]
+}
+
+fn default_propagation_timeout_secs() -> u64 {
+    300
+}
+
+fn default_propagation_interval_secs() -> u64 {
+    10
+}
+
+fn default_dns_ttl() -> u32 {
+    60
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    300
+}
+
+fn default_proxy_timeout_secs() -> u64 {
+    30
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+
+// Where `defe-fetcher` looks for the repository info
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FetcherSettings {
+    // IPFS HTTP gateways, raced concurrently; the healthiest start first
+    pub gateways: Vec<String>,
+    // Time each gateway has to deliver the verified content
+    pub timeout_secs: u64,
+    // Delay between starting one gateway and the next in health order, so a
+    // healthy gateway usually answers before the others are bothered
+    pub stagger_ms: u64,
+    // Latency and failure history per gateway; relative to the workspace
+    pub health_file: String,
+}
+
+impl Default for FetcherSettings {
+    fn default() -> Self {
+        Self {
+            gateways: vec![
+                "https://ipfs.io".to_string(),
+                "https://dweb.link".to_string(),
+                "https://cloudflare-ipfs.com".to_string(),
+            ],
+            timeout_secs: 30,
+            stagger_ms: 250,
+            health_file: "defe-gateway-health.json".to_string(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> io::Result<Self> {
+        Self::load_from(Path::new(CONFIG_FILE))
+    }
+
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid configuration in {}: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
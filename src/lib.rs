@@ -1,7 +1,9 @@
+#![cfg_attr(target_env = "sgx", feature(sgx_platform))]
 use colored::*;
-use dialoguer::Input;
 use dialoguer::{theme::ColorfulTheme, Select};
+pub mod cli;
 pub mod commands;
+pub mod config;
 
 pub const HELP_MESSAGE: &str = r#"
 DFE Certbot Helper
@@ -25,11 +27,12 @@ pub fn print_help_message_certbot() {
     println!("{}", HELP_MESSAGE.bright_yellow());
 }
 
-pub fn print_navigation_help_certbot(target_dir: &std::path::PathBuf) {
+pub fn print_navigation_help_certbot(target_dir: &std::path::Path) {
     println!("\n{}", "Next steps:".bright_blue());
-    println!("1. Ensure that the 'fullchain.pem' and 'privkey.pem' files are in your enclave's directory.");
+    println!("1. Ensure that 'fullchain.pem' and the encrypted key 'privkey.store.json' are in your enclave's directory.");
     println!("2. Configure your enclave to use the generated certificate and key files.");
     println!("3. Build and run your enclave.");
+    println!("4. Run 'defe certs renew --daemon' in that directory to renew the certificate before it expires.");
     println!("\nFor more information and detailed instructions, visit:");
     println!("{}", "https://example.com/enclave-setup".underline());
     println!(
@@ -42,16 +45,20 @@ pub fn print_navigation_help_certbot(target_dir: &std::path::PathBuf) {
     );
 }
 
-pub fn print_success_message_certbot(target_dir: &std::path::PathBuf) {
+pub fn print_success_message_certbot(target_dir: &std::path::Path, domains: &[String]) {
     println!(
         "{}",
         "SSL/TLS certificate and key files generated successfully!".bright_green()
     );
+    println!("The certificate covers:");
+    for domain in domains {
+        println!("{}", domain);
+    }
     print_navigation_help_certbot(target_dir);
 }
 
-pub fn confirm_domain_configuration_certbot() {
-    println!("Before proceeding, please ensure that you have completed the following steps:");
+pub fn print_domain_configuration_help_certbot() {
+    println!("To point your domain at this server:");
     println!(
         "1. Log in to your domain registrar's control panel (e.g., GoDaddy, Namecheap, etc.)."
     );
@@ -59,20 +66,7 @@ pub fn confirm_domain_configuration_certbot() {
     println!("3. Create an A record that points your domain name to your server's IP address.");
     println!("   - If you want to obtain a certificate for a subdomain (e.g., www.example.com), create an A record for the subdomain as well.");
     println!("4. Save the DNS changes and wait for the changes to propagate. This can take some time (usually a few minutes to a few hours).");
-    println!("5. Verify that your domain is properly pointing to your server's IP address by running the following command in your terminal:");
-    println!("   ping your_domain_name");
-
-    let _domain_config_confirm = Input::<String>::new()
-        .with_prompt("Please type 'I have' to confirm that you have completed the above steps")
-        .validate_with(|input: &String| -> Result<(), &str> {
-            if input.trim().to_lowercase() == "i have" {
-                Ok(())
-            } else {
-                Err("Please type 'I have' to confirm")
-            }
-        })
-        .interact_text()
-        .unwrap();
+    println!("5. Make sure port 80 (HTTP) is open and forwarded to this server.");
 }
 
 pub fn print_certbot_error_message(error_message: &str) {
@@ -129,6 +123,8 @@ pub fn run_defe_menu() {
             "Run defe-tls",
             "Run defe-rosario",
             "Run MPC operations",
+            "Generate asset manifest",
+            "Pack frontend bundle",
             "Create new project",
             "Exit",
         ];
@@ -158,8 +154,18 @@ pub fn run_defe_menu() {
                     eprintln!("MPC operations error: {:?}", e);
                 }
             }
-            5 => handle_new_defe_project(),
+            5 => {
+                if let Err(e) = commands::tls::generate_manifest() {
+                    eprintln!("Error generating asset manifest: {}", e);
+                }
+            }
             6 => {
+                if let Err(e) = commands::tls::pack_bundle() {
+                    eprintln!("Error packing frontend bundle: {}", e);
+                }
+            }
+            7 => handle_new_defe_project(),
+            8 => {
                 println!("Exiting...");
                 break;
             }
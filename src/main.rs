@@ -1,9 +1,21 @@
 // Import everything from the colored crate
+use clap::Parser;
+use dfe_lib::cli::Cli;
 use dotenv::dotenv;
 
 fn main() {
     dotenv().ok(); // Load .env file if it exists
 
-    dfe_lib::print_welcome_defe_message();
-    dfe_lib::run_defe_menu();
+    match Cli::parse().command {
+        Some(command) => {
+            if let Err(e) = dfe_lib::cli::run(command) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            dfe_lib::print_welcome_defe_message();
+            dfe_lib::run_defe_menu();
+        }
+    }
 }